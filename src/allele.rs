@@ -0,0 +1,191 @@
+use bam::pileup::PileupEntry;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+/// Symbols stored in `Frequency` for allele counts: A, C, G, T, deletion and insertion.
+/// They are lower-cased to be distinguished from the dominant allele of `--heterozygous-frequency`.
+pub const ALLELE_SYMBOLS: [char; 6] = ['a', 'c', 'g', 't', '-', '+'];
+
+/// Per-position counts of A/C/G/T/deletion/insertion on a pileup column.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AlleleCount {
+    pub counts: [u32; 6],
+}
+
+impl AlleleCount {
+    pub fn new() -> Self {
+        AlleleCount { counts: [0; 6] }
+    }
+
+    /// Counts alleles on the entries of a pileup column.
+    pub fn from_entries(entries: &[PileupEntry]) -> Self {
+        let mut count = AlleleCount::new();
+        for entry in entries.iter() {
+            match entry.sequence() {
+                Some(seq) => {
+                    let seq: Vec<u8> = seq.collect();
+                    if let Some(&nt) = seq.first() {
+                        count.add(nt as char);
+                    }
+                    if seq.len() > 1 {
+                        count.counts[5] += 1;
+                    }
+                }
+                None => count.counts[4] += 1,
+            }
+        }
+        count
+    }
+
+    pub fn add(&mut self, symbol: char) {
+        if let Some(index) = Self::symbol_index(symbol) {
+            self.counts[index] += 1;
+        }
+    }
+
+    fn symbol_index(symbol: char) -> Option<usize> {
+        let symbol = symbol.to_ascii_lowercase();
+        ALLELE_SYMBOLS.iter().position(|&t| t == symbol)
+    }
+
+    /// The number of reads covering the position (insertions are not counted twice).
+    pub fn depth(&self) -> u32 {
+        self.counts[..5].iter().sum()
+    }
+
+    /// The fraction of non-reference bases, deletions and insertions at the position.
+    pub fn non_reference_fraction(&self, reference: char) -> Option<f64> {
+        let depth = self.depth();
+        if depth == 0 {
+            return None;
+        }
+        let reference = Self::symbol_index(reference)
+            .filter(|&t| t < 4)
+            .map(|t| self.counts[t])
+            .unwrap_or(0);
+        Some((depth - reference + self.counts[5]) as f64 / depth as f64)
+    }
+
+    /// Returns true if the non-reference fraction exceeds the threshold.
+    pub fn is_flagged(&self, reference: Option<char>, threshold: f64) -> bool {
+        reference
+            .and_then(|t| self.non_reference_fraction(t))
+            .map_or(false, |t| t > threshold)
+    }
+
+    /// Converts counts into entries of `Frequency`.
+    pub fn to_frequency(&self, pos: u64) -> Vec<(u64, u32, char)> {
+        ALLELE_SYMBOLS
+            .iter()
+            .zip(self.counts.iter())
+            .filter(|(_, &count)| count > 0)
+            .map(|(&symbol, &count)| (pos, count, symbol))
+            .collect()
+    }
+
+    /// Collects allele counts from entries of `Frequency`.
+    pub fn from_frequency(values: &[(u64, u32, char)]) -> BTreeMap<u64, AlleleCount> {
+        let mut counts = BTreeMap::new();
+        for &(pos, count, symbol) in values.iter() {
+            if !ALLELE_SYMBOLS.contains(&symbol) {
+                continue;
+            }
+            let entry = counts.entry(pos).or_insert_with(AlleleCount::new);
+            if let Some(index) = Self::symbol_index(symbol) {
+                entry.counts[index] += count;
+            }
+        }
+        counts
+    }
+}
+
+pub fn write_allele_count_header<W: Write>(writer: &mut W) -> io::Result<()> {
+    writeln!(
+        writer,
+        "#sample\tchrom\tpos\tref\tA\tC\tG\tT\tdel\tins\tdepth\tflagged"
+    )
+}
+
+/// Writes allele counts as a tab-separated table. Positions are written 1-based.
+pub fn write_allele_counts<W: Write, F>(
+    writer: &mut W,
+    sample: &str,
+    chrom: &str,
+    counts: &BTreeMap<u64, AlleleCount>,
+    reference: F,
+    threshold: f64,
+) -> io::Result<()>
+where
+    F: Fn(u64) -> Option<char>,
+{
+    for (pos, count) in counts.iter() {
+        let ref_base = reference(*pos);
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            sample,
+            chrom,
+            pos + 1,
+            ref_base.unwrap_or('N'),
+            count.counts[0],
+            count.counts[1],
+            count.counts[2],
+            count.counts[3],
+            count.counts[4],
+            count.counts[5],
+            count.depth(),
+            count.is_flagged(ref_base, threshold) as u8
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AlleleCount;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn non_reference_fraction() {
+        let mut count = AlleleCount::new();
+        for symbol in "AAAAAAACG-".chars() {
+            count.add(symbol);
+        }
+        assert_eq!(count.depth(), 10);
+        assert_eq!(count.non_reference_fraction('A'), Some(0.3));
+        assert!(count.is_flagged(Some('a'), 0.2));
+        assert!(!count.is_flagged(Some('A'), 0.3));
+        assert!(!count.is_flagged(None, 0.0));
+    }
+
+    #[test]
+    fn frequency_round_trip() {
+        let mut count = AlleleCount::new();
+        for symbol in "TTG+".chars() {
+            count.add(symbol);
+        }
+        let mut values = count.to_frequency(42);
+        values.push((42, 10, '*'));
+        values.push((42, 2, 'T'));
+        let counts = AlleleCount::from_frequency(&values);
+        let mut expected = BTreeMap::new();
+        expected.insert(42, count);
+        assert_eq!(counts, expected);
+    }
+
+    #[test]
+    fn write_counts() {
+        let mut count = AlleleCount::new();
+        for symbol in "CCCT".chars() {
+            count.add(symbol);
+        }
+        let mut counts = BTreeMap::new();
+        counts.insert(9, count);
+        let mut out = vec![];
+        super::write_allele_counts(&mut out, "s", "chr1", &counts, |_| Some('C'), 0.2).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "s\tchr1\t10\tC\t0\t3\t0\t1\t0\t0\t4\t1\n"
+        );
+    }
+}
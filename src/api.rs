@@ -0,0 +1,196 @@
+use bam::record::tags::TagValue;
+use bam::Record;
+use genomic_range::StringRegion;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// A read returned by `/api/reads`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ApiRead {
+    pub sample_id: u64,
+    pub name: String,
+    pub chrom: String,
+    /// 0-based start and end (not included) on the reference.
+    pub start: i32,
+    pub end: i32,
+    pub reverse: bool,
+    pub flag: u16,
+    pub mapq: u8,
+    pub cigar: String,
+    pub sa: Option<String>,
+    /// Tags with character, integer, float and string values.
+    pub tags: BTreeMap<String, Value>,
+    /// The row on the packed view.
+    pub row: usize,
+}
+
+impl ApiRead {
+    pub fn new(sample_id: u64, record: &Record, chrom: &str, row: usize) -> Self {
+        let mut cigar = vec![];
+        let _ = record.cigar().write_readable(&mut cigar);
+        let mut tags = BTreeMap::new();
+        for (key, value) in record.tags().iter() {
+            let value = match value {
+                TagValue::Char(t) => Value::from((t as char).to_string()),
+                TagValue::Int(t, _) => Value::from(t),
+                TagValue::Float(t) => Value::from(t),
+                TagValue::String(t, _) => Value::from(String::from_utf8_lossy(t).to_string()),
+                _ => continue,
+            };
+            tags.insert(String::from_utf8_lossy(&key).to_string(), value);
+        }
+        let sa = match tags.get("SA") {
+            Some(Value::String(t)) => Some(t.clone()),
+            _ => None,
+        };
+        ApiRead {
+            sample_id,
+            name: String::from_utf8_lossy(record.name()).to_string(),
+            chrom: chrom.to_string(),
+            start: record.start(),
+            end: record.calculate_end(),
+            reverse: record.flag().is_reverse_strand(),
+            flag: record.flag().0,
+            mapq: record.mapq(),
+            cigar: String::from_utf8_lossy(&cigar).to_string(),
+            sa,
+            tags,
+            row,
+        }
+    }
+}
+
+/// Shown reads overlapping the range, where `list` and `index_list` are those of a `Vis`.
+pub fn reads(
+    list: &[(u64, Record)],
+    index_list: &[usize],
+    range: &StringRegion,
+    sample_id: Option<u64>,
+) -> Vec<ApiRead> {
+    list.iter()
+        .zip(index_list.iter())
+        .filter(|(t, row)| {
+            **row < std::u32::MAX as usize
+                && sample_id.map_or(true, |id| t.0 == id)
+                && (t.1.start() as u64) < range.end()
+                && (t.1.calculate_end() as u64) > range.start()
+        })
+        .map(|(t, row)| ApiRead::new(t.0, &t.1, &range.path, *row))
+        .collect()
+}
+
+/// Coverage of a sample returned by `/api/coverage`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ApiCoverage {
+    pub sample_id: u64,
+    pub chrom: String,
+    /// (0-based position, depth)
+    pub depth: Vec<(u64, u32)>,
+    /// (0-based position, count, base) of major alleles and allele counts.
+    pub alleles: Vec<(u64, u32, char)>,
+}
+
+/// Splits `Frequency` values on the range into depths and alleles.
+pub fn coverage(
+    freq: &BTreeMap<u64, Vec<(u64, u32, char)>>,
+    range: &StringRegion,
+    sample_id: Option<u64>,
+) -> Vec<ApiCoverage> {
+    freq.iter()
+        .filter(|(id, _)| sample_id.map_or(true, |t| **id == t))
+        .map(|(id, values)| {
+            let values = values
+                .iter()
+                .filter(|t| range.start() <= t.0 && t.0 <= range.end());
+            ApiCoverage {
+                sample_id: *id,
+                chrom: range.path.clone(),
+                depth: values
+                    .clone()
+                    .filter(|t| t.2 == '*')
+                    .map(|t| (t.0, t.1))
+                    .collect(),
+                alleles: values.filter(|t| t.2 != '*').copied().collect(),
+            }
+        })
+        .collect()
+}
+
+/// A reference sequence returned by `/api/header`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ApiReference {
+    pub name: String,
+    pub length: u64,
+}
+
+/// An input returned by `/api/samples`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ApiSample {
+    pub sample_id: u64,
+    pub name: String,
+    /// The type of the local header ("bam" or "none").
+    pub header_type: String,
+}
+
+/// A dataset of the catalog returned by `/datasets`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ApiDataset {
+    pub id: String,
+    /// "bam" or "ghb".
+    pub format: String,
+    /// Whether the dataset is open (loaded on the server).
+    pub open: bool,
+}
+
+/// Capabilities of the server returned by `/api/info`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ApiInfo {
+    pub version: String,
+    pub formats: Vec<String>,
+    pub presets: Vec<String>,
+    pub color_presets: Vec<String>,
+    pub display_modes: Vec<String>,
+}
+
+impl ApiInfo {
+    pub fn new() -> Self {
+        let strings = |t: &[&str]| t.iter().map(|t| t.to_string()).collect();
+        ApiInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            formats: strings(&["png", "bmp", "json"]),
+            presets: strings(&["auto", "base", "gene", "chrom", "sv", "qual"]),
+            color_presets: strings(&["hgb", "igv", "jbrowse"]),
+            display_modes: strings(&["expanded", "squished", "collapsed"]),
+        }
+    }
+}
+
+impl Default for ApiInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::coverage;
+    use genomic_range::StringRegion;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn coverage_of_samples() {
+        let mut freq = BTreeMap::new();
+        freq.insert(
+            0,
+            vec![(99, 1, '*'), (100, 2, '*'), (100, 2, 'A'), (201, 1, '*')],
+        );
+        freq.insert(1, vec![(150, 3, '*')]);
+        let range = StringRegion::new("chr1:100-200").unwrap();
+        let coverages = coverage(&freq, &range, Some(0));
+        assert_eq!(coverages.len(), 1);
+        assert_eq!(coverages[0].depth, vec![(100, 2)]);
+        assert_eq!(coverages[0].alleles, vec![(100, 2, 'A')]);
+        assert_eq!(coverage(&freq, &range, None).len(), 2);
+    }
+}
@@ -0,0 +1,255 @@
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2};
+use clap::ArgMatches;
+use rand::rngs::OsRng;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
+
+/// Users and API tokens allowed on the web servers, given by `--auth-file`.
+///
+/// Passwords are stored as salted Argon2 hashes in the PHC string format, e.g. by
+/// `printf %s <password> | argon2 <salt> -id -e`, and tokens, which are random and long
+/// enough not to need a slow hash, as SHA-256 digests in hex, e.g. by
+/// `printf %s <token> | sha256sum`:
+///
+/// ```yaml
+/// users:
+///   - name: alice
+///     password: '$argon2id$v=19$m=4096,t=3,p=1$c2FsdHNhbHRzYWx0$tQcKGfqT6pMUHe45CkrYPoqdke/6SToWA6omscG8TDM'
+///   - name: bob
+///     password: '$argon2id$v=19$m=4096,t=3,p=1$cGVwcGVycGVwcGVy$ONN+s9/bxtxJlW+wwYrLs0FPgt1wfQBmdqvWo5jniG4'
+///     datasets: [tumor]
+/// tokens:
+///   - name: pipeline
+///     token: 2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b
+/// ```
+#[derive(Debug, Default, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Credentials {
+    /// Sent by basic authentication.
+    pub users: Vec<Account>,
+    /// Sent as bearer tokens, e.g. `Authorization: Bearer <token>`.
+    pub tokens: Vec<Account>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Account {
+    pub name: String,
+    /// Argon2 hash of the password in the PHC string format, or SHA-256 digest of the token
+    /// in hex.
+    #[serde(alias = "token")]
+    pub password: String,
+    /// Ids of datasets allowed on `--catalog`, or all datasets if not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub datasets: Option<Vec<String>>,
+}
+
+/// Datasets allowed to an authenticated user or token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Access {
+    pub name: String,
+    pub datasets: Option<Vec<String>>,
+}
+
+impl Credentials {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let credentials: Self = serde_yaml::from_reader(File::open(path)?)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+        credentials.verify()?;
+        Ok(credentials)
+    }
+
+    /// A single user given as `username:password`.
+    pub fn from_basic(basic_auth: &str) -> io::Result<Self> {
+        let mut fields = basic_auth.splitn(2, ':');
+        match (fields.next(), fields.next()) {
+            (Some(name), Some(password)) if !name.is_empty() => Ok(Credentials {
+                users: vec![Account {
+                    name: name.to_string(),
+                    password: hash_password(password)?,
+                    datasets: None,
+                }],
+                tokens: vec![],
+            }),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Basic authentication should be username:password",
+            )),
+        }
+    }
+
+    /// Credentials given by `auth-file`, or `basic-auth` if not given.
+    pub fn from_matches(matches: &ArgMatches) -> io::Result<Option<Self>> {
+        if let Some(path) = matches.value_of("auth-file") {
+            return Self::from_path(path).map(Some);
+        }
+        matches
+            .value_of("basic-auth")
+            .map(Self::from_basic)
+            .transpose()
+    }
+
+    /// Rejects duplicated user names, passwords which are not Argon2 hashes and tokens which
+    /// are not SHA-256 digests in hex, e.g. plain passwords.
+    pub fn verify(&self) -> io::Result<()> {
+        let mut names = HashSet::new();
+        for user in self.users.iter() {
+            let is_hash = match PasswordHash::new(&user.password) {
+                Ok(hash) => Algorithm::try_from(hash.algorithm).is_ok(),
+                Err(_) => false,
+            };
+            if !is_hash {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "Not an Argon2 hash in the PHC string format for {}",
+                        user.name
+                    ),
+                ));
+            }
+        }
+        for token in self.tokens.iter() {
+            let is_digest =
+                token.password.len() == 64 && token.password.chars().all(|t| t.is_ascii_hexdigit());
+            if !is_digest {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Not a SHA-256 digest in hex for {}", token.name),
+                ));
+            }
+        }
+        for user in self.users.iter() {
+            if !names.insert(&user.name) {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Duplicated user: {}", user.name),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the password by the slow hash, which should not run on async workers.
+    pub fn basic(&self, name: &str, password: &str) -> Option<Access> {
+        self.users
+            .iter()
+            .find(|t| t.name == name)
+            .filter(|t| password_matches(&t.password, password))
+            .map(Account::access)
+    }
+
+    pub fn bearer(&self, token: &str) -> Option<Access> {
+        let digest = sha256_hex(token);
+        self.tokens
+            .iter()
+            .find(|t| digest_eq(&t.password, &digest))
+            .map(Account::access)
+    }
+}
+
+impl Account {
+    fn access(&self) -> Access {
+        Access {
+            name: self.name.clone(),
+            datasets: self.datasets.clone(),
+        }
+    }
+}
+
+impl Access {
+    pub fn allows(&self, dataset: &str) -> bool {
+        match &self.datasets {
+            Some(datasets) => datasets.iter().any(|t| t == dataset),
+            None => true,
+        }
+    }
+
+    /// Whether all datasets are allowed, e.g. to purge the cache shared by them.
+    pub fn is_unrestricted(&self) -> bool {
+        self.datasets.is_none()
+    }
+}
+
+/// Argon2 hash of the password with a random salt in the PHC string format.
+pub fn hash_password(password: &str) -> io::Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|t| t.to_string())
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e.to_string()))
+}
+
+/// Whether the password matches the Argon2 hash in the PHC string format.
+fn password_matches(hash: &str, password: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+pub fn sha256_hex(text: &str) -> String {
+    format!("{:x}", Sha256::digest(text.as_bytes()))
+}
+
+/// Compares digests in hex in constant time, ignoring cases.
+fn digest_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes().zip(b.bytes()).fold(0, |acc, (x, y)| {
+            acc | (x.to_ascii_lowercase() ^ y.to_ascii_lowercase())
+        }) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hash_password, sha256_hex, Credentials};
+
+    #[test]
+    fn authentication() {
+        let yaml = format!(
+            "users:\n  - {{name: alice, password: '{}'}}\n  - {{name: bob, password: '{}', datasets: [tumor]}}\ntokens:\n  - {{name: pipeline, token: {}}}\n",
+            hash_password("secret").unwrap(),
+            hash_password("hunter2").unwrap(),
+            sha256_hex("api-key").to_uppercase(),
+        );
+        let credentials: Credentials = serde_yaml::from_str(&yaml).unwrap();
+        assert!(credentials.verify().is_ok());
+        assert!(credentials
+            .basic("alice", "secret")
+            .unwrap()
+            .is_unrestricted());
+        assert_eq!(credentials.basic("alice", "hunter2"), None);
+        let bob = credentials.basic("bob", "hunter2").unwrap();
+        assert!(bob.allows("tumor"));
+        assert!(!bob.allows("normal"));
+        assert_eq!(credentials.bearer("api-key").unwrap().name, "pipeline");
+        assert_eq!(credentials.bearer("secret"), None);
+
+        // Salted, so that the same passwords have different hashes.
+        assert_ne!(
+            credentials.users[0].password,
+            hash_password("secret").unwrap()
+        );
+
+        let plain: Credentials =
+            serde_yaml::from_str("users:\n  - {name: alice, password: secret}\n").unwrap();
+        assert!(plain.verify().is_err());
+        // A fast hash is not accepted for passwords.
+        let digest: Credentials = serde_yaml::from_str(&format!(
+            "users:\n  - {{name: alice, password: {}}}\n",
+            sha256_hex("secret")
+        ))
+        .unwrap();
+        assert!(digest.verify().is_err());
+        let basic = Credentials::from_basic("carol:a:b").unwrap();
+        assert!(basic.basic("carol", "a:b").is_some());
+        assert!(Credentials::from_basic("carol").is_err());
+    }
+}
@@ -0,0 +1,541 @@
+use bam::record::tags::TagValue;
+use bam::Record;
+use std::collections::BTreeSet;
+
+/// An alignment of a part of a read, from the record itself or from its SA tag.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub chrom: String,
+    /// Start position on the reference (0-based).
+    pub start: u64,
+    /// End position on the reference (0-based, not included).
+    pub end: u64,
+    pub reverse: bool,
+    /// Aligned interval on the read in its original orientation.
+    pub query_start: u32,
+    pub query_end: u32,
+    /// The length of the read including clipped bases.
+    pub query_len: u32,
+    pub mapq: u8,
+    pub cigar: Vec<(u32, char)>,
+}
+
+impl Segment {
+    /// Creates a segment from a CIGAR string; returns None if it is malformed.
+    pub fn from_cigar(
+        chrom: &str,
+        start: u64,
+        reverse: bool,
+        cigar: &str,
+        mapq: u8,
+    ) -> Option<Self> {
+        let mut ops = vec![];
+        let mut len = 0u32;
+        for c in cigar.chars() {
+            if let Some(d) = c.to_digit(10) {
+                len = len.checked_mul(10)?.checked_add(d)?;
+            } else {
+                ops.push((len, c));
+                len = 0;
+            }
+        }
+        if len != 0 {
+            return None;
+        }
+        Segment::from_ops(chrom, start, reverse, ops, mapq)
+    }
+
+    /// Creates a segment from CIGAR operations as (length, operation); returns None if they
+    /// are empty or unknown.
+    pub fn from_ops(
+        chrom: &str,
+        start: u64,
+        reverse: bool,
+        ops: Vec<(u32, char)>,
+        mapq: u8,
+    ) -> Option<Self> {
+        if ops.is_empty() {
+            return None;
+        }
+        let is_clip = |op: &(u32, char)| op.1 == 'S' || op.1 == 'H';
+        let left_clip: u32 = ops.iter().take_while(|t| is_clip(t)).map(|t| t.0).sum();
+        let right_clip: u32 = ops
+            .iter()
+            .rev()
+            .take_while(|t| is_clip(t))
+            .map(|t| t.0)
+            .sum();
+        let (_, query_len, ref_len) = walk(&ops)?;
+        let (query_start, query_end) = if reverse {
+            (right_clip, query_len - left_clip)
+        } else {
+            (left_clip, query_len - right_clip)
+        };
+        Some(Segment {
+            chrom: chrom.to_string(),
+            start,
+            end: start + ref_len,
+            reverse,
+            query_start,
+            query_end,
+            query_len,
+            mapq,
+            cigar: ops,
+        })
+    }
+
+    /// Parses an SA tag (`rname,pos,strand,CIGAR,mapQ,NM;`).
+    pub fn from_sa(sa: &str) -> Vec<Self> {
+        sa.split(';')
+            .filter_map(|t| {
+                let fields: Vec<&str> = t.split(',').collect();
+                if fields.len() < 5 {
+                    return None;
+                }
+                let pos = fields[1].parse::<u64>().ok()?;
+                Segment::from_cigar(
+                    fields[0],
+                    pos.saturating_sub(1),
+                    fields[2] == "-",
+                    fields[3],
+                    fields[4].parse::<u8>().unwrap_or(0),
+                )
+            })
+            .collect()
+    }
+
+    pub fn from_record(record: &Record, chrom: &str) -> Option<Self> {
+        Segment::from_ops(
+            chrom,
+            record.start().max(0) as u64,
+            record.flag().is_reverse_strand(),
+            record
+                .cigar()
+                .iter()
+                .map(|(len, op)| (len, op.to_byte() as char))
+                .collect(),
+            record.mapq(),
+        )
+    }
+
+    /// Aligned blocks as lines from (query, reference) to (query, reference), where query
+    /// positions are on the read in its original orientation.
+    pub fn blocks(&self) -> Vec<((u32, u64), (u32, u64))> {
+        let query = |t: u32| if self.reverse { self.query_len - t } else { t };
+        walk(&self.cigar)
+            .map(|t| t.0)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(query_pos, ref_offset, len)| {
+                let ref_pos = self.start + ref_offset;
+                (
+                    (query(query_pos), ref_pos),
+                    (query(query_pos + len), ref_pos + len as u64),
+                )
+            })
+            .collect()
+    }
+
+    /// The position on the reference where the read leaves this segment.
+    pub fn exit(&self) -> Breakpoint {
+        if self.reverse {
+            Breakpoint::new(&self.chrom, self.start + 1, false)
+        } else {
+            Breakpoint::new(&self.chrom, self.end, true)
+        }
+    }
+
+    /// The position on the reference where the read enters this segment.
+    pub fn entry(&self) -> Breakpoint {
+        if self.reverse {
+            Breakpoint::new(&self.chrom, self.end, true)
+        } else {
+            Breakpoint::new(&self.chrom, self.start + 1, false)
+        }
+    }
+}
+
+/// An aligned block as (query position along the reference strand, offset on the reference,
+/// length).
+type Block = (u32, u64, u32);
+
+/// Walks CIGAR operations, and returns aligned blocks with the lengths of the query including
+/// clipped bases and the reference; returns None if an operation is unknown.
+fn walk(ops: &[(u32, char)]) -> Option<(Vec<Block>, u32, u64)> {
+    let mut blocks = vec![];
+    let mut query_pos = 0u32;
+    let mut ref_offset = 0u64;
+    for &(len, op) in ops.iter() {
+        match op {
+            'M' | '=' | 'X' => {
+                blocks.push((query_pos, ref_offset, len));
+                query_pos += len;
+                ref_offset += len as u64;
+            }
+            'D' | 'N' => ref_offset += len as u64,
+            'I' | 'S' | 'H' => query_pos += len,
+            'P' => {}
+            _ => return None,
+        }
+    }
+    Some((blocks, query_pos, ref_offset))
+}
+
+/// All alignments of the read of a record (the record and its SA tag) ordered along the read.
+pub fn segments(record: &Record, chrom: &str) -> Vec<Segment> {
+    let mut segments: Vec<Segment> = Segment::from_record(record, chrom).into_iter().collect();
+    if let Some(TagValue::String(sa, _)) = record.tags().get(b"SA") {
+        for segment in Segment::from_sa(&String::from_utf8_lossy(sa)) {
+            if !segments.contains(&segment) {
+                segments.push(segment);
+            }
+        }
+    }
+    segments.sort_by_key(|t| (t.query_start, t.query_end));
+    segments
+}
+
+/// One side of a junction.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Breakpoint {
+    pub chrom: String,
+    /// The outermost aligned base next to the junction (1-based).
+    pub pos: u64,
+    /// True if the aligned sequence lies on the left of `pos`.
+    pub retains_left: bool,
+}
+
+impl Breakpoint {
+    pub fn new(chrom: &str, pos: u64, retains_left: bool) -> Self {
+        Breakpoint {
+            chrom: chrom.to_string(),
+            pos,
+            retains_left,
+        }
+    }
+}
+
+/// A pair of reference positions joined on a read, ordered by position.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Junction {
+    pub first: Breakpoint,
+    pub second: Breakpoint,
+}
+
+impl Junction {
+    pub fn new(a: Breakpoint, b: Breakpoint) -> Self {
+        if (&a.chrom, a.pos) <= (&b.chrom, b.pos) {
+            Junction {
+                first: a,
+                second: b,
+            }
+        } else {
+            Junction {
+                first: b,
+                second: a,
+            }
+        }
+    }
+
+    pub fn is_translocation(&self) -> bool {
+        self.first.chrom != self.second.chrom
+    }
+
+    /// Junctions between consecutive segments along a read.
+    pub fn from_segments(segments: &[Segment]) -> Vec<Self> {
+        Junction::from_segments_min_mapq(segments, 0)
+    }
+
+    /// Junctions between consecutive segments along a read, both with a MAPQ of at least
+    /// `min_mapq`. A segment below it does not join its neighbours into a false junction.
+    pub fn from_segments_min_mapq(segments: &[Segment], min_mapq: u8) -> Vec<Self> {
+        segments
+            .windows(2)
+            .filter(|t| t[0].mapq >= min_mapq && t[1].mapq >= min_mapq)
+            .map(|t| Junction::new(t[0].exit(), t[1].entry()))
+            .collect()
+    }
+}
+
+/// Junctions supported by reads within a tolerance.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cluster {
+    /// The junction at the median positions of supporting reads.
+    pub junction: Junction,
+    /// The minimal and maximal positions of both sides.
+    pub first_span: (u64, u64),
+    pub second_span: (u64, u64),
+    pub reads: BTreeSet<String>,
+}
+
+impl Cluster {
+    pub fn support(&self) -> usize {
+        self.reads.len()
+    }
+
+    fn read_names(&self) -> String {
+        self.reads.iter().cloned().collect::<Vec<_>>().join(",")
+    }
+
+    /// A BEDPE line, with strands of "+" if the aligned sequence lies on the left.
+    pub fn to_bedpe(&self, id: usize) -> String {
+        let strand = |t: &Breakpoint| if t.retains_left { "+" } else { "-" };
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\tbnd_{}\t{}\t{}\t{}\t{}\t{}",
+            self.junction.first.chrom,
+            self.first_span.0.saturating_sub(1),
+            self.first_span.1,
+            self.junction.second.chrom,
+            self.second_span.0.saturating_sub(1),
+            self.second_span.1,
+            id,
+            self.support(),
+            strand(&self.junction.first),
+            strand(&self.junction.second),
+            self.support(),
+            self.read_names()
+        )
+    }
+
+    /// A pair of VCF BND records; REF is N as the reference is not read.
+    pub fn to_vcf(&self, id: usize) -> [String; 2] {
+        let record = |this: &Breakpoint, span: (u64, u64), mate: &Breakpoint, side: usize| {
+            let p = format!("{}:{}", mate.chrom, mate.pos);
+            let alt = match (this.retains_left, mate.retains_left) {
+                (true, false) => format!("N[{}[", p),
+                (true, true) => format!("N]{}]", p),
+                (false, true) => format!("]{}]N", p),
+                (false, false) => format!("[{}[N", p),
+            };
+            format!(
+                "{}\t{}\tbnd_{}_{}\tN\t{}\t.\tPASS\tSVTYPE=BND;MATEID=bnd_{}_{};CIPOS={},{};SUPPORT={};READS={}",
+                this.chrom,
+                this.pos,
+                id,
+                side,
+                alt,
+                id,
+                3 - side,
+                span.0 as i64 - this.pos as i64,
+                span.1 as i64 - this.pos as i64,
+                self.support(),
+                self.read_names()
+            )
+        };
+        [
+            record(
+                &self.junction.first,
+                self.first_span,
+                &self.junction.second,
+                1,
+            ),
+            record(
+                &self.junction.second,
+                self.second_span,
+                &self.junction.first,
+                2,
+            ),
+        ]
+    }
+}
+
+/// The header of VCF written by `breakpoints`.
+pub fn vcf_header(chromosomes: &[(String, u64)]) -> String {
+    let mut header = vec![
+        "##fileformat=VCFv4.2".to_string(),
+        format!("##source=hgb {}", env!("CARGO_PKG_VERSION")),
+    ];
+    for (name, len) in chromosomes.iter() {
+        header.push(format!("##contig=<ID={},length={}>", name, len));
+    }
+    header.extend(
+        [
+            "##INFO=<ID=SVTYPE,Number=1,Type=String,Description=\"Type of structural variant\">",
+            "##INFO=<ID=MATEID,Number=.,Type=String,Description=\"ID of mate breakends\">",
+            "##INFO=<ID=CIPOS,Number=2,Type=Integer,Description=\"Positions of supporting reads relative to POS\">",
+            "##INFO=<ID=SUPPORT,Number=1,Type=Integer,Description=\"Number of supporting reads\">",
+            "##INFO=<ID=READS,Number=.,Type=String,Description=\"Names of supporting reads\">",
+            "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO",
+        ]
+        .iter()
+        .map(|t| t.to_string()),
+    );
+    header.join("\n")
+}
+
+/// Clusters junctions whose sides are on the same chromosomes with the same orientations,
+/// and whose positions are within the tolerance from the first junction of the cluster.
+pub fn cluster(mut junctions: Vec<(Junction, String)>, tolerance: u64) -> Vec<Cluster> {
+    let key = |t: &Junction| {
+        (
+            t.first.chrom.clone(),
+            t.second.chrom.clone(),
+            t.first.retains_left,
+            t.second.retains_left,
+        )
+    };
+    junctions.sort_by(|a, b| {
+        key(&a.0)
+            .cmp(&key(&b.0))
+            .then(a.0.first.pos.cmp(&b.0.first.pos))
+            .then(a.0.second.pos.cmp(&b.0.second.pos))
+    });
+    // (the first junction, members) of each cluster
+    let mut groups: Vec<(Junction, Vec<(Junction, String)>)> = vec![];
+    for (junction, name) in junctions {
+        let found = groups
+            .iter_mut()
+            .rev()
+            .take_while(|t| {
+                key(&t.0) == key(&junction) && t.0.first.pos + tolerance >= junction.first.pos
+            })
+            .find(|t| {
+                t.0.second.pos.max(junction.second.pos) - t.0.second.pos.min(junction.second.pos)
+                    <= tolerance
+            });
+        match found {
+            Some(group) => group.1.push((junction, name)),
+            None => groups.push((junction.clone(), vec![(junction, name)])),
+        }
+    }
+    groups
+        .into_iter()
+        .map(|(first, members)| {
+            let median = |f: &dyn Fn(&Junction) -> u64| {
+                let mut values: Vec<u64> = members.iter().map(|t| f(&t.0)).collect();
+                values.sort_unstable();
+                (
+                    values[values.len() / 2],
+                    values[0],
+                    values[values.len() - 1],
+                )
+            };
+            let (first_pos, first_min, first_max) = median(&|t| t.first.pos);
+            let (second_pos, second_min, second_max) = median(&|t| t.second.pos);
+            let mut junction = first;
+            junction.first.pos = first_pos;
+            junction.second.pos = second_pos;
+            Cluster {
+                junction,
+                first_span: (first_min, first_max),
+                second_span: (second_min, second_max),
+                reads: members.into_iter().map(|t| t.1).collect(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cluster, Breakpoint, Junction, Segment};
+
+    #[test]
+    fn segment_from_cigar() {
+        let segment = Segment::from_cigar("chr1", 100, false, "10S20M5I10M2D5H", 60).unwrap();
+        assert_eq!((segment.start, segment.end), (100, 132));
+        assert_eq!(
+            (segment.query_start, segment.query_end, segment.query_len),
+            (10, 45, 50)
+        );
+        let segment = Segment::from_cigar("chr1", 100, true, "10S20M5I10M2D5H", 60).unwrap();
+        assert_eq!((segment.query_start, segment.query_end), (5, 40));
+        assert_eq!(
+            segment.blocks(),
+            vec![((40, 100), (20, 120)), ((15, 120), (5, 130))]
+        );
+        assert!(Segment::from_cigar("chr1", 0, false, "10M5", 0).is_none());
+        assert!(Segment::from_cigar("chr1", 0, false, "10Q", 0).is_none());
+    }
+
+    #[test]
+    fn junctions_from_sa() {
+        // The first half of the read is on chr1 (forward), the rest is on chr2 (reverse).
+        let mut segments =
+            Segment::from_sa("chr2,1001,-,50M50S,60,0;chr1,101,+,50M50S,60,0;invalid;");
+        assert_eq!(segments.len(), 2);
+        segments.sort_by_key(|t| t.query_start);
+        let junctions = Junction::from_segments(&segments);
+        assert_eq!(
+            junctions,
+            vec![Junction {
+                first: Breakpoint::new("chr1", 150, true),
+                second: Breakpoint::new("chr2", 1050, true),
+            }]
+        );
+        assert!(junctions[0].is_translocation());
+    }
+
+    #[test]
+    fn junctions_min_mapq() {
+        // The middle of the read is aligned to chr2 with a low MAPQ.
+        let mut segments = Segment::from_sa(
+            "chr1,101,+,30M70S,60,0;chr2,1001,+,30S30M40S,5,0;chr3,2001,+,60S40M,60,0;",
+        );
+        segments.sort_by_key(|t| t.query_start);
+        assert_eq!(Junction::from_segments(&segments).len(), 2);
+        assert_eq!(
+            Junction::from_segments_min_mapq(&segments, 5),
+            Junction::from_segments(&segments)
+        );
+        // chr1 and chr3 are not adjacent on the read.
+        assert!(Junction::from_segments_min_mapq(&segments, 20).is_empty());
+    }
+
+    #[test]
+    fn cluster_junctions() {
+        let junction = |a: u64, b: u64| {
+            Junction::new(
+                Breakpoint::new("chr1", a, true),
+                Breakpoint::new("chr2", b, false),
+            )
+        };
+        let clusters = cluster(
+            vec![
+                (junction(100, 1000), "a".to_string()),
+                (junction(1000, 1000), "b".to_string()),
+                (junction(110, 1010), "c".to_string()),
+                (junction(105, 1500), "d".to_string()),
+                (junction(102, 1002), "e".to_string()),
+            ],
+            20,
+        );
+        assert_eq!(clusters.len(), 3);
+        assert_eq!(clusters[0].support(), 3);
+        assert_eq!(clusters[0].junction, junction(102, 1002));
+        assert_eq!(clusters[0].first_span, (100, 110));
+        assert_eq!(clusters[1].support(), 1);
+    }
+
+    #[test]
+    fn export_cluster() {
+        let clusters = cluster(
+            vec![
+                (
+                    Junction::new(
+                        Breakpoint::new("chr1", 150, true),
+                        Breakpoint::new("chr2", 1050, true),
+                    ),
+                    "a".to_string(),
+                ),
+                (
+                    Junction::new(
+                        Breakpoint::new("chr1", 152, true),
+                        Breakpoint::new("chr2", 1049, true),
+                    ),
+                    "b".to_string(),
+                ),
+            ],
+            10,
+        );
+        assert_eq!(
+            clusters[0].to_bedpe(0),
+            "chr1\t149\t152\tchr2\t1048\t1050\tbnd_0\t2\t+\t+\t2\ta,b"
+        );
+        let [first, second] = clusters[0].to_vcf(0);
+        assert_eq!(
+            first,
+            "chr1\t152\tbnd_0_1\tN\tN]chr2:1050]\t.\tPASS\tSVTYPE=BND;MATEID=bnd_0_2;CIPOS=-2,0;SUPPORT=2;READS=a,b"
+        );
+        assert!(second.starts_with("chr2\t1050\tbnd_0_2\tN\tN]chr1:152]\t"));
+    }
+}
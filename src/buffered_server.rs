@@ -1,17 +1,32 @@
+use crate::rest_server::{
+    auth_validator, get_api_header, get_api_info, get_api_samples, tls_config, ApiError,
+};
 use crate::server::{Image, Size, DZI};
+use crate::xyz::{write_tile, Grid};
 use actix_cors::Cors;
 use actix_files::NamedFile;
 use actix_web::http::header::{ContentDisposition, DispositionType};
-use actix_web::{error, middleware::Logger, web, HttpRequest, Responder, Result};
-use bam::Record;
+use actix_web::middleware::Condition;
+use actix_web::{middleware::Logger, web, HttpRequest, Responder, Result};
+use actix_web_httpauth::middleware::HttpAuthentication;
 use clap::{App, AppSettings, Arg, ArgMatches, ArgSettings};
 use genomic_range::StringRegion;
-use ghi::{vis::bam_record_vis, ChromosomeBufferTrait, ReadBuffer, Vis, VisRef};
+use ghi::auth::Credentials;
+use ghi::cache::stable_hash;
+use ghi::snapshot::{InFlight, Snapshot};
+use ghi::{ChromosomeBufferTrait, Vis};
 use itertools::Itertools;
 use rand::Rng;
+use rayon::prelude::*;
+use std::convert::TryFrom;
+use std::path::Path;
+use std::sync::Arc;
 use std::time::Instant;
 use std::{collections::BTreeSet, fs, sync::RwLock};
 
+/// Results of drawing shared by identical requests in flight.
+type Renders = InFlight<Result<(), String>>;
+
 fn id_to_range(
     range: &StringRegion,
     args: &[String],
@@ -121,6 +136,8 @@ fn id_to_range(
                     .takes_value(true)
                     .about("Cache directory for server (generated randomly if not specified)"),
             )
+            .arg(Arg::new("cache-size").long("cache-size").takes_value(true).about("Set the maximal size in MB of files cached by the REST server, evicting the oldest ones [default: 1024]"))
+            .arg(Arg::new("cache-age").long("cache-age").takes_value(true).about("Set seconds to keep files cached by the REST server (kept until evicted by --cache-size if not given)"))
             .arg(
                     Arg::new("static-dir")
                         .short('.')
@@ -155,6 +172,37 @@ fn id_to_range(
                     .about("Hide read alignments (display only coverage; for chromosome scale view)"),
             )
             .arg(Arg::new("all-bases").short('B').about("Show all nucleotides by color"))
+            .arg(Arg::new("allele-count").long("allele-count-track").takes_value(true).about("Show per-base allele counts on each coverage track"))
+            .arg(Arg::new("soft-clip").long("show-soft-clips").about("Show soft-clipped sequences as faded extensions of read alignments"))
+            .arg(Arg::new("view-as-pairs").long("view-as-pairs").about("Display mates of paired-end reads on the same line connected by a line, colored by insert size and orientation"))
+            .arg(Arg::new("insert-size-range").long("insert-size-range").takes_value(true).about("Expected insert sizes as min:max on --view-as-pairs (inferred from 0.5 and 99.5 percentiles if not given)"))
+            .arg(Arg::new("pair-orientation").long("pair-orientation").takes_value(true).possible_values(&["FR", "RF", "FF", "RR"]).about("Expected orientation of mates on --view-as-pairs [default: FR]"))
+            .arg(Arg::new("downsample").long("downsample").takes_value(true).about("Downsample reads deterministically to at most the given number of reads starting in each window (coverage is computed from all reads)"))
+            .arg(Arg::new("downsample-window").long("downsample-window").takes_value(true).about("Window size in bp for --downsample [default: 50]"))
+            .arg(Arg::new("downsample-seed").long("downsample-seed").takes_value(true).about("Seed of the read-name hash for --downsample [default: 0]"))
+            .arg(Arg::new("display-mode").long("display-mode").takes_value(true).about("Set display modes of BAM inputs: expanded, squished (thin rows without details) or collapsed (a single density row); comma-separated in the order of inputs, or a single mode for all"))
+            .arg(Arg::new("circos").long("circos").takes_value(true).about("Draw a circos plot of translocations, clustering breakpoints of split alignments of loaded reads and weighting chords by supporting reads"))
+            .arg(Arg::new("breakpoint-tolerance").long("breakpoint-tolerance").takes_value(true).about("Distance in bp to cluster breakpoints of split alignments [default: 100]"))
+            .arg(Arg::new("dotplot").long("dotplot").about("Draw a dot plot of all alignments of the read given by --read-name (the primary and SA entries) on reference against read coordinates"))
+            .arg(Arg::new("breakpoint-view").long("breakpoint-view").about("Show each split read on one row across all ranges (e.g. on different chromosomes) and link its pieces to the panels of their neighbors"))
+            .arg(Arg::new("highlight-reads").long("highlight-reads").takes_value(true).about("Outline reads whose names are listed in the file (one name per line)"))
+            .arg(Arg::new("highlight-read-names").long("highlight-read-names").takes_value(true).about("Outline reads whose names are given as a comma-separated list"))
+            .arg(Arg::new("only-reads").long("only-reads").takes_value(true).about("Show only reads whose names are listed in the file (one name per line)"))
+            .arg(Arg::new("only-read-names").long("only-read-names").takes_value(true).about("Show only reads whose names are given as a comma-separated list"))
+            .arg(Arg::new("catalog").long("catalog").takes_value(true).about("Serve GHB/BAM datasets at /datasets/<id> on the REST server (--rest-server only; not supported by --web); a directory of the files (ids are file stems) or a YAML file listing ids and paths"))
+            .arg(Arg::new("max-open-datasets").long("max-open-datasets").takes_value(true).about("Set the maximal number of datasets kept open on --catalog, closing the least recently used one [default: 8]"))
+            .arg(Arg::new("tiles").long("tiles").takes_value(true).about("Render tiles of the zoomable web server into the directory with genome.dzi and exit"))
+            .arg(Arg::new("tile-zoom").long("tile-zoom").takes_value(true).about("Zoom levels to render on --tiles as min:max or a single level [default: all levels]"))
+            .arg(Arg::new("tile-range").long("tile-range").takes_value(true).multiple(true).about("Render only tiles overlapping the genomic ranges on --tiles"))
+            .arg(Arg::new("auth-file").long("auth-file").takes_value(true).about("Authenticate users by Argon2 hashes of their passwords and API tokens by SHA-256 digests in a YAML file for the web server, optionally restricting datasets on --catalog (overrides --basic-auth)"))
+            .arg(Arg::new("tls-cert").long("tls-cert").takes_value(true).requires("tls-key").about("Serve HTTPS on the web server with the certificate chain in PEM"))
+            .arg(Arg::new("tls-key").long("tls-key").takes_value(true).requires("tls-cert").about("Private key in PEM (PKCS #8, RSA or EC) of --tls-cert"))
+            .arg(Arg::new("genome-overview").long("genome-overview").about("Draw binned coverage over all chromosomes with ideograms and markers of translocational split alignments"))
+            .arg(Arg::new("cytoband").long("cytoband").takes_value(true).about("Cytoband BED (UCSC cytoBand) to draw ideograms on --genome-overview; only chromosomes in the file are drawn"))
+            .arg(Arg::new("overview-bin-size").long("overview-bin-size").takes_value(true).about("Bin size in bp of coverage on --genome-overview [default: 1000000]"))
+            .arg(Arg::new("track-colors").long("track-colors").takes_value(true).about("Set read colors of BAM inputs as #rrggbb; comma-separated in the order of inputs (empty to keep the default)"))
+            .arg(Arg::new("track-heights").long("track-heights").takes_value(true).about("Set the maximal number of rows of BAM inputs; comma-separated in the order of inputs (empty for no limit)"))
+            .arg(Arg::new("track-filters").long("track-filters").takes_value(true).about("Set read filters of BAM inputs, e.g. min-mapq=20,min-read-length=1000,flag-exclude=1796; semicolon-separated in the order of inputs"))
             .arg(
                 Arg::new("pileup")
                     .short('P')
@@ -180,6 +228,7 @@ fn id_to_range(
                     .takes_value(true)
                     .about("[Output] image file (prefixed as .bmp / .png)"),
             )
+            .arg(Arg::new("output-dir").long("output-dir").takes_value(true).about("[Output] Directory to write an image per region of --bed-range (named after the name column) in parallel, with index.html of thumbnails"))
             .arg(
                 Arg::new("bed")
                     .short('L')
@@ -342,143 +391,188 @@ async fn get_js_aux(_data: web::Data<RwLock<Item>>) -> Result<NamedFile> {
 
 async fn index<T: 'static + ChromosomeBufferTrait + Send + Sync>(
     item: web::Data<RwLock<Item>>,
-    vis: web::Data<RwLock<Vis>>,
-    list: web::Data<RwLock<Vec<(u64, Record)>>>,
-    list_btree: web::Data<RwLock<ReadBuffer>>,
+    snapshot: web::Data<RwLock<Arc<Snapshot>>>,
     buffer: web::Data<RwLock<T>>,
+    renders: web::Data<Renders>,
     req: HttpRequest,
 ) -> Result<NamedFile> {
-    let start = Instant::now();
-    let zoom: u64 = req.match_info().query("zoom").parse().unwrap();
-    let path: u64 = req.match_info().query("filename").parse().unwrap();
+    let zoom = path_number(&req, "zoom")?;
+    let path = path_number(&req, "filename")?;
     let format = req.match_info().query("format");
+    let path_string = strip(item, snapshot, buffer, renders, zoom, path, format).await?;
+    Ok(NamedFile::open(path_string)
+        .map_err(ApiError::internal)?
+        .set_content_disposition(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![],
+        }))
+}
+
+/// Returns the path of the `path`-th tile on the zoom level, which is a horizontal strip of
+/// all rows, drawing it unless cached.
+async fn strip<T: 'static + ChromosomeBufferTrait + Send + Sync>(
+    item: web::Data<RwLock<Item>>,
+    snapshot: web::Data<RwLock<Arc<Snapshot>>>,
+    buffer: web::Data<RwLock<T>>,
+    renders: web::Data<Renders>,
+    zoom: u64,
+    path: u64,
+    format: &str,
+) -> Result<String> {
+    if !["png", "bmp"].contains(&format) {
+        return Err(ApiError::bad_request(format!("Invalid format: {}", format)).into());
+    }
+    let start = Instant::now();
     let data = item.read().unwrap();
 
     let cache_dir = &data.params.cache_dir;
-    match NamedFile::open(format!("{}/{}/{}_0.{}", cache_dir, zoom, path, format)) {
-        Ok(file) => Ok(file.set_content_disposition(ContentDisposition {
-            disposition: DispositionType::Attachment,
-            parameters: vec![],
-        })),
-        _ => {
-            let end0 = start.elapsed();
-            eprintln!(
-                "match named file: {}.{:03} sec.",
-                end0.as_secs(),
-                end0.subsec_millis()
-            );
-            let params = &data.params;
-            let args = &data.args;
-
-            //let min_zoom = 13;
-
-            let path_string = format!("{}/{}/{}_0.{}", cache_dir, zoom, path, format);
-            let max_zoom = params.max_zoom as u64;
-            //let min_zoom = ((&data.params).criteria << (max_zoom - zoom)) >= 10000000;
-            let min_zoom = params.min_zoom as u64;
-            if zoom < min_zoom || zoom > max_zoom as u64 {
-                return Err(error::ErrorBadRequest(format!(
-                    "zoom level {} should be between {} and {}",
-                    zoom, min_zoom, max_zoom
-                )));
-            }
-            fs::create_dir_all(format!("{}/{}", cache_dir, zoom))?; //error is permitted.
-            let end1 = start.elapsed();
-            eprintln!(
-                "create dir: {}.{:03} sec.",
-                end1.as_secs(),
-                end1.subsec_millis()
-            );
+    let path_string = format!("{}/{}/{}_0.{}", cache_dir, zoom, path, format);
+    if Path::new(&path_string).exists() {
+        return Ok(path_string);
+    }
+    let end0 = start.elapsed();
+    eprintln!(
+        "match named file: {}.{:03} sec.",
+        end0.as_secs(),
+        end0.subsec_millis()
+    );
+    let params = &data.params;
+    let args = &data.args;
 
-            let (matches, string_range) =
-                id_to_range(&data.range, args, zoom, path, params, path_string.clone());
-            let end2 = start.elapsed();
-            eprintln!(
-                "id_to_range: {}.{:03} sec.",
-                end2.as_secs(),
-                end2.subsec_millis()
-            );
-            if !buffer
-                .read()
-                .unwrap()
-                .included_string_local(&string_range, &list_btree.read().unwrap())
-            {
-                // TODO() This ignores
-                let endx = start.elapsed();
-                eprintln!(
-                    "Fallback to reload: {}.{:03} sec.",
-                    endx.as_secs(),
-                    endx.subsec_millis()
-                );
-                //let (mut list, mut list_btree) = &*;
-                {
-                    buffer.write().unwrap().retrieve(
-                        &string_range,
-                        &mut list.write().unwrap(),
-                        &mut list_btree.write().unwrap(),
-                    );
-                }
-                let new_vis = buffer.read().unwrap().vis(
-                    &matches,
-                    &string_range,
-                    &mut list.write().unwrap(),
-                    &mut list_btree.write().unwrap(),
-                );
-                let endy = start.elapsed();
-                eprintln!(
-                    "Fallback to reload2: {}.{:03} sec.",
-                    endy.as_secs(),
-                    endy.subsec_millis()
-                );
-                let mut old_vis = vis.write().unwrap();
-                *old_vis = new_vis.unwrap();
-                let endz = start.elapsed();
-                eprintln!(
-                    "Fallback to reload3: {}.{:03} sec.",
-                    endz.as_secs(),
-                    endz.subsec_millis()
-                );
+    let max_zoom = params.max_zoom as u64;
+    let min_zoom = params.min_zoom as u64;
+    if zoom < min_zoom || zoom > max_zoom as u64 {
+        return Err(ApiError::bad_request(format!(
+            "zoom level {} should be between {} and {}",
+            zoom, min_zoom, max_zoom
+        ))
+        .into());
+    }
+    //error is permitted.
+    fs::create_dir_all(format!("{}/{}", cache_dir, zoom)).map_err(ApiError::internal)?;
+    let end1 = start.elapsed();
+    eprintln!(
+        "create dir: {}.{:03} sec.",
+        end1.as_secs(),
+        end1.subsec_millis()
+    );
+
+    let (matches, string_range) =
+        id_to_range(&data.range, args, zoom, path, params, path_string.clone());
+    drop(data);
+    let end2 = start.elapsed();
+    eprintln!(
+        "id_to_range: {}.{:03} sec.",
+        end2.as_secs(),
+        end2.subsec_millis()
+    );
+
+    // Tiles are drawn in the blocking thread pool on a snapshot of the loaded reads, and
+    // identical tiles in flight wait for the first one.
+    let key = stable_hash(&[&path_string]);
+    let output = path_string.clone();
+    web::block(move || {
+        renders.run(key, || {
+            if Path::new(&output).exists() {
+                return Ok(());
             }
+            let snapshot = Snapshot::load(&matches, &string_range, &snapshot, &buffer)
+                .map_err(|e| e.to_string())?;
+            snapshot
+                .render(&matches, string_range)
+                .map_err(|e| e.to_string())
+        })
+    })
+    .await
+    .map_err(ApiError::internal)?
+    .map_err(ApiError::internal)?;
+    let end3 = start.elapsed();
+    eprintln!(
+        "img_saved: {}.{:03} sec.",
+        end3.as_secs(),
+        end3.subsec_millis()
+    );
+    Ok(path_string)
+}
 
-            let data = vis.read().unwrap();
-            let ann = &data.annotation;
-            let freq = &data.freq;
-            let compressed_list = &data.compressed_list;
-            let index_list = &data.index_list;
-            let supplementary_list = &data.supplementary_list;
-            let prev_index = data.prev_index;
-
-            // If the end is exceeds the prefetch region, raise error.
-            bam_record_vis(
-                &matches,
-                vec![VisRef::new(
-                    string_range,
-                    &list.read().unwrap(),
-                    ann,
-                    freq,
-                    compressed_list,
-                    index_list,
-                    prev_index,
-                    supplementary_list,
-                )],
-                |_| None,
-            )
-            .unwrap();
-            let end3 = start.elapsed();
-            eprintln!(
-                "img_saved: {}.{:03} sec.",
-                end3.as_secs(),
-                end3.subsec_millis()
-            );
-            // bam_vis(matches, 1);
-            Ok(
-                NamedFile::open(path_string)?.set_content_disposition(ContentDisposition {
-                    disposition: DispositionType::Attachment,
-                    parameters: vec![],
-                }),
-            )
-        }
+/// Returns the XYZ tile `{x}/{y}` on the zoom `{z}`, which is a square cropped from the DZI
+/// strip of the column.
+async fn get_xyz<T: 'static + ChromosomeBufferTrait + Send + Sync>(
+    item: web::Data<RwLock<Item>>,
+    snapshot: web::Data<RwLock<Arc<Snapshot>>>,
+    buffer: web::Data<RwLock<T>>,
+    renders: web::Data<Renders>,
+    req: HttpRequest,
+) -> Result<NamedFile> {
+    let (z, x, y) = (
+        path_number(&req, "z")?,
+        path_number(&req, "x")?,
+        path_number(&req, "y")?,
+    );
+    let format = req.match_info().query("format");
+    if !["png", "bmp"].contains(&format) {
+        return Err(ApiError::bad_request(format!("Invalid format: {}", format)).into());
     }
+    let (cache_dir, size, grid) = {
+        let data = item.read().unwrap();
+        (
+            data.params.cache_dir.clone(),
+            data.params.x,
+            data.params.grid(),
+        )
+    };
+    let (level, path) = grid
+        .tile(u32::try_from(z).unwrap_or(u32::MAX), x, y)
+        .ok_or_else(|| ApiError::not_found(format!("No tile {}/{}/{}", z, x, y)))?;
+    let tile_dir = format!("{}/xyz/{}/{}", cache_dir, z, x);
+    let tile = format!("{}/{}.{}", tile_dir, y, format);
+    if !Path::new(&tile).exists() {
+        let strip = match path {
+            Some(path) => Some(
+                strip(
+                    item,
+                    snapshot,
+                    buffer,
+                    renders.clone(),
+                    level as u64,
+                    path,
+                    format,
+                )
+                .await?,
+            ),
+            None => None,
+        };
+        fs::create_dir_all(&tile_dir).map_err(ApiError::internal)?;
+        let output = tile.clone();
+        let y = u32::try_from(y).unwrap_or(u32::MAX);
+        web::block(move || {
+            renders.run(stable_hash(&[&output]), || {
+                write_tile(strip.as_deref(), y, size, &output)
+            })
+        })
+        .await
+        .map_err(ApiError::internal)?
+        .map_err(ApiError::internal)?;
+    }
+    Ok(NamedFile::open(tile).map_err(ApiError::internal)?)
+}
+
+/// A number in the path of a request.
+fn path_number(req: &HttpRequest, name: &str) -> std::result::Result<u64, ApiError> {
+    let value = req.match_info().query(name);
+    value
+        .parse::<u64>()
+        .map_err(|_| ApiError::bad_request(format!("Invalid {}: {}", name, value)))
+}
+
+/// TileJSON describing the XYZ tiles.
+async fn get_tilejson(data: web::Data<RwLock<Item>>) -> impl Responder {
+    let data = data.read().unwrap();
+    let image = &data.dzi.image;
+    web::Json(data.params.grid().tilejson(
+        format!("{}xyz/{{z}}/{{x}}/{{y}}.{}", image.url, image.format),
+        data.params.x,
+    ))
 }
 
 pub struct Item {
@@ -504,7 +598,7 @@ impl Item {
 pub struct Param {
     x_scale: u32,
     max_y: u32,
-    _prefetch_max: u64,
+    prefetch_max: u64,
     criteria: u64,
     max_zoom: u32,
     min_zoom: u32,
@@ -515,6 +609,17 @@ pub struct Param {
     y_adjust: bool,
 }
 
+impl Param {
+    fn grid(&self) -> Grid {
+        Grid::new(
+            self.min_zoom,
+            self.max_zoom,
+            self.criteria,
+            self.prefetch_max,
+        )
+    }
+}
+
 const fn num_bits<T>() -> usize {
     std::mem::size_of::<T>() * 8
 }
@@ -524,25 +629,17 @@ fn log_2(x: i64) -> u32 {
     num_bits::<i64>() as u32 - x.leading_zeros() - 1
 }
 
-#[actix_rt::main]
-pub async fn server<T: 'static + ChromosomeBufferTrait + Send + Sync>(
-    matches: ArgMatches,
-    range: StringRegion,
+/// Geometry of the tile pyramid: the range covered by tiles, parameters to draw them and
+/// the DZI descriptor of tiles at `url`.
+fn layout(
+    matches: &ArgMatches,
+    range: &StringRegion,
     prefetch_range: StringRegion,
-    args: Vec<String>,
-    mut buffer: T,
-    threads: u16,
-) -> std::io::Result<()> {
-    use actix_web::{web, HttpServer};
-    let mut list = vec![];
-    let mut list_btree = (0, BTreeSet::new());
-    let bind = matches.value_of("web").unwrap_or(&"0.0.0.0:4000");
+    vis: &Vis,
+    url: String,
+    cache_dir: String,
+) -> (StringRegion, Param, DZI) {
     let no_margin = matches.is_present("no-scale");
-    buffer.retrieve(&prefetch_range, &mut list, &mut list_btree);
-    let vis = buffer
-        .vis(&matches, &prefetch_range, &mut list, &mut list_btree)
-        .unwrap();
-
     let annotation = &vis.annotation;
     let prev_index = vis.prev_index;
     let freq = &vis.freq;
@@ -598,7 +695,7 @@ pub async fn server<T: 'static + ChromosomeBufferTrait + Send + Sync>(
     };
     let image = Image {
         xmlns: "http://schemas.microsoft.com/deepzoom/2008".to_string(),
-        url: format!("http://{}/", bind),
+        url,
         format: format.to_string(),
         overlap: "0".to_string(),
         tile_size: x.to_string(),
@@ -609,11 +706,6 @@ pub async fn server<T: 'static + ChromosomeBufferTrait + Send + Sync>(
         .value_of("x-scale")
         .and_then(|a| a.parse::<u32>().ok())
         .unwrap_or(20u32);
-    let mut rng = rand::thread_rng();
-    let cache_dir = matches
-        .value_of("cache-dir")
-        .map(|a| a.to_string())
-        .unwrap_or_else(|| rng.gen::<u32>().to_string());
     let y_adjust = matches.is_present("adjust-y");
 
     let x_width = all as u32 / diff as u32 * x;
@@ -623,14 +715,10 @@ pub async fn server<T: 'static + ChromosomeBufferTrait + Send + Sync>(
     );
     let max_zoom = log_2(x_width as i64) + 1;
     let min_zoom = max_zoom - zoom_range;
-
-    if let Err(e) = fs::create_dir(&cache_dir) {
-        panic!("{}: {}", &cache_dir, e)
-    }
     let params = Param {
         x_scale,
         max_y: x,
-        _prefetch_max: all,
+        prefetch_max: all,
         max_zoom,
         min_zoom,
         criteria: diff,
@@ -640,40 +728,206 @@ pub async fn server<T: 'static + ChromosomeBufferTrait + Send + Sync>(
         cache_dir,
         y_adjust,
     };
+    (view_range, params, dzi)
+}
+
+/// Zoom levels given by `tile-zoom` as min:max or a single level, within those of the
+/// pyramid.
+fn tile_zoom(matches: &ArgMatches, params: &Param) -> std::io::Result<(u32, u32)> {
+    let invalid = |t: &str| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Invalid zoom levels: {}", t),
+        )
+    };
+    let (min, max) = match matches.value_of("tile-zoom") {
+        Some(t) => {
+            let mut levels = t.splitn(2, ':').map(|t| t.parse::<u32>());
+            let min = levels
+                .next()
+                .and_then(|t| t.ok())
+                .ok_or_else(|| invalid(t))?;
+            let max = match levels.next() {
+                Some(level) => level.map_err(|_| invalid(t))?,
+                None => min,
+            };
+            (min, max)
+        }
+        None => (params.min_zoom, params.max_zoom),
+    };
+    Ok((min.max(params.min_zoom), max.min(params.max_zoom)))
+}
+
+/// Pre-renders tiles of the pyramid with `genome.dzi` into the directory given by `tiles`,
+/// which is served by `vis --web -W` with the same options and `-d`, or by a static file host.
+/// Tiles already in the directory are kept.
+pub fn tiles<T: ChromosomeBufferTrait + Send + Sync>(
+    matches: ArgMatches,
+    range: StringRegion,
+    prefetch_range: StringRegion,
+    args: Vec<String>,
+    mut buffer: T,
+    threads: u16,
+) -> std::io::Result<()> {
+    let mut list = vec![];
+    let mut list_btree = (0, BTreeSet::new());
+    buffer.retrieve(&prefetch_range, &mut list, &mut list_btree);
+    let vis = buffer.vis(&matches, &prefetch_range, &mut list, &mut list_btree)?;
+    let dir = matches.value_of("tiles").unwrap().to_string();
+    // Tiles are relative to the page on a static file host unless the server is given.
+    let scheme = if matches.is_present("tls-cert") {
+        "https"
+    } else {
+        "http"
+    };
+    let url = matches
+        .value_of("web")
+        .map_or_else(|| "./".to_string(), |t| format!("{}://{}/", scheme, t));
+    let (view_range, params, dzi) =
+        layout(&matches, &range, prefetch_range, &vis, url, dir.clone());
+    fs::create_dir_all(&dir)?;
+    fs::write(format!("{}/genome.dzi", dir), serde_json::to_string(&dzi)?)?;
+    let tile_ranges = matches
+        .values_of("tile-range")
+        .into_iter()
+        .flatten()
+        .map(|t| {
+            StringRegion::new(t).map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("Invalid range: {}", t),
+                )
+            })
+        })
+        .collect::<std::io::Result<Vec<_>>>()?;
+    let (min_zoom, max_zoom) = tile_zoom(&matches, &params)?;
+    let snapshot = RwLock::new(Arc::new(Snapshot::new(list, list_btree, vis)));
+    let buffer = RwLock::new(buffer);
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads as usize)
+        .build()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    eprintln!(
+        "Rendering zoom levels {} to {} into {} with {} threads",
+        min_zoom, max_zoom, dir, threads
+    );
+
+    for zoom in min_zoom..=max_zoom {
+        let start = Instant::now();
+        let width = params.criteria << (params.max_zoom - zoom);
+        let count = (view_range.end - view_range.start) / width + 1;
+        let paths: Vec<u64> = (0..count)
+            .filter(|path| {
+                let start = view_range.start + width * path;
+                let end = start + width - 1;
+                tile_ranges.is_empty()
+                    || tile_ranges
+                        .iter()
+                        .any(|t| t.path == view_range.path && t.start <= end && start <= t.end)
+            })
+            .collect();
+        fs::create_dir_all(format!("{}/{}", dir, zoom))?;
+        pool.install(|| {
+            paths.par_iter().try_for_each(|&path| {
+                let path_string = format!("{}/{}/{}_0.{}", dir, zoom, path, dzi.image.format);
+                if Path::new(&path_string).exists() {
+                    return Ok(());
+                }
+                let (matches, string_range) =
+                    id_to_range(&view_range, &args, zoom as u64, path, &params, path_string);
+                let snapshot = Snapshot::load(&matches, &string_range, &snapshot, &buffer)
+                    .map_err(|e| e.to_string())?;
+                snapshot
+                    .render(&matches, string_range)
+                    .map_err(|e| e.to_string())
+            })
+        })
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        let end = start.elapsed();
+        eprintln!(
+            "Zoom level {}: {} tiles in {}.{:03} sec.",
+            zoom,
+            paths.len(),
+            end.as_secs(),
+            end.subsec_millis()
+        );
+    }
+    Ok(())
+}
+
+#[actix_rt::main]
+pub async fn server<T: 'static + ChromosomeBufferTrait + Send + Sync>(
+    matches: ArgMatches,
+    range: StringRegion,
+    prefetch_range: StringRegion,
+    args: Vec<String>,
+    mut buffer: T,
+    threads: u16,
+) -> std::io::Result<()> {
+    use actix_web::{web, HttpServer};
+    let mut list = vec![];
+    let mut list_btree = (0, BTreeSet::new());
+    let bind = matches.value_of("web").unwrap_or(&"0.0.0.0:4000");
+    let tls = tls_config(&matches)?;
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    buffer.retrieve(&prefetch_range, &mut list, &mut list_btree);
+    let vis = buffer.vis(&matches, &prefetch_range, &mut list, &mut list_btree)?;
+    let mut rng = rand::thread_rng();
+    let cache_dir = matches
+        .value_of("cache-dir")
+        .map(|a| a.to_string())
+        .unwrap_or_else(|| rng.gen::<u32>().to_string());
+    // Tiles pre-rendered by the tiles subcommand are reused.
+    if let Err(e) = fs::create_dir_all(&cache_dir) {
+        panic!("{}: {}", &cache_dir, e)
+    }
+    let (view_range, params, dzi) = layout(
+        &matches,
+        &range,
+        prefetch_range,
+        &vis,
+        format!("{}://{}/", scheme, bind),
+        cache_dir,
+    );
     eprintln!(
         "{:?}, threads: {}, zoom: {}",
-        params,
-        threads,
-        log_2(x_width as i64) + 1
+        params, threads, params.max_zoom
     );
     println!("Buffered Server is running on {}", bind);
     let counter = web::Data::new(RwLock::new(Item::new(view_range, args, params, dzi)));
     let buffer = web::Data::new(RwLock::new(buffer));
+    let snapshot = web::Data::new(RwLock::new(Arc::new(Snapshot::new(list, list_btree, vis))));
+    let renders = web::Data::new(Renders::new());
+    let credentials = Credentials::from_matches(&matches)?;
+    let auth_condition = credentials.is_some();
+    let credentials = web::Data::new(credentials.unwrap_or_default());
     let cross_origin_bool = matches.is_present("production");
 
     // https://github.com/actix/examples/blob/master/state/src/main.rs
-    HttpServer::new(move || {
+    let server = HttpServer::new(move || {
         let cross_origin = if cross_origin_bool {
             Cors::default()
         } else {
             Cors::permissive()
         };
-
-        let list = RwLock::new(list.clone());
-        let list_btree = RwLock::new(list_btree.clone()); //buffer =
-        let vis = RwLock::new(vis.clone());
+        let auth = HttpAuthentication::with_fn(auth_validator);
 
         actix_web::App::new()
-            .app_data(web::Data::new(list))
-            .app_data(web::Data::new(list_btree)) /*.app_data(web::Data::new(RwLock::new(list.clone()))).*/
             .app_data(counter.clone())
-            .app_data(web::Data::new(vis)) //.app_data(vis.clone())
+            .app_data(credentials.clone())
+            .app_data(snapshot.clone())
             .app_data(buffer.clone())
+            .app_data(renders.clone())
             .route("/", web::get().to(get_index))
             .route("openseadragon.min.js", web::get().to(get_js))
             .route("openseadragon.min.js.map", web::get().to(get_js_map))
             .route("openseadragon-scalebar.js", web::get().to(get_js_aux))
             .route("genome.dzi", web::get().to(get_dzi))
+            .route("tilejson.json", web::get().to(get_tilejson))
+            .route("/xyz/{z}/{x}/{y}.{format}", web::get().to(get_xyz::<T>))
+            .route("/api/header", web::get().to(get_api_header::<T>))
+            .route("/api/samples", web::get().to(get_api_samples::<T>))
+            .route("/api/info", web::get().to(get_api_info))
             .route(
                 "/{zoom:.*}/{filename:.*}_0.{format:.*}",
                 web::get().to(index::<T>),
@@ -681,9 +935,47 @@ pub async fn server<T: 'static + ChromosomeBufferTrait + Send + Sync>(
             .service(actix_files::Files::new("/images", "static/images").show_files_listing())
             .wrap(Logger::default())
             .wrap(cross_origin)
-    })
-    .bind(bind)?
-    .workers(threads as usize)
-    .run()
-    .await
+            .wrap(Condition::new(auth_condition, auth))
+    });
+    let server = match tls {
+        Some(tls) => server.bind_rustls(bind, tls)?,
+        None => server.bind(bind)?,
+    };
+    server.workers(threads as usize).run().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tile_zoom, Param};
+    use clap::{App, Arg};
+
+    #[test]
+    fn zoom_levels() {
+        let params = Param {
+            x_scale: 20,
+            max_y: 1280,
+            prefetch_max: 1_000_000,
+            criteria: 1000,
+            max_zoom: 18,
+            min_zoom: 10,
+            y_freq: 50,
+            x: 1280,
+            y: 15,
+            cache_dir: String::new(),
+            y_adjust: false,
+        };
+        let zoom = |args: &[&str]| {
+            let matches = App::new("vis")
+                .arg(Arg::new("tile-zoom").long("tile-zoom").takes_value(true))
+                .get_matches_from(args);
+            tile_zoom(&matches, &params).ok()
+        };
+        assert_eq!(zoom(&["vis"]), Some((10, 18)));
+        assert_eq!(zoom(&["vis", "--tile-zoom", "12:14"]), Some((12, 14)));
+        assert_eq!(zoom(&["vis", "--tile-zoom", "15"]), Some((15, 15)));
+        // Levels out of the pyramid are not rendered.
+        assert_eq!(zoom(&["vis", "--tile-zoom", "5:30"]), Some((10, 18)));
+        assert_eq!(zoom(&["vis", "--tile-zoom", "a:14"]), None);
+        assert_eq!(zoom(&["vis", "--tile-zoom", "12:"]), None);
+    }
 }
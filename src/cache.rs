@@ -0,0 +1,291 @@
+use crate::hash::{fnv1a, fnv1a_with};
+use clap::ArgMatches;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Options of `vis` giving input files, whose modification invalidates cached images.
+const INPUT_OPTIONS: [&str; 10] = [
+    "INPUT",
+    "bam",
+    "frequency",
+    "bed",
+    "gff3",
+    "ref-column",
+    "graph",
+    "cytoband",
+    "highlight-reads",
+    "only-reads",
+];
+
+/// Extensions of indexes next to inputs, e.g. of a FASTA reference given by `ref-column`.
+const INDEX_EXTENSIONS: [&str; 2] = ["fai", "gzi"];
+
+/// 64-bit FNV-1a hash of the fields, which is stable across Rust releases and platforms.
+pub fn stable_hash(fields: &[&str]) -> u64 {
+    fields.iter().fold(fnv1a(b""), |hash, field| {
+        // The length prefix separates fields, e.g. ["ab", "c"] and ["a", "bc"].
+        let hash = fnv1a_with(hash, &(field.len() as u64).to_le_bytes());
+        fnv1a_with(hash, field.as_bytes())
+    })
+}
+
+/// Input files given to `vis`.
+pub fn input_files(matches: &ArgMatches) -> Vec<String> {
+    INPUT_OPTIONS
+        .iter()
+        .filter_map(|t| matches.values_of(t))
+        .flatten()
+        .map(|t| t.to_string())
+        .collect()
+}
+
+/// Paths with their sizes and modification times (in nanoseconds), or only the paths if
+/// they cannot be read. Each path is followed by its existing indexes.
+pub fn input_stamps(paths: &[String]) -> Vec<String> {
+    paths
+        .iter()
+        .flat_map(|path| {
+            let indexes = INDEX_EXTENSIONS
+                .iter()
+                .map(move |t| format!("{}.{}", path, t))
+                .filter(|t| Path::new(t).is_file());
+            std::iter::once(path.clone()).chain(indexes)
+        })
+        .map(|path| {
+            let stamp = fs::metadata(&path).and_then(|t| Ok((t.len(), t.modified()?)));
+            match stamp {
+                Ok((len, modified)) => {
+                    let modified = modified
+                        .duration_since(UNIX_EPOCH)
+                        .map_or(0, |t| t.as_nanos());
+                    format!("{}:{}:{}", path, len, modified)
+                }
+                Err(_) => path,
+            }
+        })
+        .collect()
+}
+
+/// Rendered images and JSON files in a directory, named by their keys and kept up to
+/// `max_size` bytes in total and `max_age` since they are written.
+///
+/// The directory is reused on restart; other files in it are left untouched.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderCache {
+    dir: String,
+    max_size: u64,
+    max_age: Option<Duration>,
+}
+
+impl RenderCache {
+    pub fn open(dir: &str, max_size: u64, max_age: Option<Duration>) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let cache = RenderCache {
+            dir: dir.to_string(),
+            max_size,
+            max_age,
+        };
+        cache.evict()?;
+        Ok(cache)
+    }
+
+    /// Opens the cache given by `cache-dir` (a directory of the server under the cache
+    /// directory of the user if not given), `cache-size` in MB (1024 if not given) and
+    /// `cache-age` in seconds.
+    pub fn from_matches(matches: &ArgMatches) -> io::Result<Self> {
+        let dir = matches
+            .value_of("cache-dir")
+            .map_or_else(|| default_dir(matches), |t| t.to_string());
+        let max_size = matches
+            .value_of("cache-size")
+            .and_then(|t| t.parse::<u64>().ok())
+            .unwrap_or(1024)
+            * 1024
+            * 1024;
+        let max_age = matches
+            .value_of("cache-age")
+            .and_then(|t| t.parse::<u64>().ok())
+            .map(Duration::from_secs);
+        Self::open(&dir, max_size, max_age)
+    }
+
+    pub fn dir(&self) -> &str {
+        &self.dir
+    }
+
+    pub fn path(&self, key: u64, format: &str) -> String {
+        format!("{}/{:016x}.{}", self.dir, key, format)
+    }
+
+    /// Returns the path of the cached file unless it is missing or expired.
+    pub fn get(&self, key: u64, format: &str) -> Option<String> {
+        let path = self.path(key, format);
+        let modified = fs::metadata(&path).and_then(|t| t.modified()).ok()?;
+        if self.is_expired(modified) {
+            let _ = fs::remove_file(&path);
+            return None;
+        }
+        Some(path)
+    }
+
+    fn is_expired(&self, modified: SystemTime) -> bool {
+        match (self.max_age, modified.elapsed()) {
+            (Some(max_age), Ok(age)) => age > max_age,
+            _ => false,
+        }
+    }
+
+    /// Cached files with their sizes and modification times, the oldest first.
+    fn entries(&self) -> io::Result<Vec<(PathBuf, u64, SystemTime)>> {
+        let mut entries = vec![];
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if !is_cache_file(&path) {
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            if metadata.is_file() {
+                entries.push((path, metadata.len(), metadata.modified()?));
+            }
+        }
+        entries.sort_by_key(|t| t.2);
+        Ok(entries)
+    }
+
+    /// Removes expired files, then the oldest ones beyond the size limit.
+    /// Returns the number of removed files.
+    pub fn evict(&self) -> io::Result<usize> {
+        let entries = self.entries()?;
+        let mut total: u64 = entries.iter().map(|t| t.1).sum();
+        let mut removed = 0;
+        for (path, len, modified) in entries {
+            if total <= self.max_size && !self.is_expired(modified) {
+                continue;
+            }
+            // The file may have been removed by another request.
+            match fs::remove_file(path) {
+                Ok(()) => removed += 1,
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+            total -= len;
+        }
+        Ok(removed)
+    }
+
+    /// Removes all cached files and returns the number of them.
+    pub fn purge(&self) -> io::Result<usize> {
+        let mut removed = 0;
+        for (path, _, _) in self.entries()? {
+            // The file may have been removed by another request.
+            match fs::remove_file(path) {
+                Ok(()) => removed += 1,
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(removed)
+    }
+}
+
+/// `hgb/<key>` in the cache directory of the user (`$XDG_CACHE_HOME` or `~/.cache`, or the
+/// temporary directory without them), keyed by the address and the inputs of the server so
+/// that servers neither share nor purge each other's files.
+fn default_dir(matches: &ArgMatches) -> String {
+    let non_empty = |key: &str| std::env::var_os(key).filter(|t| !t.is_empty());
+    let base = non_empty("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| non_empty("HOME").map(|t| PathBuf::from(t).join(".cache")))
+        .unwrap_or_else(std::env::temp_dir);
+    let inputs: Vec<String> = input_files(matches)
+        .into_iter()
+        .chain(matches.value_of("catalog").map(|t| t.to_string()))
+        .map(|t| fs::canonicalize(&t).map_or(t, |t| t.to_string_lossy().to_string()))
+        .collect();
+    let mut fields = vec![
+        matches.value_of("web").unwrap_or(""),
+        matches.value_of("unix-socket").unwrap_or(""),
+    ];
+    fields.extend(inputs.iter().map(|t| t.as_str()));
+    base.join("hgb")
+        .join(format!("{:016x}", stable_hash(&fields)))
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Files named by keys, e.g. `0123456789abcdef.png`.
+fn is_cache_file(path: &std::path::Path) -> bool {
+    matches!(
+        path.file_stem().and_then(|t| t.to_str()),
+        Some(t) if t.len() == 16 && t.chars().all(|c| c.is_ascii_hexdigit())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{default_dir, input_stamps, stable_hash, RenderCache};
+    use clap::{App, Arg};
+    use std::fs;
+
+    #[test]
+    fn hash() {
+        assert_eq!(stable_hash(&[]), 0xcbf2_9ce4_8422_2325);
+        assert_ne!(stable_hash(&["ab", "c"]), stable_hash(&["a", "bc"]));
+        assert_eq!(
+            stable_hash(&["png", "-r chr1"]),
+            stable_hash(&["png", "-r chr1"])
+        );
+    }
+
+    #[test]
+    fn stamps() {
+        let path = std::env::temp_dir().join("hgb_stamps.fa");
+        let path = path.to_str().unwrap().to_string();
+        fs::write(&path, ">chr1\nACGT\n").unwrap();
+        let _ = fs::remove_file(format!("{}.fai", path));
+        let paths = vec![path.clone()];
+        assert_eq!(input_stamps(&paths).len(), 1);
+        fs::write(format!("{}.fai", path), "chr1\t4\t6\t4\t5\n").unwrap();
+        let stamps = input_stamps(&paths);
+        assert_eq!(stamps.len(), 2);
+        assert!(stamps[1].starts_with(&format!("{}.fai:", path)));
+        fs::remove_file(format!("{}.fai", path)).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn eviction() {
+        let dir = std::env::temp_dir().join("hgb_render_cache");
+        let _ = fs::remove_dir_all(&dir);
+        let cache = RenderCache::open(dir.to_str().unwrap(), 25, None).unwrap();
+        for key in 0..3 {
+            fs::write(cache.path(key, "png"), [0u8; 10]).unwrap();
+        }
+        fs::write(dir.join("notes.txt"), "kept").unwrap();
+        assert!(cache.get(1, "png").is_some());
+        assert!(cache.get(3, "png").is_none());
+        assert_eq!(cache.evict().unwrap(), 1);
+        assert_eq!(cache.purge().unwrap(), 2);
+        assert!(dir.join("notes.txt").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn default_dirs() {
+        let dir = |args: &[&str]| {
+            let matches = App::new("vis")
+                .arg(Arg::new("bam").short('a').takes_value(true).multiple(true))
+                .arg(Arg::new("web").short('w').takes_value(true))
+                .get_matches_from(std::iter::once("vis").chain(args.iter().copied()));
+            default_dir(&matches)
+        };
+        let first = dir(&["-w", "0.0.0.0:4000", "-a", "Cargo.toml"]);
+        assert!(first.contains("hgb"));
+        assert_eq!(first, dir(&["-w", "0.0.0.0:4000", "-a", "./Cargo.toml"]));
+        assert_ne!(first, dir(&["-w", "0.0.0.0:4001", "-a", "Cargo.toml"]));
+        assert_ne!(first, dir(&["-w", "0.0.0.0:4000", "-a", "src"]));
+    }
+}
@@ -0,0 +1,219 @@
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
+
+/// Datasets served by `vis --rest-server --catalog`.
+///
+/// The catalog is a directory, where each GHB or BAM file is a dataset identified by its
+/// file stem, or a YAML file:
+///
+/// ```yaml
+/// datasets:
+///   - id: tumor
+///     path: /data/tumor.bam
+///   - id: cohort
+///     path: /data/cohort.ghb
+/// ```
+#[derive(Debug, Default, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Catalog {
+    pub datasets: Vec<Dataset>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Dataset {
+    /// Identifier in URLs; letters, digits, '-', '_' and '.' are allowed.
+    pub id: String,
+    /// GHB, or BAM with its index.
+    pub path: String,
+}
+
+impl Catalog {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let catalog = if path.is_dir() {
+            Self::from_dir(path)?
+        } else {
+            serde_yaml::from_reader(File::open(path)?)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?
+        };
+        catalog.verify()?;
+        Ok(catalog)
+    }
+
+    /// GHB and BAM files in the directory, sorted by their ids.
+    pub fn from_dir<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        let mut datasets = vec![];
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let is_dataset = matches!(
+                path.extension().and_then(|t| t.to_str()),
+                Some("ghb") | Some("bam")
+            );
+            if !is_dataset {
+                continue;
+            }
+            if let Some(id) = path.file_stem().and_then(|t| t.to_str()) {
+                datasets.push(Dataset {
+                    id: id.to_string(),
+                    path: path.to_string_lossy().to_string(),
+                });
+            }
+        }
+        datasets.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(Catalog { datasets })
+    }
+
+    /// Rejects empty, duplicated or unsafe ids.
+    pub fn verify(&self) -> io::Result<()> {
+        let mut ids = HashSet::new();
+        for dataset in self.datasets.iter() {
+            let id = &dataset.id;
+            if id.is_empty()
+                || id.starts_with('.')
+                || !id
+                    .chars()
+                    .all(|t| t.is_ascii_alphanumeric() || "-_.".contains(t))
+            {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Invalid dataset id: {:?}", id),
+                ));
+            }
+            if !ids.insert(id) {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Duplicated dataset id: {}", id),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Dataset> {
+        self.datasets.iter().find(|t| t.id == id)
+    }
+}
+
+impl Dataset {
+    pub fn is_bam(&self) -> bool {
+        self.path.ends_with(".bam")
+    }
+
+    /// Adds the dataset as an input of the `vis` command line.
+    pub fn args(&self, args: &[String]) -> Vec<String> {
+        let mut args = args.to_vec();
+        let index = args.iter().position(|t| t == "vis").map_or(0, |t| t + 1);
+        if self.is_bam() {
+            args.splice(index..index, vec!["-a".to_string(), self.path.clone()]);
+        } else {
+            args.insert(index, self.path.clone());
+        }
+        args
+    }
+}
+
+/// Values keyed by dataset ids, evicting the least recently used one beyond `capacity`.
+#[derive(Debug, Clone)]
+pub struct Lru<V> {
+    capacity: usize,
+    entries: VecDeque<(String, V)>,
+}
+
+impl<V> Lru<V> {
+    pub fn new(capacity: usize) -> Self {
+        Lru {
+            capacity: capacity.max(1),
+            entries: VecDeque::new(),
+        }
+    }
+
+    /// Returns the value, marking it as the most recently used.
+    pub fn get(&mut self, key: &str) -> Option<&V> {
+        let index = self.entries.iter().position(|t| t.0 == key)?;
+        let entry = self.entries.remove(index)?;
+        self.entries.push_back(entry);
+        self.entries.back().map(|t| &t.1)
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.entries.iter().any(|t| t.0 == key)
+    }
+
+    /// Inserts the value and returns the evicted ones.
+    pub fn insert(&mut self, key: String, value: V) -> Vec<(String, V)> {
+        if let Some(index) = self.entries.iter().position(|t| t.0 == key) {
+            self.entries.remove(index);
+        }
+        self.entries.push_back((key, value));
+        let mut evicted = vec![];
+        while self.entries.len() > self.capacity {
+            evicted.extend(self.entries.pop_front());
+        }
+        evicted
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Catalog, Dataset, Lru};
+
+    #[test]
+    fn catalog() {
+        let catalog: Catalog = serde_yaml::from_str(
+            "datasets:\n  - {id: tumor, path: /data/tumor.bam}\n  - {id: cohort, path: cohort.ghb}\n",
+        )
+        .unwrap();
+        assert!(catalog.verify().is_ok());
+        assert!(catalog.get("tumor").unwrap().is_bam());
+        assert_eq!(catalog.get("normal"), None);
+        let args: Vec<String> = ["hgb", "vis", "--rest-server"]
+            .iter()
+            .map(|t| t.to_string())
+            .collect();
+        assert_eq!(
+            catalog.get("tumor").unwrap().args(&args),
+            vec!["hgb", "vis", "-a", "/data/tumor.bam", "--rest-server"]
+        );
+        assert_eq!(
+            catalog.get("cohort").unwrap().args(&args),
+            vec!["hgb", "vis", "cohort.ghb", "--rest-server"]
+        );
+
+        let mut invalid = catalog.clone();
+        invalid.datasets.push(Dataset {
+            id: "../etc".to_string(),
+            path: "a.bam".to_string(),
+        });
+        assert!(invalid.verify().is_err());
+        let mut duplicated = catalog;
+        duplicated.datasets.push(Dataset {
+            id: "tumor".to_string(),
+            path: "b.bam".to_string(),
+        });
+        assert!(duplicated.verify().is_err());
+    }
+
+    #[test]
+    fn lru() {
+        let mut lru = Lru::new(2);
+        assert!(lru.insert("a".to_string(), 1).is_empty());
+        assert!(lru.insert("b".to_string(), 2).is_empty());
+        assert_eq!(lru.get("a"), Some(&1));
+        assert_eq!(lru.insert("c".to_string(), 3), vec![("b".to_string(), 2)]);
+        assert!(lru.contains("a"));
+        assert!(!lru.contains("b"));
+        assert_eq!(lru.len(), 2);
+    }
+}
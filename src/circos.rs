@@ -0,0 +1,142 @@
+use crate::breakpoint::Cluster;
+use plotters::prelude::*;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+/// Arcs of chromosomes around a circle, proportional to their lengths.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CircosLayout {
+    chromosomes: Vec<(String, u64)>,
+    /// (start angle, span) in radians of each chromosome.
+    arcs: Vec<(f64, f64)>,
+    index: HashMap<String, usize>,
+}
+
+impl CircosLayout {
+    /// A gap of `gap` radians is put after each chromosome.
+    pub fn new(chromosomes: Vec<(String, u64)>, gap: f64) -> Self {
+        let total: u64 = chromosomes.iter().map(|t| t.1).sum::<u64>().max(1);
+        let available = (2.0 * PI - gap * chromosomes.len() as f64).max(0.0);
+        let mut angle = 0.0;
+        let mut arcs = vec![];
+        for (_, len) in chromosomes.iter() {
+            let span = available * *len as f64 / total as f64;
+            arcs.push((angle, span));
+            angle += span + gap;
+        }
+        let index = chromosomes
+            .iter()
+            .enumerate()
+            .map(|(i, t)| (t.0.clone(), i))
+            .collect();
+        CircosLayout {
+            chromosomes,
+            arcs,
+            index,
+        }
+    }
+
+    /// The angle (clockwise from the top) of a position.
+    pub fn angle(&self, chrom: &str, pos: u64) -> Option<f64> {
+        let i = *self.index.get(chrom)?;
+        let (start, span) = self.arcs[i];
+        let len = self.chromosomes[i].1.max(1);
+        Some(start + span * pos.min(len) as f64 / len as f64)
+    }
+
+    pub fn point(center: (i32, i32), radius: f64, angle: f64) -> (i32, i32) {
+        (
+            center.0 + (radius * angle.sin()) as i32,
+            center.1 - (radius * angle.cos()) as i32,
+        )
+    }
+
+    /// A quadratic Bezier curve between two angles through the center.
+    pub fn chord(center: (i32, i32), radius: f64, a: f64, b: f64, n: usize) -> Vec<(i32, i32)> {
+        let p0 = CircosLayout::point(center, radius, a);
+        let p1 = CircosLayout::point(center, radius, b);
+        (0..=n)
+            .map(|i| {
+                let t = i as f64 / n.max(1) as f64;
+                let u = 1.0 - t;
+                (
+                    (u * u * p0.0 as f64 + 2.0 * u * t * center.0 as f64 + t * t * p1.0 as f64)
+                        as i32,
+                    (u * u * p0.1 as f64 + 2.0 * u * t * center.1 as f64 + t * t * p1.1 as f64)
+                        as i32,
+                )
+            })
+            .collect()
+    }
+
+    /// Draws chromosomes and chords of translocations weighted by supporting reads.
+    pub fn draw(
+        &self,
+        output: &str,
+        size: u32,
+        clusters: &[Cluster],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let root = BitMapBackend::new(output, (size, size)).into_drawing_area();
+        root.fill(&WHITE)?;
+        let center = (size as i32 / 2, size as i32 / 2);
+        let radius = size as f64 * 0.38;
+        let font = ("sans-serif", 12).into_font();
+
+        for (i, (name, _)) in self.chromosomes.iter().enumerate() {
+            let (start, span) = self.arcs[i];
+            let color = Palette99::pick(i);
+            let points: Vec<(i32, i32)> = (0..=64)
+                .map(|j| CircosLayout::point(center, radius + 6.0, start + span * j as f64 / 64.0))
+                .collect();
+            root.draw(&PathElement::new(points, color.stroke_width(8)))?;
+            let label = CircosLayout::point(center, radius + 24.0, start + span / 2.0);
+            root.draw(&Text::new(
+                name.clone(),
+                (label.0 - 4 * name.len() as i32, label.1 - 6),
+                font.clone(),
+            ))?;
+        }
+
+        let max_support = clusters.iter().map(|t| t.support()).max().unwrap_or(1);
+        for cluster in clusters.iter().filter(|t| t.junction.is_translocation()) {
+            let junction = &cluster.junction;
+            let (a, b) = match (
+                self.angle(&junction.first.chrom, junction.first.pos),
+                self.angle(&junction.second.chrom, junction.second.pos),
+            ) {
+                (Some(a), Some(b)) => (a, b),
+                _ => continue,
+            };
+            let color = Palette99::pick(self.index[&junction.first.chrom]);
+            let weight = cluster.support() as f64 / max_support as f64;
+            root.draw(&PathElement::new(
+                CircosLayout::chord(center, radius, a, b, 64),
+                color
+                    .mix(0.3 + 0.6 * weight)
+                    .stroke_width(1 + (weight * 5.0) as u32),
+            ))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CircosLayout;
+    use std::f64::consts::PI;
+
+    #[test]
+    fn layout() {
+        let layout = CircosLayout::new(
+            vec![("chr1".to_string(), 300), ("chr2".to_string(), 100)],
+            0.0,
+        );
+        assert_eq!(layout.angle("chr1", 0), Some(0.0));
+        assert!((layout.angle("chr1", 150).unwrap() - 0.75 * PI).abs() < 1e-9);
+        assert!((layout.angle("chr2", 100).unwrap() - 2.0 * PI).abs() < 1e-9);
+        assert_eq!(layout.angle("chr3", 0), None);
+        assert_eq!(CircosLayout::point((100, 100), 50.0, 0.0), (100, 50));
+        let chord = CircosLayout::chord((100, 100), 50.0, 0.0, PI, 2);
+        assert_eq!(chord, vec![(100, 50), (100, 100), (100, 150)]);
+    }
+}
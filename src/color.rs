@@ -16,6 +16,28 @@ pub enum VisColor {
     PosCol,
     NegCol,
     SplCol,
+    ClipCol,
+    SmallInsCol,
+    LargeInsCol,
+    RfCol,
+    FfCol,
+    RrCol,
+    MateChromCol,
+    HighlightCol,
+}
+
+/// Parses a color given as #rrggbb.
+pub fn parse_hex_color(s: &str) -> Option<RGBColor> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if hex.len() != 6 {
+        return None;
+    }
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    Some(RGBColor(
+        (value >> 16) as u8,
+        (value >> 8) as u8,
+        value as u8,
+    ))
 }
 
 fn f(color: &VisColor) -> usize {
@@ -90,6 +112,14 @@ impl Palette for HgbColor {
         (0xbf, 0x61, 0x6a),
         (0x81, 0xa1, 0xc1),
         (0x8f, 0xbc, 0xbb),
+        (0xeb, 0xcb, 0x8b),
+        (0x5e, 0x81, 0xac),
+        (0xd0, 0x87, 0x70),
+        (0xa3, 0xbe, 0x8c),
+        (0x88, 0xc0, 0xd0),
+        (0xb4, 0x8e, 0xad),
+        (0x4c, 0x56, 0x6a),
+        (0xff, 0xa5, 0x00),
     ];
 }
 
@@ -108,6 +138,14 @@ impl Palette for IgvColor {
         (230, 150, 150),
         (150, 150, 230),
         (120, 85, 43),
+        (255, 128, 0),
+        (0, 0, 220),
+        (200, 0, 0),
+        (0, 150, 0),
+        (0, 150, 150),
+        (20, 50, 200),
+        (150, 0, 150),
+        (255, 165, 0),
     ];
 }
 
@@ -126,6 +164,14 @@ impl Palette for JBrowseColor {
         (236, 139, 139), //#EC8B8B
         (143, 143, 216), //#8F8FD8
         (120, 85, 43),   //#Spl
+        (255, 152, 0),   //#ff9800
+        (0, 0, 255),     //#0000ff
+        (255, 0, 0),     //#ff0000
+        (0, 128, 0),     //#008000
+        (0, 128, 128),   //#008080
+        (75, 0, 130),    //#4b0082
+        (156, 39, 176),  //#9c27b0
+        (255, 165, 0),   //#ffa500
     ];
 }
 
@@ -142,3 +188,11 @@ impl Palette2 for JBrowseColor {}
 //PosCol,
 //NegCol,
 //SplCol,
+//ClipCol,
+//SmallInsCol,
+//LargeInsCol,
+//RfCol,
+//FfCol,
+//RrCol,
+//MateChromCol,
+//HighlightCol,
@@ -0,0 +1,333 @@
+use bam::Record;
+use serde_derive::Deserialize;
+use serde_yaml::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
+use std::str::FromStr;
+
+/// A figure described by a YAML file given to `vis --config`.
+///
+/// ```yaml
+/// ranges: [chr1:10000-20000]
+/// output: figure.png
+/// reference: hg38.2bit
+/// tracks:
+///   - type: bam
+///     path: tumor.bam
+///     label: Tumor
+///     color: "#bf616a"
+///     height: 30
+///     display: squished
+///     filters: {min-mapq: 20, min-read-length: 1000}
+///     group-by: HP
+///   - type: bed
+///     path: genes.bed
+/// options:
+///   coverage-plot: true
+/// ```
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    pub ranges: Vec<String>,
+    pub prefetch_ranges: Vec<String>,
+    pub output: Option<String>,
+    pub reference: Option<String>,
+    pub tracks: Vec<TrackConfig>,
+    /// Other options of `vis`, keyed by their long names.
+    pub options: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct TrackConfig {
+    /// One of bam, frequency, bed and gff3.
+    #[serde(rename = "type")]
+    pub track_type: String,
+    pub path: String,
+    pub label: Option<String>,
+    /// Read color of a bam track as #rrggbb.
+    pub color: Option<String>,
+    /// The maximal number of rows of a bam track.
+    pub height: Option<usize>,
+    /// Display mode of a bam track (expanded, squished or collapsed).
+    pub display: Option<String>,
+    pub filters: Option<TrackFilter>,
+    /// Tag name (or "strand") to split a bam track into groups.
+    pub group_by: Option<String>,
+}
+
+impl Config {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        serde_yaml::from_reader(File::open(path)?)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))
+    }
+
+    /// Converts the configuration into options of `vis`, which are parsed in the same way
+    /// as those given on the command line.
+    pub fn to_args(&self) -> io::Result<Vec<String>> {
+        let mut args = vec![];
+        // Empty values are given as bare flags, e.g. `--grouped-by-tag` for strands.
+        let mut push = |key: &str, value: &str| match value {
+            "" => args.push(format!("--{}", key)),
+            value => args.push(format!("--{}={}", key, value)),
+        };
+        for range in self.ranges.iter() {
+            push("range", range);
+        }
+        for range in self.prefetch_ranges.iter() {
+            push("prefetch-range", range);
+        }
+        if let Some(output) = &self.output {
+            push("output", output);
+        }
+        if let Some(reference) = &self.reference {
+            push("2bit", reference);
+        }
+
+        // Tracks are ordered as bam_vis assigns their ids.
+        let mut labels = vec![];
+        let mut group_by = None;
+        for track_type in ["bam", "frequency", "bed", "gff3"].iter() {
+            for track in self.tracks.iter().filter(|t| t.track_type == *track_type) {
+                push(track_type, &track.path);
+                labels.push(track.label.clone().unwrap_or_else(|| {
+                    Path::new(&track.path)
+                        .file_name()
+                        .map_or(track.path.clone(), |t| t.to_string_lossy().to_string())
+                }));
+                if let Some(tag) = &track.group_by {
+                    if *group_by.get_or_insert(tag) != tag {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            "All tracks must be grouped by the same tag",
+                        ));
+                    }
+                }
+            }
+        }
+        if let Some(track) = self
+            .tracks
+            .iter()
+            .find(|t| !["bam", "frequency", "bed", "gff3"].contains(&t.track_type.as_str()))
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unknown track type: {}", track.track_type),
+            ));
+        }
+        if self.tracks.iter().any(|t| t.label.is_some()) {
+            for label in labels.iter() {
+                push("labels", label);
+            }
+        }
+        if let Some(tag) = group_by {
+            push("grouped-by-tag", if tag == "strand" { "" } else { tag });
+        }
+
+        let bam_tracks: Vec<&TrackConfig> = self
+            .tracks
+            .iter()
+            .filter(|t| t.track_type == "bam")
+            .collect();
+        let join = |f: &dyn Fn(&TrackConfig) -> Option<String>, separator: &str| {
+            let values: Vec<Option<String>> = bam_tracks.iter().map(|t| f(t)).collect();
+            if values.iter().any(|t| t.is_some()) {
+                Some(
+                    values
+                        .into_iter()
+                        .map(|t| t.unwrap_or_default())
+                        .collect::<Vec<_>>()
+                        .join(separator),
+                )
+            } else {
+                None
+            }
+        };
+        if let Some(colors) = join(&|t| t.color.clone(), ",") {
+            push("track-colors", &colors);
+        }
+        if let Some(heights) = join(&|t| t.height.map(|t| t.to_string()), ",") {
+            push("track-heights", &heights);
+        }
+        if let Some(modes) = join(&|t| t.display.clone(), ",") {
+            push("display-mode", &modes);
+        }
+        if let Some(filters) = join(&|t| t.filters.as_ref().map(|t| t.to_string()), ";") {
+            push("track-filters", &filters);
+        }
+
+        for (key, value) in self.options.iter() {
+            match value {
+                Value::Bool(true) => args.push(format!("--{}", key)),
+                Value::Bool(false) | Value::Null => {}
+                Value::Sequence(values) => {
+                    for value in values.iter() {
+                        args.push(format!("--{}={}", key, value_to_string(value)?));
+                    }
+                }
+                value => args.push(format!("--{}={}", key, value_to_string(value)?)),
+            }
+        }
+        Ok(args)
+    }
+}
+
+fn value_to_string(value: &Value) -> io::Result<String> {
+    match value {
+        Value::String(t) => Ok(t.clone()),
+        Value::Number(t) => Ok(t.to_string()),
+        Value::Bool(t) => Ok(t.to_string()),
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Unsupported option value: {:?}", value),
+        )),
+    }
+}
+
+/// Read filters applied to each bam track, given as `min-mapq=20,min-read-length=1000,flag-exclude=1796`.
+#[derive(Debug, Default, Clone, Copy, Deserialize, PartialEq)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct TrackFilter {
+    pub min_mapq: Option<u8>,
+    pub min_read_length: Option<u32>,
+    pub flag_exclude: Option<u16>,
+}
+
+impl TrackFilter {
+    /// Parses filters of tracks separated by semicolons.
+    pub fn parse_list(s: &str) -> Result<Vec<Self>, String> {
+        s.split(';').map(|t| t.parse::<TrackFilter>()).collect()
+    }
+
+    pub fn is_match_values(&self, mapq: u8, read_length: u32, flag: u16) -> bool {
+        self.min_mapq.map_or(true, |t| mapq >= t)
+            && self.min_read_length.map_or(true, |t| read_length >= t)
+            && self.flag_exclude.map_or(true, |t| flag & t == 0)
+    }
+
+    pub fn is_match(&self, record: &Record) -> bool {
+        self.is_match_values(record.mapq(), record.query_len(), record.flag().0)
+    }
+}
+
+impl FromStr for TrackFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut filter = TrackFilter::default();
+        for item in s.split(',').filter(|t| !t.is_empty()) {
+            let mut kv = item.splitn(2, '=');
+            let (key, value) = (kv.next().unwrap_or(""), kv.next().unwrap_or(""));
+            let invalid = |_| format!("Invalid track filter: {}", item);
+            match key {
+                "min-mapq" => filter.min_mapq = Some(value.parse().map_err(invalid)?),
+                "min-read-length" => filter.min_read_length = Some(value.parse().map_err(invalid)?),
+                "flag-exclude" => filter.flag_exclude = Some(value.parse().map_err(invalid)?),
+                _ => return Err(format!("Unknown track filter: {}", item)),
+            }
+        }
+        Ok(filter)
+    }
+}
+
+impl fmt::Display for TrackFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut items = vec![];
+        if let Some(t) = self.min_mapq {
+            items.push(format!("min-mapq={}", t));
+        }
+        if let Some(t) = self.min_read_length {
+            items.push(format!("min-read-length={}", t));
+        }
+        if let Some(t) = self.flag_exclude {
+            items.push(format!("flag-exclude={}", t));
+        }
+        write!(f, "{}", items.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, TrackFilter};
+
+    #[test]
+    fn config_to_args() {
+        let config: Config = serde_yaml::from_str(
+            r##"
+ranges: ["chr1:100-200"]
+output: out.png
+tracks:
+  - type: bed
+    path: data/genes.bed
+  - type: bam
+    path: data/a.bam
+    label: A
+    color: "#ff0000"
+    display: squished
+    filters: {min-mapq: 20}
+  - type: bam
+    path: data/b.bam
+    height: 10
+    group-by: HP
+options:
+  coverage-plot: true
+  no-packing: false
+  max-coverage: 100
+"##,
+        )
+        .unwrap();
+        assert_eq!(
+            config.to_args().unwrap(),
+            vec![
+                "--range=chr1:100-200",
+                "--output=out.png",
+                "--bam=data/a.bam",
+                "--bam=data/b.bam",
+                "--bed=data/genes.bed",
+                "--labels=A",
+                "--labels=b.bam",
+                "--labels=genes.bed",
+                "--grouped-by-tag=HP",
+                "--track-colors=#ff0000,",
+                "--track-heights=,10",
+                "--display-mode=squished,",
+                "--track-filters=min-mapq=20;",
+                "--coverage-plot",
+                "--max-coverage=100",
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_config() {
+        assert!(serde_yaml::from_str::<Config>("trakcs: []").is_err());
+        let config: Config = serde_yaml::from_str("tracks: [{type: cram, path: a.cram}]").unwrap();
+        assert!(config.to_args().is_err());
+    }
+
+    #[test]
+    fn grouped_by_strand() {
+        let config: Config =
+            serde_yaml::from_str("tracks: [{type: bam, path: a.bam, group-by: strand}]").unwrap();
+        assert!(config
+            .to_args()
+            .unwrap()
+            .contains(&"--grouped-by-tag".to_string()));
+    }
+
+    #[test]
+    fn track_filter() {
+        let filters = TrackFilter::parse_list("min-mapq=20,flag-exclude=4;").unwrap();
+        assert_eq!(filters.len(), 2);
+        assert_eq!(filters[0].to_string(), "min-mapq=20,flag-exclude=4");
+        assert!(filters[0].is_match_values(30, 100, 0));
+        assert!(!filters[0].is_match_values(10, 100, 0));
+        assert!(!filters[0].is_match_values(30, 100, 4));
+        assert!(filters[1].is_match_values(0, 0, 4));
+        assert!("min-mapq=x".parse::<TrackFilter>().is_err());
+        assert!("mapq=1".parse::<TrackFilter>().is_err());
+    }
+}
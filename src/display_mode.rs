@@ -0,0 +1,259 @@
+use clap::ArgMatches;
+use std::io::{self, Error, ErrorKind};
+use std::str::FromStr;
+
+/// IGV-like display modes of alignment tracks.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DisplayMode {
+    /// Each read is drawn on its own row with details.
+    Expanded,
+    /// Rows are packed into thin slots without texts or alignment details.
+    Squished,
+    /// All reads are overlaid on a single row as a density.
+    Collapsed,
+}
+
+impl FromStr for DisplayMode {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "expanded" => Ok(DisplayMode::Expanded),
+            "squished" => Ok(DisplayMode::Squished),
+            "collapsed" => Ok(DisplayMode::Collapsed),
+            _ => Err("display mode must be one of expanded, squished and collapsed"),
+        }
+    }
+}
+
+/// Display modes and heights (the maximal number of rows) for each BAM input.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisplayModes {
+    modes: Vec<DisplayMode>,
+    heights: Vec<Option<usize>>,
+    /// The number of sample ids assigned to each BAM input (with `--separated-by-tag`).
+    interval: usize,
+}
+
+impl DisplayModes {
+    /// A single mode is applied to all inputs; otherwise the remaining inputs are expanded.
+    pub fn new(modes: Vec<DisplayMode>, interval: usize) -> Self {
+        DisplayModes {
+            modes,
+            heights: vec![],
+            interval: interval.max(1),
+        }
+    }
+
+    /// Limits the number of rows of each input in the same manner as modes.
+    pub fn with_heights(mut self, heights: Vec<Option<usize>>) -> Self {
+        self.heights = heights;
+        self
+    }
+
+    pub fn from_matches(matches: &ArgMatches) -> io::Result<Self> {
+        let modes = matches
+            .value_of("display-mode")
+            .map(|t| {
+                t.split(',')
+                    .map(|t| {
+                        t.parse::<DisplayMode>().map_err(|e| {
+                            Error::new(ErrorKind::InvalidInput, format!("{}: {}", e, t))
+                        })
+                    })
+                    .collect::<io::Result<Vec<_>>>()
+            })
+            .transpose()?
+            .unwrap_or_default();
+        let interval = if matches.occurrences_of("separated-by-tag") != 0 {
+            matches
+                .value_of("separated-by-tag-offset")
+                .and_then(|a| a.parse::<usize>().ok())
+                .unwrap_or(1)
+        } else {
+            1
+        };
+        let heights = matches
+            .value_of("track-heights")
+            .map(|t| t.split(',').map(|t| t.parse::<usize>().ok()).collect())
+            .unwrap_or_default();
+        Ok(DisplayModes::new(modes, interval).with_heights(heights))
+    }
+
+    /// The index of BAM input of the sample.
+    pub fn input_index(&self, sample_id: u64) -> usize {
+        sample_id as usize / self.interval
+    }
+
+    pub fn get(&self, sample_id: u64) -> DisplayMode {
+        if self.modes.len() == 1 {
+            self.modes[0]
+        } else {
+            self.modes
+                .get(self.input_index(sample_id))
+                .copied()
+                .unwrap_or(DisplayMode::Expanded)
+        }
+    }
+
+    pub fn height(&self, sample_id: u64) -> Option<usize> {
+        if self.heights.len() == 1 {
+            self.heights[0]
+        } else {
+            self.heights
+                .get(self.input_index(sample_id))
+                .copied()
+                .flatten()
+        }
+    }
+
+    /// Returns true if all tracks are expanded without limits, so that the layout is kept as is.
+    pub fn is_expanded(&self) -> bool {
+        self.modes.iter().all(|t| *t == DisplayMode::Expanded)
+            && self.heights.iter().all(|t| t.is_none())
+    }
+}
+
+/// Rows of alignment tracks after applying display modes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layout {
+    pub compressed_list: Vec<(u64, usize)>,
+    pub index_list: Vec<usize>,
+    /// The slot of each read within its row (always 0 unless squished).
+    pub slots: Vec<usize>,
+    pub prev_index: usize,
+    /// (first row, end row, first row after layout, mode, the number of rows shown) of each track.
+    tracks: Vec<(usize, usize, usize, DisplayMode, usize)>,
+    slots_per_row: usize,
+}
+
+impl Layout {
+    /// Converts rows given by packing (`index_list`, and `compressed_list` whose values are
+    /// cumulative end rows of each sample) into rows of each display mode.
+    pub fn new(
+        compressed_list: &[(u64, usize)],
+        index_list: &[usize],
+        prev_index: usize,
+        modes: &DisplayModes,
+        slots_per_row: usize,
+    ) -> Self {
+        let slots_per_row = slots_per_row.max(1);
+        let mut tracks = vec![];
+        let mut new_compressed_list = vec![];
+        let mut start = 0;
+        let mut new_start = 0;
+        for &(sample_id, end) in compressed_list.iter() {
+            let mode = modes.get(sample_id);
+            // Heights are given in rows after layout.
+            let limit = match (mode, modes.height(sample_id)) {
+                (DisplayMode::Expanded, Some(height)) => height,
+                (DisplayMode::Squished, Some(height)) => height * slots_per_row,
+                _ => std::usize::MAX,
+            };
+            let rows = end.saturating_sub(start).min(limit);
+            let new_rows = match mode {
+                DisplayMode::Expanded => rows,
+                DisplayMode::Squished => ((rows + slots_per_row - 1) / slots_per_row).max(1),
+                DisplayMode::Collapsed => 1,
+            };
+            tracks.push((start, end, new_start, mode, rows));
+            new_compressed_list.push((sample_id, new_start + new_rows));
+            start = end;
+            new_start += new_rows;
+        }
+        let mut layout = Layout {
+            compressed_list: new_compressed_list,
+            index_list: vec![],
+            slots: vec![],
+            prev_index: new_start + prev_index.saturating_sub(start),
+            tracks,
+            slots_per_row,
+        };
+        let (index_list, slots) = index_list.iter().map(|&t| layout.map_row(t)).unzip();
+        layout.index_list = index_list;
+        layout.slots = slots;
+        layout
+    }
+
+    /// Returns the row and the slot of a row given by packing. Hidden rows are kept as is,
+    /// and rows over the height of the track are hidden.
+    pub fn map_row(&self, row: usize) -> (usize, usize) {
+        match self.tracks.iter().find(|t| t.0 <= row && row < t.1) {
+            Some(&(start, _, _, _, shown)) if row - start >= shown => (std::u32::MAX as usize, 0),
+            Some(&(start, _, new_start, DisplayMode::Squished, _)) => (
+                new_start + (row - start) / self.slots_per_row,
+                (row - start) % self.slots_per_row,
+            ),
+            Some(&(_, _, new_start, DisplayMode::Collapsed, _)) => (new_start, 0),
+            Some(&(start, _, new_start, DisplayMode::Expanded, _)) => (new_start + row - start, 0),
+            None if row >= std::u32::MAX as usize => (row, 0),
+            None => {
+                let last = self.tracks.last().map_or(0, |t| t.1);
+                let new_last = self.compressed_list.last().map_or(0, |t| t.1);
+                ((row + new_last).saturating_sub(last), 0)
+            }
+        }
+    }
+
+    /// Returns true if the row given by packing belongs to an expanded track.
+    pub fn is_expanded_row(&self, row: usize) -> bool {
+        self.tracks
+            .iter()
+            .find(|t| t.0 <= row && row < t.1)
+            .map_or(true, |t| t.3 == DisplayMode::Expanded)
+    }
+
+    pub fn slots_per_row(&self) -> usize {
+        self.slots_per_row
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DisplayMode, DisplayModes, Layout};
+
+    #[test]
+    fn display_modes() {
+        let modes = DisplayModes::new(vec![DisplayMode::Squished], 1);
+        assert_eq!(modes.get(5), DisplayMode::Squished);
+        let modes = DisplayModes::new(vec![DisplayMode::Collapsed, DisplayMode::Squished], 2);
+        assert_eq!(modes.get(1), DisplayMode::Collapsed);
+        assert_eq!(modes.get(2), DisplayMode::Squished);
+        assert_eq!(modes.get(4), DisplayMode::Expanded);
+        assert!(!modes.is_expanded());
+        assert!(DisplayModes::new(vec![], 1).is_expanded());
+        assert!("dense".parse::<DisplayMode>().is_err());
+    }
+
+    #[test]
+    fn layout() {
+        // Sample 0 has rows 0..5, sample 1 has rows 5..9 and sample 2 has rows 9..12.
+        let compressed_list = vec![(0, 5), (1, 9), (2, 12)];
+        let index_list = vec![1, 2, 3, 4, 6, 8, 10, 11, std::u32::MAX as usize];
+        let modes = DisplayModes::new(
+            vec![
+                DisplayMode::Squished,
+                DisplayMode::Collapsed,
+                DisplayMode::Expanded,
+            ],
+            1,
+        );
+        let layout = Layout::new(&compressed_list, &index_list, 12, &modes, 2);
+        assert_eq!(layout.compressed_list, vec![(0, 3), (1, 4), (2, 7)]);
+        assert_eq!(
+            layout.index_list,
+            vec![0, 1, 1, 2, 3, 3, 5, 6, std::u32::MAX as usize]
+        );
+        assert_eq!(layout.slots, vec![1, 0, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(layout.prev_index, 7);
+        assert!(layout.is_expanded_row(10));
+        assert!(!layout.is_expanded_row(6));
+
+        // Only one row of sample 2 is shown.
+        let modes = modes.with_heights(vec![None, None, Some(1)]);
+        let layout = Layout::new(&compressed_list, &index_list, 12, &modes, 2);
+        assert_eq!(layout.compressed_list, vec![(0, 3), (1, 4), (2, 5)]);
+        assert_eq!(layout.index_list[6..8], [std::u32::MAX as usize; 2]);
+        assert_eq!(layout.prev_index, 5);
+    }
+}
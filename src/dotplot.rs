@@ -0,0 +1,130 @@
+use crate::breakpoint::Segment;
+use crate::color::{ColorSet, VisColor};
+use plotters::prelude::*;
+
+/// Alignments of a read plotted on reference (x) against query (y) coordinates.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DotPlot {
+    name: String,
+    segments: Vec<Segment>,
+    /// (chromosome, start, end, offset on the x axis) of each panel.
+    panels: Vec<(String, u64, u64, u64)>,
+}
+
+impl DotPlot {
+    /// Each chromosome is a panel spanning its alignments with `margin` bp on both sides.
+    pub fn new(name: &str, segments: Vec<Segment>, margin: u64) -> Self {
+        let mut panels: Vec<(String, u64, u64, u64)> = vec![];
+        for segment in segments.iter() {
+            match panels.iter_mut().find(|t| t.0 == segment.chrom) {
+                Some(panel) => {
+                    panel.1 = panel.1.min(segment.start.saturating_sub(margin));
+                    panel.2 = panel.2.max(segment.end + margin);
+                }
+                None => panels.push((
+                    segment.chrom.clone(),
+                    segment.start.saturating_sub(margin),
+                    segment.end + margin,
+                    0,
+                )),
+            }
+        }
+        let mut offset = 0;
+        for panel in panels.iter_mut() {
+            panel.3 = offset;
+            offset += panel.2 - panel.1;
+        }
+        DotPlot {
+            name: name.to_string(),
+            segments,
+            panels,
+        }
+    }
+
+    /// The position on the x axis.
+    pub fn x(&self, chrom: &str, pos: u64) -> Option<u64> {
+        self.panels
+            .iter()
+            .find(|t| t.0 == chrom)
+            .map(|t| t.3 + pos.saturating_sub(t.1))
+    }
+
+    /// The chromosome and the position of a position on the x axis.
+    pub fn position(&self, x: u64) -> Option<(&str, u64)> {
+        self.panels
+            .iter()
+            .find(|t| x >= t.3 && x < t.3 + t.2 - t.1)
+            .map(|t| (t.0.as_str(), t.1 + x - t.3))
+    }
+
+    pub fn draw(
+        &self,
+        output: &str,
+        size: (u32, u32),
+        preset_color: &ColorSet,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let root = BitMapBackend::new(output, size).into_drawing_area();
+        root.fill(&WHITE)?;
+        let x_max = self.panels.last().map_or(1, |t| t.3 + t.2 - t.1);
+        let y_max = self.segments.iter().map(|t| t.query_len).max().unwrap_or(1) as u64;
+        let mut chart = ChartBuilder::on(&root)
+            .caption(&self.name, ("sans-serif", 16).into_font())
+            .margin(10)
+            .x_label_area_size(30)
+            .y_label_area_size(60)
+            .build_cartesian_2d(0..x_max, 0..y_max)?;
+        chart
+            .configure_mesh()
+            .x_labels(6)
+            .x_label_style(("sans-serif", 12).into_font())
+            .y_label_style(("sans-serif", 12).into_font())
+            .x_label_formatter(&|x| {
+                self.position(*x)
+                    .map_or(String::new(), |(chrom, pos)| format!("{}:{}", chrom, pos))
+            })
+            .y_desc("Read position")
+            .draw()?;
+
+        for panel in self.panels.iter().skip(1) {
+            chart.draw_series(LineSeries::new(
+                vec![(panel.3, 0), (panel.3, y_max)],
+                BLACK.stroke_width(1),
+            ))?;
+        }
+        for segment in self.segments.iter() {
+            let color = preset_color.pick(if segment.reverse {
+                VisColor::NegCol
+            } else {
+                VisColor::PosCol
+            });
+            let x = |pos: u64| self.x(&segment.chrom, pos).unwrap_or(0);
+            chart.draw_series(segment.blocks().into_iter().map(|(a, b)| {
+                PathElement::new(
+                    vec![(x(a.1), a.0 as u64), (x(b.1), b.0 as u64)],
+                    color.stroke_width(2),
+                )
+            }))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DotPlot;
+    use crate::breakpoint::Segment;
+
+    #[test]
+    fn panels() {
+        let segments = vec![
+            Segment::from_cigar("chr1", 1000, false, "50M50S", 60).unwrap(),
+            Segment::from_cigar("chr2", 5000, true, "50M50S", 60).unwrap(),
+            Segment::from_cigar("chr1", 900, false, "10S20M70S", 60).unwrap(),
+        ];
+        let plot = DotPlot::new("read", segments, 100);
+        assert_eq!(plot.x("chr1", 800), Some(0));
+        assert_eq!(plot.x("chr2", 4900), Some(350));
+        assert_eq!(plot.position(350), Some(("chr2", 4900)));
+        assert_eq!(plot.position(600), None);
+    }
+}
@@ -0,0 +1,135 @@
+use crate::hash::{fnv1a, fnv1a_with};
+use clap::ArgMatches;
+use std::collections::{HashMap, HashSet};
+
+/// Deterministic downsampling: in each window, only the reads with the smallest
+/// seeded hash of their names are kept. A name dropped in any window is dropped in all
+/// windows, so that mates and split alignments sharing the name are kept or dropped together.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Downsampler {
+    /// The maximal number of reads starting in a window.
+    pub depth: usize,
+    pub window: u64,
+    pub seed: u64,
+}
+
+impl Downsampler {
+    pub fn new(depth: usize, window: u64, seed: u64) -> Self {
+        Downsampler {
+            depth,
+            window: window.max(1),
+            seed,
+        }
+    }
+
+    /// Returns None unless `--downsample` is given.
+    pub fn from_matches(matches: &ArgMatches) -> Option<Self> {
+        let depth = matches
+            .value_of("downsample")
+            .and_then(|a| a.parse::<usize>().ok())?;
+        let window = matches
+            .value_of("downsample-window")
+            .and_then(|a| a.parse::<u64>().ok())
+            .unwrap_or(50);
+        let seed = matches
+            .value_of("downsample-seed")
+            .and_then(|a| a.parse::<u64>().ok())
+            .unwrap_or(0);
+        Some(Downsampler::new(depth, window, seed))
+    }
+
+    pub fn hash(&self, name: &[u8]) -> u64 {
+        fnv1a_with(fnv1a(&self.seed.to_le_bytes()), name)
+    }
+
+    /// Takes (sample id, start, read name) of reads, and returns the ones to be dropped.
+    pub fn dropped<'a, I>(&self, reads: I) -> HashSet<(u64, i32, &'a [u8])>
+    where
+        I: Iterator<Item = (u64, i32, &'a [u8])>,
+    {
+        let mut windows = HashMap::new();
+        for read in reads {
+            windows
+                .entry((read.0, read.1.max(0) as u64 / self.window))
+                .or_insert_with(Vec::new)
+                .push((self.hash(read.2), read));
+        }
+        let mut dropped_names = HashSet::new();
+        for reads in windows.values_mut() {
+            if reads.len() <= self.depth {
+                continue;
+            }
+            reads.sort_by(|a, b| a.0.cmp(&b.0).then((a.1).2.cmp((b.1).2)));
+            dropped_names.extend(reads.iter().skip(self.depth).map(|t| ((t.1).0, (t.1).2)));
+        }
+        windows
+            .into_values()
+            .flat_map(|reads| reads.into_iter().map(|t| t.1))
+            .filter(|t| dropped_names.contains(&(t.0, t.2)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Downsampler;
+
+    #[test]
+    fn downsample_is_deterministic() {
+        let names: Vec<Vec<u8>> = (0..100)
+            .map(|i| format!("read{}", i).into_bytes())
+            .collect();
+        let reads = || {
+            names
+                .iter()
+                .enumerate()
+                .map(|(i, name)| (0u64, i as i32, name.as_slice()))
+        };
+        let downsampler = Downsampler::new(10, 50, 1);
+        let dropped = downsampler.dropped(reads());
+        // 10 reads are kept for each of two windows.
+        assert_eq!(dropped.len(), 80);
+        assert_eq!(dropped, downsampler.dropped(reads().rev()));
+        assert_ne!(dropped, Downsampler::new(10, 50, 2).dropped(reads()));
+        assert!(Downsampler::new(50, 50, 1).dropped(reads()).is_empty());
+    }
+
+    #[test]
+    fn mates_are_kept_together() {
+        // The windows share only "c", which is kept or dropped on both of them.
+        let reads = vec![
+            (0u64, 0, &b"a"[..]),
+            (0, 1, &b"b"[..]),
+            (0, 2, &b"c"[..]),
+            (0, 10, &b"c"[..]),
+            (0, 11, &b"d"[..]),
+            (0, 12, &b"e"[..]),
+        ];
+        let downsampler = Downsampler::new(2, 10, 0);
+        let mut hashes: Vec<_> = [&b"c"[..], b"d", b"e"]
+            .iter()
+            .map(|t| (downsampler.hash(t), *t))
+            .collect();
+        hashes.sort();
+        let dropped = downsampler.dropped(reads.clone().into_iter());
+        for read in reads.iter() {
+            let is_dropped = dropped.contains(read);
+            assert_eq!(
+                is_dropped,
+                reads
+                    .iter()
+                    .filter(|t| t.2 == read.2)
+                    .all(|t| dropped.contains(t))
+            );
+        }
+        // At most 2 reads are kept on each window.
+        assert!(reads[..3].iter().filter(|t| !dropped.contains(t)).count() <= 2);
+        assert!(reads[3..].iter().filter(|t| !dropped.contains(t)).count() <= 2);
+        // The largest hash on the second window is dropped, with its read on the first window.
+        let largest = hashes[2].1;
+        assert!(reads
+            .iter()
+            .filter(|t| t.2 == largest)
+            .all(|t| dropped.contains(t)));
+    }
+}
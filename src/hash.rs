@@ -0,0 +1,29 @@
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// 64-bit FNV-1a hash, which is stable across runs and platforms.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    fnv1a_with(FNV_OFFSET_BASIS, bytes)
+}
+
+/// Continues FNV-1a from the given hash, to hash data given in pieces.
+pub fn fnv1a_with(mut hash: u64, bytes: &[u8]) -> u64 {
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fnv1a, fnv1a_with};
+
+    #[test]
+    fn fnv1a_works() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x8594_4171_f739_67e8);
+        assert_eq!(fnv1a_with(fnv1a(b"foo"), b"bar"), fnv1a(b"foobar"));
+    }
+}
@@ -115,6 +115,10 @@ impl Header {
     pub fn reference_names(&self) -> &[String] {
         &self.global_header.reference_names()
     }
+    /// Returns reference lengths.
+    pub fn reference_lengths(&self) -> &[u32] {
+        self.global_header.reference_lengths()
+    }
     ///
     pub fn to_tsv<W: Write>(&self, stream: &mut W) -> Result<()> {
         for (name, len) in self
@@ -171,6 +175,10 @@ impl Header {
     pub fn get_name(&self, index: usize) -> Option<&String> {
         self.names.get(index)
     }
+    /// Returns the number of samples.
+    pub fn n_samples(&self) -> usize {
+        self.headers.len().max(self.names.len())
+    }
     pub fn get_local_bam_header(&self, index: usize) -> Option<&bam::Header> {
         self.headers.get(index).and_then(|f| match &f {
             HeaderType::None => None,
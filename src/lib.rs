@@ -14,22 +14,40 @@ extern crate serde_json;
 extern crate twobit;
 
 //pub mod alignment;
+pub mod allele;
+pub mod api;
+pub mod auth;
 pub mod bed;
 pub mod binary;
 //pub mod buffer;
+pub mod breakpoint;
 pub mod builder;
+pub mod cache;
+pub mod catalog;
 pub mod checker_index;
+pub mod circos;
 pub mod color;
 pub mod compression;
+pub mod config;
+pub mod display_mode;
+pub mod dotplot;
+pub mod downsample;
 pub mod dump;
 pub mod gff;
+pub mod hash;
 pub mod header;
 pub mod index;
+pub mod overview;
+pub mod pair;
 //pub mod server;
 pub mod range;
+pub mod read_selection;
 pub mod reader;
+pub mod reference;
+pub mod session;
 pub mod simple_bam_buffer;
 pub mod simple_buffer;
+pub mod snapshot;
 
 pub mod twopass_alignment;
 pub mod vis;
@@ -172,7 +190,10 @@ pub trait ChromosomeBufferTrait {
         string_range: &StringRegion,
         list: &mut Vec<(u64, bam::Record)>,
         _list_btree: &mut ReadBuffer,
-    ) -> Option<Vis>;
+    ) -> std::io::Result<Vis>;
+
+    fn references(&self) -> Vec<api::ApiReference>;
+    fn samples(&self) -> Vec<api::ApiSample>;
 }
 
 /// Visualization Presets
@@ -8,21 +8,23 @@ pub mod buffered_server;
 pub mod rest_server;
 #[cfg(feature = "web")]
 pub mod server;
+#[cfg(feature = "web")]
+pub mod xyz;
 
 //#[cfg(feature = "web")]
 //pub mod bam_server;
 
 pub mod subcommands;
 
-use clap::{App, AppSettings, Arg, ArgSettings};
+use clap::{App, AppSettings, Arg, ArgMatches, ArgSettings};
+use ghi::config::Config;
+use ghi::session::{remove_option, Session};
 
 use std::env;
 use subcommands::*;
 
-fn main() {
-    //    env::set_var("RUST_LOG", "actix_web=info");
-    env_logger::init();
-    let app = App::new("A hybrid genomic data visualization tool")
+fn build_app() -> App<'static> {
+    App::new("A hybrid genomic data visualization tool")
         // .setting(AppSettings::ArgsNegateSubcommands)
         .setting(AppSettings::SubcommandRequiredElseHelp)
         .setting(AppSettings::UnifiedHelpMessage)
@@ -292,9 +294,27 @@ fn main() {
                         .long("range")
                         .takes_value(true)
                         .multiple(true)
-                        .required(true)
+                        .required_unless_present_any(&["config", "session", "genome-overview", "catalog"])
                         .about("Genomic range to visualize. Format is chr:from-to"),
                 )
+                .arg(
+                    Arg::new("config")
+                        .long("config")
+                        .takes_value(true)
+                        .about("YAML file describing tracks (type, path, label, color, height, display, filters and group-by), ranges and other options"),
+                )
+                .arg(
+                    Arg::new("session")
+                        .long("session")
+                        .takes_value(true)
+                        .about("Session file to regenerate the view recorded by --write-session (other options are ignored)"),
+                )
+                .arg(
+                    Arg::new("write-session")
+                        .long("write-session")
+                        .takes_value(true)
+                        .about("[Output] Writes a session file recording input paths and checksums, ranges, all options and the version of hgb"),
+                )
                 .arg(
                     Arg::new("prefetch-range")
                         .long("prefetch-range")
@@ -425,8 +445,40 @@ fn main() {
                 .arg(Arg::new("production").short('$').long("serve-as-production").about("Serves a web server on production mode (no cross-origin request is allowed)"))
                 .arg(Arg::new("dump-json").short('%').long("write-json").about("Dumps JSON of read metadata"))
                 .arg(Arg::new("adjust-y").short('&').long("not-adjust-y").about("Do not adjust y parameter on server mode"))
-                .arg(Arg::new("ref-column").short('!').long("2bit").takes_value(true).about("[Input] 2 bit file of reference genome to display the base colors of reference genome"))
+                .arg(Arg::new("ref-column").short('!').long("2bit").takes_value(true).about("[Input] Reference genome (2bit, or FASTA indexed with .fai; bgzipped FASTA also needs .gzi) to display the base colors of reference genome and mismatches without MD tags"))
                 .arg(Arg::new("insertion-string").short('{').long("show-insertion-sequence").about("Shows insertion sequences along with insertion symbols"))
+                .arg(Arg::new("allele-count").long("allele-count-track").takes_value(true).about("Shows per-base allele counts on each coverage track, flagging positions whose non-reference fraction exceeds the given value (requires a reference to flag)"))
+                .arg(Arg::new("allele-count-output").long("write-allele-counts").takes_value(true).requires("allele-count").about("[Output] Writes allele counts of the allele count track as a tab-separated file"))
+                .arg(Arg::new("soft-clip").long("show-soft-clips").about("Shows soft-clipped sequences as faded extensions of read alignments (mismatches are colored if the reference is given)"))
+                .arg(Arg::new("view-as-pairs").long("view-as-pairs").about("Displays mates of paired-end reads on the same line connected by a line, colored by insert size and orientation"))
+                .arg(Arg::new("insert-size-range").long("insert-size-range").takes_value(true).about("Expected insert sizes as min:max on --view-as-pairs (inferred from 0.5 and 99.5 percentiles if not given)"))
+                .arg(Arg::new("pair-orientation").long("pair-orientation").takes_value(true).possible_values(&["FR", "RF", "FF", "RR"]).about("Expected orientation of mates on --view-as-pairs [default: FR]"))
+                .arg(Arg::new("downsample").long("downsample").takes_value(true).about("Downsamples reads deterministically to at most the given number of reads starting in each window (coverage is computed from all reads)"))
+                .arg(Arg::new("downsample-window").long("downsample-window").takes_value(true).about("Window size in bp for --downsample [default: 50]"))
+                .arg(Arg::new("downsample-seed").long("downsample-seed").takes_value(true).about("Seed of the read-name hash for --downsample [default: 0]"))
+                .arg(Arg::new("display-mode").long("display-mode").takes_value(true).about("Sets display modes of BAM inputs: expanded, squished (thin rows without details) or collapsed (a single density row); comma-separated in the order of inputs, or a single mode for all"))
+                .arg(Arg::new("circos").long("circos").takes_value(true).about("[Output] Draws a circos plot of translocations, clustering breakpoints of split alignments of loaded reads and weighting chords by supporting reads"))
+                .arg(Arg::new("breakpoint-tolerance").long("breakpoint-tolerance").takes_value(true).about("Distance in bp to cluster breakpoints of split alignments [default: 100]"))
+                .arg(Arg::new("dotplot").long("dotplot").requires("read-name").about("Draws a dot plot of all alignments of the read given by --read-name (the primary and SA entries) on reference against read coordinates"))
+                .arg(Arg::new("breakpoint-view").long("breakpoint-view").about("Shows each split read on one row across all ranges (e.g. on different chromosomes) and links its pieces to the panels of their neighbors"))
+                .arg(Arg::new("highlight-reads").long("highlight-reads").takes_value(true).about("Outlines reads whose names are listed in the file (one name per line)"))
+                .arg(Arg::new("highlight-read-names").long("highlight-read-names").takes_value(true).about("Outlines reads whose names are given as a comma-separated list"))
+                .arg(Arg::new("only-reads").long("only-reads").takes_value(true).about("Shows only reads whose names are listed in the file (one name per line)"))
+                .arg(Arg::new("only-read-names").long("only-read-names").takes_value(true).about("Shows only reads whose names are given as a comma-separated list"))
+                .arg(Arg::new("catalog").long("catalog").takes_value(true).requires("rest").about("[Input] Serves GHB/BAM datasets at /datasets/<id> on the REST server (--rest-server only; not supported by --web); a directory of the files (ids are file stems) or a YAML file listing ids and paths"))
+                .arg(Arg::new("max-open-datasets").long("max-open-datasets").takes_value(true).about("The maximal number of datasets kept open on --catalog, closing the least recently used one [default: 8]"))
+                .arg(Arg::new("tiles").long("tiles").takes_value(true).requires("whole-chromosome").about("Renders tiles of the zoomable web server (-W) into the directory with genome.dzi and exits (see the tiles subcommand)"))
+                .arg(Arg::new("tile-zoom").long("tile-zoom").takes_value(true).about("Zoom levels to render on --tiles as min:max or a single level [default: all levels]"))
+                .arg(Arg::new("tile-range").long("tile-range").takes_value(true).multiple(true).about("Renders only tiles overlapping the genomic ranges on --tiles"))
+                .arg(Arg::new("auth-file").long("auth-file").takes_value(true).about("Authenticates users by Argon2 hashes of their passwords and API tokens by SHA-256 digests in a YAML file for the web server, optionally restricting datasets on --catalog (overrides --basic-auth)"))
+                .arg(Arg::new("tls-cert").long("tls-cert").takes_value(true).requires("tls-key").about("Serves HTTPS on the web server with the certificate chain in PEM"))
+                .arg(Arg::new("tls-key").long("tls-key").takes_value(true).requires("tls-cert").about("Private key in PEM (PKCS #8, RSA or EC) of --tls-cert"))
+                .arg(Arg::new("genome-overview").long("genome-overview").about("Draws binned coverage over all chromosomes with ideograms and markers of translocational split alignments"))
+                .arg(Arg::new("cytoband").long("cytoband").takes_value(true).about("[Input] Cytoband BED (UCSC cytoBand) to draw ideograms on --genome-overview; only chromosomes in the file are drawn"))
+                .arg(Arg::new("overview-bin-size").long("overview-bin-size").takes_value(true).about("Bin size in bp of coverage on --genome-overview [default: 1000000]"))
+                .arg(Arg::new("track-colors").long("track-colors").takes_value(true).about("Sets read colors of BAM inputs as #rrggbb; comma-separated in the order of inputs (empty to keep the default)"))
+                .arg(Arg::new("track-heights").long("track-heights").takes_value(true).about("Sets the maximal number of rows of BAM inputs; comma-separated in the order of inputs (empty for no limit)"))
+                .arg(Arg::new("track-filters").long("track-filters").takes_value(true).about("Sets read filters of BAM inputs, e.g. min-mapq=20,min-read-length=1000,flag-exclude=1796; semicolon-separated in the order of inputs"))
                 .arg(
                     Arg::new("labels")
                         .short('}')
@@ -488,8 +540,10 @@ fn main() {
                         .short('d')
                         .long("cache-directory")
                         .takes_value(true)
-                        .about("Cache directory for web server (generated randomly if not specified)"),
+                        .about("Cache directory for web server (the REST server reuses a directory of its address and inputs under the cache directory of the user, and the DZI server generates one randomly, if not specified)"),
                 )
+                .arg(Arg::new("cache-size").long("cache-size").takes_value(true).about("The maximal size in MB of files cached by the REST server, evicting the oldest ones [default: 1024]"))
+                .arg(Arg::new("cache-age").long("cache-age").takes_value(true).about("Seconds to keep files cached by the REST server (kept until evicted by --cache-size if not given)"))
                 .arg(
                     Arg::new("static-dir")
                         .short('.')
@@ -548,7 +602,7 @@ fn main() {
                 .arg(Arg::new("with-caption").short('<').long("caption").takes_value(true).setting(ArgSettings::AllowEmptyValues)
                 .default_value("").about("Caption on the top of chart"))
                 .arg(Arg::new("end-split-callets").short('e').long("show-split-alignment-callets").about("Shows symbols on ends of read alignments if the read contains split-alignment"))
-                .arg(Arg::new("output-translocation").short('5').long("write-split-alignment").about("Writes a list of translocation split-alignment to stdout"))
+                .arg(Arg::new("output-translocation").short('5').long("write-split-alignment").about("Writes a list of split-alignment ends to stdout (see the breakpoints subcommand for BEDPE/VCF)"))
                 .arg(Arg::new("translocation-target").short('6').long("translocation-target-chromosome").takes_value(true).about("Target chromosome on writing a list of translocation"))
                 .arg(
                     Arg::new("graph")
@@ -583,8 +637,9 @@ fn main() {
                     Arg::new("read-name")
                         .short(')')
                         .long("filtered-by-read-name")
+                        .alias("read-name")
                         .takes_value(true)
-                        .about("Read name to filter by"),
+                        .about("Shows only the read with the name"),
                 )
                 .arg(
                     Arg::new("output")
@@ -593,6 +648,12 @@ fn main() {
                         .takes_value(true)
                         .about("[Output] Image file (prefixed as .bmp / .png)"),
                 )
+                .arg(
+                    Arg::new("output-dir")
+                        .long("output-dir")
+                        .takes_value(true)
+                        .about("[Output] Directory to write an image per region of --bed-range (named after the name column) in parallel, with index.html of thumbnails"),
+                )
                 .arg(
                     Arg::new("bed")
                         .short('L')
@@ -623,6 +684,111 @@ fn main() {
                         .index(1),
                 ),
         )
+        .subcommand(
+            App::new("tiles")
+                .setting(AppSettings::ArgRequiredElseHelp)
+                .setting(AppSettings::ColoredHelp)
+                .about("Pre-renders the tile pyramid of the zoomable web server (vis --web -W) with genome.dzi into a directory")
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .takes_value(true)
+                        .required(true)
+                        .about("[Output] Directory of tiles, served by vis --web -W with the same options and -d, or by a static file host"),
+                )
+                .arg(
+                    Arg::new("zoom")
+                        .short('z')
+                        .long("zoom")
+                        .takes_value(true)
+                        .about("Zoom levels to render as min:max or a single level [default: all levels]"),
+                )
+                .arg(
+                    Arg::new("range")
+                        .short('r')
+                        .long("range")
+                        .takes_value(true)
+                        .multiple(true)
+                        .about("Renders only tiles overlapping the genomic ranges [default: all tiles]"),
+                )
+                .arg(
+                    Arg::new("VIS")
+                        .about("Options of vis after --, which should include -W, e.g. -- -a input.bam -r chr1:1-100000 -W")
+                        .required(true)
+                        .multiple(true)
+                        .last(true),
+                ),
+        )
+        .subcommand(
+            App::new("breakpoints")
+                .about("Extracts breakpoints of split alignments, clusters them and writes BEDPE or VCF")
+                .arg(
+                    Arg::new("INPUT")
+                        .about("Sets the input BAM files to use")
+                        .required(true)
+                        .multiple(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("range")
+                        .short('r')
+                        .long("range")
+                        .takes_value(true)
+                        .multiple(true)
+                        .about("Ranges to scan [default: all chromosomes]"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .short('f')
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["bedpe", "vcf"])
+                        .about("Output format [default: bedpe]"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .takes_value(true)
+                        .about("Output file [default: stdout]"),
+                )
+                .arg(
+                    Arg::new("tolerance")
+                        .long("tolerance")
+                        .takes_value(true)
+                        .about("Distance in bp to cluster breakpoints [default: 100]"),
+                )
+                .arg(
+                    Arg::new("min-support")
+                        .long("min-support")
+                        .takes_value(true)
+                        .about("Minimal number of supporting reads [default: 1]"),
+                )
+                .arg(
+                    Arg::new("min-mapq")
+                        .long("min-mapq")
+                        .takes_value(true)
+                        .about("Minimal mapping quality of both alignments of a junction [default: 0]"),
+                )
+                .arg(
+                    Arg::new("no-bits")
+                        .long("no-bits")
+                        .takes_value(true)
+                        .about("Excludes reads with any of the flag bits [default: 1796]"),
+                )
+                .arg(
+                    Arg::new("translocation-only")
+                        .long("translocation-only")
+                        .about("Outputs only junctions between different chromosomes"),
+                )
+                .arg(
+                    Arg::new("target-chromosome")
+                        .long("target-chromosome")
+                        .takes_value(true)
+                        .about("Outputs only junctions with a side on the chromosome"),
+                ),
+        )
         .subcommand(
             App::new("precomp")
                 .about("Attach y positions on each read by default view options.")
@@ -640,9 +806,78 @@ fn main() {
                 )
                 .arg(Arg::new("header").short('z').about("Outputs only header"))
                 .arg(Arg::new("formatted-header").short('f').about("Outputs formatted header")),
-        );
-    let matches = app.get_matches();
-    let args: Vec<String> = env::args().collect();
+        )
+}
+
+/// The vis command line to render tiles, replacing `tiles` and its options.
+fn tiles_args(args: &[String], matches: &ArgMatches) -> Vec<String> {
+    let index = args.iter().position(|t| t == "tiles").unwrap_or(args.len());
+    let mut vis_args = args[..index].to_vec();
+    vis_args.push("vis".to_string());
+    vis_args.extend(
+        matches
+            .values_of("VIS")
+            .into_iter()
+            .flatten()
+            .map(|t| t.to_string()),
+    );
+    vis_args.push("--tiles".to_string());
+    vis_args.push(matches.value_of("output").unwrap().to_string());
+    if let Some(zoom) = matches.value_of("zoom") {
+        vis_args.push("--tile-zoom".to_string());
+        vis_args.push(zoom.to_string());
+    }
+    for range in matches.values_of("range").into_iter().flatten() {
+        vis_args.push("--tile-range".to_string());
+        vis_args.push(range.to_string());
+    }
+    vis_args
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    //    env::set_var("RUST_LOG", "actix_web=info");
+    env_logger::init();
+    let mut matches = build_app().get_matches();
+    let mut args: Vec<String> = env::args().collect();
+    if let Some(tiles_matches) = matches.subcommand_matches("tiles") {
+        // Tiles are rendered by vis with the given options, which are parsed again.
+        args = tiles_args(&args, tiles_matches);
+        matches = build_app().get_matches_from(&args);
+    }
+    if let Some(path) = matches
+        .subcommand_matches("vis")
+        .and_then(|t| t.value_of("session"))
+    {
+        // The command line is replaced with the one recorded on the session.
+        let session =
+            Session::from_path(path).map_err(|e| format!("Failed to load {}: {}", path, e))?;
+        for warning in session.verify() {
+            eprintln!("Warning: {}", warning);
+        }
+        args.truncate(1);
+        args.extend(session.args);
+        matches = build_app().get_matches_from(&args);
+    }
+    if let Some(path) = matches
+        .subcommand_matches("vis")
+        .and_then(|t| t.value_of("config"))
+    {
+        // Options in the configuration file are appended to the command line and parsed again.
+        let config = Config::from_path(path)
+            .and_then(|t| t.to_args())
+            .map_err(|e| format!("Failed to load {}: {}", path, e))?;
+        args = remove_option(args, "", "--config", true);
+        args.extend(config);
+        matches = build_app().get_matches_from(&args);
+    }
+    if let Some(vis_matches) = matches.subcommand_matches("vis") {
+        if let Some(path) = vis_matches.value_of("write-session") {
+            args = remove_option(args, "", "--write-session", true);
+            Session::new(args[1..].to_vec(), vis_matches)
+                .and_then(|t| t.write(path))
+                .map_err(|e| format!("Failed to write {}: {}", path, e))?;
+        }
+    }
     let threads = matches
         .value_of("threads")
         .and_then(|t| t.parse::<u16>().ok())
@@ -669,12 +904,75 @@ fn main() {
         split(matches, threads);
     } else if let Some(ref matches) = matches.subcommand_matches("bin") {
         bin(matches, threads);
+    } else if let Some(ref matches) = matches.subcommand_matches("breakpoints") {
+        breakpoints(matches, threads)?;
     } else if let Some(ref matches) = matches.subcommand_matches("vis") {
         // eprintln!("{:?}", matches.is_present("INPUT"));
         match matches.is_present("INPUT") {
-            true => vis_query(matches, args, threads).unwrap(),
-            false => bam_vis(matches, args, threads).unwrap(),
+            true => vis_query(matches, args, threads)?,
+            false => bam_vis(matches, args, threads)?,
         }
     } else if let Some(ref _matches) = matches.subcommand_matches("server") {
     }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_app, tiles_args};
+
+    #[test]
+    fn tiles_command_line() {
+        let args: Vec<String> = [
+            "hgb",
+            "-t",
+            "4",
+            "tiles",
+            "-o",
+            "out",
+            "-z",
+            "12:14",
+            "-r",
+            "chr1:1-100",
+            "--",
+            "-a",
+            "a.bam",
+            "-r",
+            "chr1:1-100",
+            "-W",
+        ]
+        .iter()
+        .map(|t| t.to_string())
+        .collect();
+        let matches = build_app().get_matches_from(&args);
+        let vis_args = tiles_args(&args, matches.subcommand_matches("tiles").unwrap());
+        assert_eq!(
+            vis_args,
+            [
+                "hgb",
+                "-t",
+                "4",
+                "vis",
+                "-a",
+                "a.bam",
+                "-r",
+                "chr1:1-100",
+                "-W",
+                "--tiles",
+                "out",
+                "--tile-zoom",
+                "12:14",
+                "--tile-range",
+                "chr1:1-100"
+            ]
+        );
+        let matches = build_app().get_matches_from(&vis_args);
+        let vis = matches.subcommand_matches("vis").unwrap();
+        assert_eq!(vis.value_of("tiles"), Some("out"));
+        assert_eq!(vis.value_of("tile-zoom"), Some("12:14"));
+        // Tiles are laid out for the buffered server only.
+        let mut args = vis_args;
+        args.retain(|t| t != "-W");
+        assert!(build_app().try_get_matches_from(&args).is_err());
+    }
 }
@@ -0,0 +1,280 @@
+use crate::bed;
+use bam::record::tags::TagValue;
+use bam::Record;
+use plotters::prelude::*;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::Path;
+
+/// Colors of cytobands by their Giemsa stain (the fifth column of UCSC cytoBand).
+pub fn stain_color(stain: &str) -> RGBColor {
+    match stain {
+        "gneg" => RGBColor(250, 250, 250),
+        "gpos25" => RGBColor(200, 200, 200),
+        "gpos33" => RGBColor(180, 180, 180),
+        "gpos50" => RGBColor(150, 150, 150),
+        "gpos66" => RGBColor(120, 120, 120),
+        "gpos75" => RGBColor(100, 100, 100),
+        "gpos100" | "gpos" => RGBColor(0, 0, 0),
+        "acen" => RGBColor(200, 50, 50),
+        "gvar" => RGBColor(220, 220, 220),
+        "stalk" => RGBColor(100, 127, 164),
+        _ => RGBColor(230, 230, 230),
+    }
+}
+
+/// Chromosomes not much shorter than the longest one (1%), to skip unplaced contigs.
+pub fn major_chromosomes(chromosomes: Vec<(String, u64)>) -> Vec<(String, u64)> {
+    let max_len = chromosomes.iter().map(|t| t.1).max().unwrap_or(0);
+    chromosomes
+        .into_iter()
+        .filter(|t| t.1 * 100 >= max_len)
+        .collect()
+}
+
+/// Returns true if an SA tag (`rname,pos,strand,CIGAR,mapQ,NM;`) contains an alignment on
+/// another chromosome.
+pub fn is_translocation(chrom: &str, sa: &str) -> bool {
+    sa.split(';')
+        .filter(|t| !t.is_empty())
+        .any(|t| t.split(',').next() != Some(chrom))
+}
+
+/// Binned coverage and positions of translocational split alignments of a sample.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SampleOverview {
+    bin_size: u64,
+    /// Aligned bases in each bin of each chromosome.
+    coverage: Vec<Vec<u64>>,
+    /// (chromosome, bin) with translocational split alignments.
+    translocations: BTreeSet<(usize, u64)>,
+}
+
+impl SampleOverview {
+    pub fn new(chromosomes: &[(String, u64)], bin_size: u64) -> Self {
+        let bin_size = bin_size.max(1);
+        SampleOverview {
+            bin_size,
+            coverage: chromosomes
+                .iter()
+                .map(|t| vec![0; (t.1 / bin_size + 1) as usize])
+                .collect(),
+            translocations: BTreeSet::new(),
+        }
+    }
+
+    /// Adds an alignment on [start, end) of the chromosome.
+    pub fn add(&mut self, chrom: usize, start: u64, end: u64, translocation: bool) {
+        let bins = &mut self.coverage[chrom];
+        let mut pos = start;
+        while pos < end {
+            let bin = pos / self.bin_size;
+            let bin_end = ((bin + 1) * self.bin_size).min(end);
+            if let Some(value) = bins.get_mut(bin as usize) {
+                *value += bin_end - pos;
+            }
+            pos = bin_end;
+        }
+        if translocation {
+            self.translocations.insert((chrom, start / self.bin_size));
+        }
+    }
+
+    pub fn add_record(&mut self, chrom: usize, chrom_name: &str, record: &Record) {
+        let translocation = match record.tags().get(b"SA") {
+            Some(TagValue::String(sa, _)) => {
+                is_translocation(chrom_name, &String::from_utf8_lossy(sa))
+            }
+            _ => false,
+        };
+        self.add(
+            chrom,
+            record.start().max(0) as u64,
+            record.calculate_end().max(0) as u64,
+            translocation,
+        );
+    }
+
+    /// Mean depth of each bin of the chromosome.
+    pub fn depth(&self, chrom: usize) -> Vec<f64> {
+        self.coverage[chrom]
+            .iter()
+            .map(|t| *t as f64 / self.bin_size as f64)
+            .collect()
+    }
+
+    /// Mean depth over the genome.
+    pub fn mean_depth(&self) -> f64 {
+        let bases: u64 = self.coverage.iter().flatten().sum();
+        let bins: usize = self.coverage.iter().map(|t| t.len()).sum();
+        bases as f64 / (bins.max(1) as u64 * self.bin_size) as f64
+    }
+}
+
+/// A genome-wide overview with an ideogram of each chromosome.
+#[derive(Debug, Clone)]
+pub struct Overview {
+    pub chromosomes: Vec<(String, u64)>,
+    pub samples: Vec<SampleOverview>,
+    /// (start, end, stain) of cytobands of each chromosome.
+    cytobands: BTreeMap<String, Vec<(u64, u64, String)>>,
+    bin_size: u64,
+}
+
+impl Overview {
+    pub fn new(
+        chromosomes: Vec<(String, u64)>,
+        samples: Vec<SampleOverview>,
+        bin_size: u64,
+    ) -> Self {
+        Overview {
+            chromosomes,
+            samples,
+            cytobands: BTreeMap::new(),
+            bin_size: bin_size.max(1),
+        }
+    }
+
+    pub fn read_cytobands<P: AsRef<Path>>(
+        path: P,
+    ) -> io::Result<BTreeMap<String, Vec<(u64, u64, String)>>> {
+        let mut reader = bed::Reader::from_file(path)?;
+        let mut cytobands = BTreeMap::new();
+        for record in reader.records() {
+            let record = record?;
+            cytobands
+                .entry(record.chrom().to_string())
+                .or_insert_with(Vec::new)
+                .push((
+                    record.start(),
+                    record.end(),
+                    record.score().unwrap_or("").to_string(),
+                ));
+        }
+        Ok(cytobands)
+    }
+
+    pub fn with_cytobands(mut self, cytobands: BTreeMap<String, Vec<(u64, u64, String)>>) -> Self {
+        self.cytobands = cytobands;
+        self
+    }
+
+    pub fn draw<'a, F>(
+        &self,
+        output: &str,
+        width: u32,
+        lambda: F,
+    ) -> Result<(), Box<dyn std::error::Error>>
+    where
+        F: Fn(usize) -> Option<&'a str>,
+    {
+        let label_width = 80;
+        let legend_height = 30;
+        let ideogram_height = 12;
+        let track_height = 24;
+        let row_height = ideogram_height + track_height * self.samples.len() as i32 + 12;
+        let height = legend_height + row_height * self.chromosomes.len() as i32 + 10;
+        let root = BitMapBackend::new(output, (width, height as u32)).into_drawing_area();
+        root.fill(&WHITE)?;
+
+        let max_len = self
+            .chromosomes
+            .iter()
+            .map(|t| t.1)
+            .max()
+            .unwrap_or(1)
+            .max(1);
+        let plot_width = (width as i32 - label_width - 10).max(1);
+        let to_x =
+            |pos: u64| label_width + (pos as f64 * plot_width as f64 / max_len as f64) as i32;
+        let font = ("sans-serif", 12).into_font();
+
+        for (i, _) in self.samples.iter().enumerate() {
+            let x = label_width + i as i32 * 160;
+            let color = Palette99::pick(i);
+            root.draw(&Rectangle::new([(x, 10), (x + 10, 20)], color.filled()))?;
+            let label = lambda(i).map_or(i.to_string(), |t| t.to_string());
+            root.draw(&Text::new(label, (x + 14, 9), font.clone()))?;
+        }
+        // Depths are scaled up to twice of the mean depth of each sample.
+        let max_depths: Vec<f64> = self
+            .samples
+            .iter()
+            .map(|t| (t.mean_depth() * 2.0).max(1.0))
+            .collect();
+
+        for (chrom, (name, len)) in self.chromosomes.iter().enumerate() {
+            let top = legend_height + row_height * chrom as i32;
+            root.draw(&Text::new(name.clone(), (4, top), font.clone()))?;
+            let ideogram = [(to_x(0), top), (to_x(*len), top + ideogram_height)];
+            match self.cytobands.get(name) {
+                Some(bands) => {
+                    for (start, end, stain) in bands.iter() {
+                        root.draw(&Rectangle::new(
+                            [(to_x(*start), top), (to_x(*end), top + ideogram_height)],
+                            stain_color(stain).filled(),
+                        ))?;
+                    }
+                }
+                None => root.draw(&Rectangle::new(ideogram, RGBColor(230, 230, 230).filled()))?,
+            }
+            root.draw(&Rectangle::new(ideogram, BLACK.stroke_width(1)))?;
+
+            for (sample, overview) in self.samples.iter().enumerate() {
+                let color = Palette99::pick(sample);
+                let bottom = top + ideogram_height + track_height * (sample as i32 + 1);
+                for (bin, depth) in overview.depth(chrom).into_iter().enumerate() {
+                    let bar_height =
+                        ((depth / max_depths[sample]).min(1.0) * (track_height - 2) as f64) as i32;
+                    if bar_height == 0 {
+                        continue;
+                    }
+                    let start = bin as u64 * self.bin_size;
+                    root.draw(&Rectangle::new(
+                        [
+                            (to_x(start), bottom - bar_height),
+                            (
+                                to_x((start + self.bin_size).min(*len)).max(to_x(start) + 1),
+                                bottom,
+                            ),
+                        ],
+                        color.mix(0.6).filled(),
+                    ))?;
+                }
+                for (_, bin) in overview.translocations.range((chrom, 0)..(chrom + 1, 0)) {
+                    let x = to_x(*bin * self.bin_size + self.bin_size / 2);
+                    root.draw(&TriangleMarker::new((x, top - 2), 4, color.filled()))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_translocation, SampleOverview};
+
+    #[test]
+    fn sa_tag() {
+        assert!(!is_translocation("chr1", "chr1,100,+,10M,60,0;"));
+        assert!(is_translocation(
+            "chr1",
+            "chr1,100,+,10M,60,0;chr2,5,-,10M,60,0;"
+        ));
+        assert!(!is_translocation("chr1", ""));
+    }
+
+    #[test]
+    fn binned_coverage() {
+        let chromosomes = vec![("chr1".to_string(), 250), ("chr2".to_string(), 100)];
+        let mut overview = SampleOverview::new(&chromosomes, 100);
+        overview.add(0, 50, 150, false);
+        overview.add(0, 180, 250, true);
+        overview.add(1, 0, 100, false);
+        assert_eq!(overview.depth(0), vec![0.5, 0.7, 0.5]);
+        assert_eq!(overview.depth(1), vec![1.0, 0.0]);
+        assert_eq!(overview.translocations.len(), 1);
+        assert!((overview.mean_depth() - 270.0 / 500.0).abs() < 1e-9);
+    }
+}
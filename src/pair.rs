@@ -0,0 +1,213 @@
+use bam::Record;
+use std::str::FromStr;
+
+/// Relative orientation of mates; the first letter is the strand of the leftmost mate.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PairOrientation {
+    FR,
+    RF,
+    FF,
+    RR,
+}
+
+impl PairOrientation {
+    /// Orientation of a pair from the starts and strands of a read and its mate.
+    pub fn new(start: i32, is_reverse: bool, mate_start: i32, mate_is_reverse: bool) -> Self {
+        let (left, right) = if start <= mate_start {
+            (is_reverse, mate_is_reverse)
+        } else {
+            (mate_is_reverse, is_reverse)
+        };
+        match (left, right) {
+            (false, true) => PairOrientation::FR,
+            (true, false) => PairOrientation::RF,
+            (false, false) => PairOrientation::FF,
+            (true, true) => PairOrientation::RR,
+        }
+    }
+}
+
+impl FromStr for PairOrientation {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "FR" => Ok(PairOrientation::FR),
+            "RF" => Ok(PairOrientation::RF),
+            "FF" => Ok(PairOrientation::FF),
+            "RR" => Ok(PairOrientation::RR),
+            _ => Err("orientation must be one of FR, RF, FF and RR"),
+        }
+    }
+}
+
+/// Expected range of absolute insert sizes, given as `min:max`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct InsertSizeRange {
+    pub min: u32,
+    pub max: u32,
+}
+
+impl InsertSizeRange {
+    /// Infers the range from the 0.5th and 99.5th percentiles of observed insert sizes.
+    pub fn from_insert_sizes(mut sizes: Vec<u32>) -> Option<Self> {
+        if sizes.is_empty() {
+            return None;
+        }
+        sizes.sort_unstable();
+        let percentile = |p: usize| sizes[(sizes.len() - 1) * p / 1000];
+        Some(InsertSizeRange {
+            min: percentile(5),
+            max: percentile(995),
+        })
+    }
+}
+
+impl FromStr for InsertSizeRange {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut iter = s.split(':').map(|t| t.parse::<u32>());
+        match (iter.next(), iter.next(), iter.next()) {
+            (Some(Ok(min)), Some(Ok(max)), None) if min <= max => Ok(InsertSizeRange { min, max }),
+            _ => Err("insert size range must be given as min:max"),
+        }
+    }
+}
+
+/// Classification of a read pair for coloring.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PairStatus {
+    Normal,
+    SmallInsert,
+    LargeInsert,
+    Orientation(PairOrientation),
+    OtherChromosome(i32),
+}
+
+impl PairStatus {
+    /// Classifies a pair. Orientation takes priority over insert size, as in most genome browsers.
+    pub fn new(
+        other_chromosome: Option<i32>,
+        orientation: PairOrientation,
+        insert_size: u32,
+        insert_size_range: Option<&InsertSizeRange>,
+        expected: PairOrientation,
+    ) -> Self {
+        if let Some(mate_ref_id) = other_chromosome {
+            return PairStatus::OtherChromosome(mate_ref_id);
+        }
+        if orientation != expected {
+            return PairStatus::Orientation(orientation);
+        }
+        match insert_size_range {
+            Some(range) if insert_size < range.min => PairStatus::SmallInsert,
+            Some(range) if insert_size > range.max => PairStatus::LargeInsert,
+            _ => PairStatus::Normal,
+        }
+    }
+
+    /// Classifies a record with a mapped mate; returns None for unpaired reads or unmapped mates.
+    pub fn from_record(
+        record: &Record,
+        insert_size_range: Option<&InsertSizeRange>,
+        expected: PairOrientation,
+    ) -> Option<Self> {
+        let flag = record.flag();
+        if !flag.is_paired() || !flag.is_mapped() || !flag.mate_is_mapped() {
+            return None;
+        }
+        let other_chromosome = if record.ref_id() != record.mate_ref_id() {
+            Some(record.mate_ref_id())
+        } else {
+            None
+        };
+        let orientation = PairOrientation::new(
+            record.start(),
+            flag.is_reverse_strand(),
+            record.mate_start(),
+            flag.mate_is_reverse_strand(),
+        );
+        Some(PairStatus::new(
+            other_chromosome,
+            orientation,
+            record.template_len().abs() as u32,
+            insert_size_range,
+            expected,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InsertSizeRange, PairOrientation, PairStatus};
+
+    #[test]
+    fn orientation() {
+        assert_eq!(
+            PairOrientation::new(100, false, 300, true),
+            PairOrientation::FR
+        );
+        assert_eq!(
+            PairOrientation::new(300, true, 100, false),
+            PairOrientation::FR
+        );
+        assert_eq!(
+            PairOrientation::new(300, false, 100, true),
+            PairOrientation::RF
+        );
+        assert_eq!(
+            PairOrientation::new(100, false, 300, false),
+            PairOrientation::FF
+        );
+        assert_eq!(
+            PairOrientation::new(100, true, 300, true),
+            PairOrientation::RR
+        );
+        assert_eq!("rf".parse::<PairOrientation>(), Ok(PairOrientation::RF));
+        assert!("FX".parse::<PairOrientation>().is_err());
+    }
+
+    #[test]
+    fn insert_size_range() {
+        assert_eq!(
+            "100:500".parse::<InsertSizeRange>(),
+            Ok(InsertSizeRange { min: 100, max: 500 })
+        );
+        assert!("500:100".parse::<InsertSizeRange>().is_err());
+        assert!("100".parse::<InsertSizeRange>().is_err());
+        let range = InsertSizeRange::from_insert_sizes((1..=1001).collect()).unwrap();
+        assert_eq!(range, InsertSizeRange { min: 6, max: 996 });
+        assert_eq!(InsertSizeRange::from_insert_sizes(vec![]), None);
+    }
+
+    #[test]
+    fn pair_status() {
+        let range = InsertSizeRange { min: 100, max: 500 };
+        let fr = PairOrientation::FR;
+        assert_eq!(
+            PairStatus::new(None, fr, 300, Some(&range), fr),
+            PairStatus::Normal
+        );
+        assert_eq!(
+            PairStatus::new(None, fr, 50, Some(&range), fr),
+            PairStatus::SmallInsert
+        );
+        assert_eq!(
+            PairStatus::new(None, fr, 5000, Some(&range), fr),
+            PairStatus::LargeInsert
+        );
+        assert_eq!(
+            PairStatus::new(None, PairOrientation::RR, 5000, Some(&range), fr),
+            PairStatus::Orientation(PairOrientation::RR)
+        );
+        assert_eq!(
+            PairStatus::new(Some(3), fr, 0, Some(&range), fr),
+            PairStatus::OtherChromosome(3)
+        );
+        assert_eq!(
+            PairStatus::new(None, fr, 5000, None, fr),
+            PairStatus::Normal
+        );
+    }
+}
@@ -0,0 +1,109 @@
+use clap::ArgMatches;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+
+/// Reads selected by the files of names `--only-reads` and `--highlight-reads`, or by names
+/// given with `--only-read-names` (or `--filtered-by-read-name`) and `--highlight-read-names`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ReadSelection {
+    /// If given, the other reads are hidden.
+    pub only: Option<HashSet<Vec<u8>>>,
+    pub highlight: HashSet<Vec<u8>>,
+}
+
+impl ReadSelection {
+    pub fn from_matches(matches: &ArgMatches) -> io::Result<Self> {
+        let only = match (
+            matches.value_of("only-read-names"),
+            matches.value_of("only-reads"),
+            matches.value_of("read-name"),
+        ) {
+            (None, None, None) => None,
+            (names, path, name) => {
+                let mut only = path.map(read_names_file).transpose()?.unwrap_or_default();
+                only.extend(names.map(read_names).unwrap_or_default());
+                only.extend(name.map(|t| t.as_bytes().to_vec()));
+                Some(only)
+            }
+        };
+        let mut highlight = matches
+            .value_of("highlight-reads")
+            .map(read_names_file)
+            .transpose()?
+            .unwrap_or_default();
+        highlight.extend(
+            matches
+                .value_of("highlight-read-names")
+                .map(read_names)
+                .unwrap_or_default(),
+        );
+        Ok(ReadSelection { only, highlight })
+    }
+
+    pub fn is_hidden(&self, name: &[u8]) -> bool {
+        matches!(&self.only, Some(only) if !only.contains(name))
+    }
+
+    pub fn is_highlighted(&self, name: &[u8]) -> bool {
+        self.highlight.contains(name)
+    }
+}
+
+/// Read names given as a comma-separated list.
+pub fn read_names(value: &str) -> HashSet<Vec<u8>> {
+    value
+        .split(',')
+        .filter(|t| !t.is_empty())
+        .map(|t| t.as_bytes().to_vec())
+        .collect()
+}
+
+/// Read names in a file: the first column of each line, skipping empty lines and ones
+/// starting with '#'. A file without names is an error, as it would select no reads.
+pub fn read_names_file(path: &str) -> io::Result<HashSet<Vec<u8>>> {
+    let names: HashSet<Vec<u8>> = fs::read_to_string(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))?
+        .lines()
+        .filter(|t| !t.starts_with('#'))
+        .filter_map(|t| t.split_whitespace().next())
+        .map(|t| t.as_bytes().to_vec())
+        .collect();
+    if names.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: no read names", path),
+        ));
+    }
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_names, read_names_file, ReadSelection};
+
+    #[test]
+    fn names() {
+        let path = std::env::temp_dir().join("hgb_read_names.txt");
+        std::fs::write(&path, "# names\nread1\tchr1\n\nread2\n").unwrap();
+        let names = read_names_file(path.to_str().unwrap()).unwrap();
+        assert_eq!(names.len(), 2);
+        assert!(names.contains(&b"read1"[..]));
+        // A path is never taken as a name.
+        assert!(!read_names(path.to_str().unwrap()).contains(&b"read1"[..]));
+        std::fs::write(&path, "# no names\n\n").unwrap();
+        assert!(read_names_file(path.to_str().unwrap()).is_err());
+        std::fs::remove_file(&path).unwrap();
+        assert!(read_names_file(path.to_str().unwrap()).is_err());
+        assert_eq!(read_names("read1,read3,").len(), 2);
+
+        let selection = ReadSelection {
+            only: Some(names),
+            highlight: read_names("read2"),
+        };
+        assert!(!selection.is_hidden(b"read1"));
+        assert!(selection.is_hidden(b"read3"));
+        assert!(selection.is_highlighted(b"read2"));
+        assert!(!ReadSelection::default().is_hidden(b"read3"));
+    }
+}
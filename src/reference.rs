@@ -0,0 +1,226 @@
+use byteorder::{LittleEndian, ReadBytesExt};
+use flate2::read::MultiGzDecoder;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Error, ErrorKind, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use twobit::TwoBitFile;
+
+const TWOBIT_SIGNATURE: u32 = 0x1A41_2743;
+
+/// A reference genome given by `--2bit`: either a 2bit file or an indexed (optionally bgzipped) FASTA.
+pub enum Reference {
+    TwoBit(PathBuf),
+    Fasta(IndexedFasta),
+}
+
+impl Reference {
+    /// Opens a reference genome. 2bit files are detected by their signature,
+    /// and the other files are treated as FASTA with `.fai` (and `.gzi` if bgzipped).
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut signature = [0u8; 4];
+        File::open(path)?.read_exact(&mut signature)?;
+        if u32::from_le_bytes(signature) == TWOBIT_SIGNATURE
+            || u32::from_be_bytes(signature) == TWOBIT_SIGNATURE
+        {
+            Ok(Reference::TwoBit(path.to_path_buf()))
+        } else {
+            Ok(Reference::Fasta(IndexedFasta::open(path)?))
+        }
+    }
+
+    /// Reads the sequence on the 0-based half-open range. Soft-masked bases are upper-cased.
+    pub fn read_sequence(&mut self, chrom: &str, range: Range<usize>) -> io::Result<String> {
+        match self {
+            Reference::TwoBit(path) => {
+                let tb = TwoBitFile::open(path)
+                    .map_err(|e| Error::new(ErrorKind::Other, format!("{:?}", e)))?;
+                let mut tb_nosoft = tb.enable_softmask(false);
+                tb_nosoft
+                    .read_sequence(chrom, range)
+                    .map_err(|e| Error::new(ErrorKind::Other, format!("{:?}", e)))
+            }
+            Reference::Fasta(fasta) => fasta.read_sequence(chrom, range),
+        }
+    }
+}
+
+/// An entry of a FASTA index (.fai).
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaiEntry {
+    pub length: u64,
+    pub offset: u64,
+    pub line_bases: u64,
+    pub line_width: u64,
+}
+
+impl FaiEntry {
+    /// The (uncompressed) file offset of the base on the given position.
+    fn position(&self, pos: u64) -> u64 {
+        self.offset + pos / self.line_bases * self.line_width + pos % self.line_bases
+    }
+}
+
+pub struct IndexedFasta {
+    path: PathBuf,
+    index: HashMap<String, FaiEntry>,
+    /// Pairs of compressed and uncompressed offsets of BGZF blocks (None if not compressed).
+    gzi: Option<Vec<(u64, u64)>>,
+}
+
+impl IndexedFasta {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let index = read_fai(File::open(append_extension(&path, "fai"))?)?;
+        let mut magic = [0u8; 2];
+        File::open(&path)?.read_exact(&mut magic)?;
+        let gzi = if magic == [0x1f, 0x8b] {
+            let gzi_path = append_extension(&path, "gzi");
+            let file = File::open(&gzi_path).map_err(|e| {
+                Error::new(
+                    e.kind(),
+                    format!(
+                        "{}: bgzipped FASTA requires .gzi index (samtools faidx)",
+                        gzi_path.display()
+                    ),
+                )
+            })?;
+            Some(read_gzi(file)?)
+        } else {
+            None
+        };
+        Ok(IndexedFasta { path, index, gzi })
+    }
+
+    pub fn read_sequence(&mut self, chrom: &str, range: Range<usize>) -> io::Result<String> {
+        let entry = self.index.get(chrom).ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!("{} is not found on FASTA index", chrom),
+            )
+        })?;
+        let start = (range.start as u64).min(entry.length);
+        let end = (range.end as u64).min(entry.length);
+        if start >= end {
+            return Ok(String::new());
+        }
+        let begin = entry.position(start);
+        let len = (entry.position(end - 1) + 1 - begin) as usize;
+        let mut buf = vec![0u8; len];
+        match &self.gzi {
+            None => {
+                let mut file = File::open(&self.path)?;
+                file.seek(SeekFrom::Start(begin))?;
+                file.read_exact(&mut buf)?;
+            }
+            Some(gzi) => {
+                let (compressed, uncompressed) = gzi
+                    .iter()
+                    .take_while(|t| t.1 <= begin)
+                    .last()
+                    .cloned()
+                    .unwrap_or((0, 0));
+                let mut file = File::open(&self.path)?;
+                file.seek(SeekFrom::Start(compressed))?;
+                let mut decoder = MultiGzDecoder::new(BufReader::new(file));
+                io::copy(
+                    &mut (&mut decoder).take(begin - uncompressed),
+                    &mut io::sink(),
+                )?;
+                decoder.read_exact(&mut buf)?;
+            }
+        }
+        Ok(buf
+            .into_iter()
+            .filter(|t| !t.is_ascii_whitespace())
+            .map(|t| t.to_ascii_uppercase() as char)
+            .collect())
+    }
+}
+
+fn append_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".");
+    path.push(extension);
+    PathBuf::from(path)
+}
+
+fn read_fai<R: Read>(reader: R) -> io::Result<HashMap<String, FaiEntry>> {
+    let mut index = HashMap::new();
+    for line in BufReader::new(reader).lines() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 5 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid FASTA index line: {}", line),
+            ));
+        }
+        let parse = |t: &str| {
+            t.parse::<u64>()
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+        };
+        index.insert(
+            fields[0].to_string(),
+            FaiEntry {
+                length: parse(fields[1])?,
+                offset: parse(fields[2])?,
+                line_bases: parse(fields[3])?,
+                line_width: parse(fields[4])?,
+            },
+        );
+    }
+    Ok(index)
+}
+
+fn read_gzi<R: Read>(mut reader: R) -> io::Result<Vec<(u64, u64)>> {
+    let n = reader.read_u64::<LittleEndian>()?;
+    let mut gzi = Vec::with_capacity(n as usize);
+    for _ in 0..n {
+        let compressed = reader.read_u64::<LittleEndian>()?;
+        let uncompressed = reader.read_u64::<LittleEndian>()?;
+        gzi.push((compressed, uncompressed));
+    }
+    Ok(gzi)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Reference;
+
+    #[test]
+    fn fasta_works() {
+        let mut reference = Reference::open("test/ref.fa").unwrap();
+        assert_eq!(
+            reference.read_sequence("chr1", 0..5).unwrap(),
+            "ACGTA".to_string()
+        );
+        // Across lines, with a soft-masked base.
+        assert_eq!(
+            reference.read_sequence("chr1", 8..14).unwrap(),
+            "ACGTCC".to_string()
+        );
+        assert_eq!(
+            reference.read_sequence("chr2", 3..100).unwrap(),
+            "TTTGGGAAACC".to_string()
+        );
+        assert!(reference.read_sequence("chr3", 0..1).is_err());
+    }
+
+    #[test]
+    fn bgzipped_fasta_works() {
+        let mut plain = Reference::open("test/ref.fa").unwrap();
+        let mut bgzipped = Reference::open("test/ref.fa.gz").unwrap();
+        for (chrom, range) in vec![("chr1", 0..5), ("chr1", 8..30), ("chr2", 0..14)] {
+            assert_eq!(
+                plain.read_sequence(chrom, range.clone()).unwrap(),
+                bgzipped.read_sequence(chrom, range).unwrap()
+            );
+        }
+    }
+}
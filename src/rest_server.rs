@@ -1,56 +1,73 @@
 use actix_cors::Cors;
 use actix_files::NamedFile;
 use actix_web::dev::ServiceRequest;
-use actix_web::error::{ErrorBadRequest, ErrorNotFound};
+use actix_web::http::header::Header;
+use actix_web::http::StatusCode;
 use actix_web::middleware::Condition;
-use actix_web::HttpRequest;
 use actix_web::{
-    http::header::{ContentDisposition, DispositionType},
+    http::header::{ContentDisposition, DispositionParam, DispositionType},
     HttpResponse,
 };
 use actix_web::{middleware::Logger, web, Result};
-use actix_web_httpauth::extractors::basic::{BasicAuth, Config};
+use actix_web::{HttpMessage, HttpRequest, ResponseError};
+use actix_web_httpauth::extractors::basic::Config;
 use actix_web_httpauth::extractors::AuthenticationError;
+use actix_web_httpauth::headers::authorization::{Authorization, Basic, Bearer};
 use actix_web_httpauth::middleware::HttpAuthentication;
-use bam::Record;
 use clap::{App, AppSettings, Arg, ArgMatches, ArgSettings, Error};
 use genomic_range::StringRegion;
+use ghi::api;
+use ghi::auth::{Access, Credentials};
+use ghi::cache::{self, RenderCache};
+use ghi::catalog::{Catalog, Dataset, Lru};
+use ghi::display_mode::DisplayModes;
 use ghi::dump::{Area, ReadTree};
-use ghi::{vis::bam_record_vis, ChromosomeBufferTrait, ReadBuffer, Vis, VisRef};
-use itertools::Itertools;
+use ghi::session::{remove_option, Checksums, Session, SERVER_OPTIONS};
+use ghi::snapshot::{InFlight, Snapshot};
+use ghi::ChromosomeBufferTrait;
 use qstring::QString;
-use rand::Rng;
-use std::collections::hash_map::DefaultHasher;
+use std::fmt;
 use std::fs::File;
-use std::hash::{Hash, Hasher};
-use std::io::BufReader;
+use std::io::{BufReader, Error as IoError, ErrorKind};
 use std::marker::{Send, Sync};
+use std::sync::{Arc, Mutex};
 use std::time::Instant;
-use std::{collections::BTreeSet, fs, sync::RwLock};
+use std::{collections::BTreeSet, sync::RwLock};
 
 use crate::subcommands::{bam_vis, vis_query};
 //struct Years(i64);
-type BasicAuthTuple = (String, String);
+/// Results of drawing shared by identical requests in flight.
+type Renders = InFlight<Result<(), String>>;
 
 #[derive(Debug, Clone)]
 struct Item {
     //vis: Vis,
     range: StringRegion,
     args: Vec<String>,
-    cache_dir: String,
+    cache: RenderCache,
+    inputs: Vec<String>,
+    /// Checksums of inputs recorded on sessions, computed on the first request.
+    checksums: Arc<Checksums>,
 }
 
 impl Item {
-    fn new(range: StringRegion, args: Vec<String>, cache_dir: String) -> Self {
+    fn new(
+        range: StringRegion,
+        args: Vec<String>,
+        cache: RenderCache,
+        inputs: Vec<String>,
+    ) -> Self {
         Item {
             range,
             args,
-            cache_dir,
+            cache,
+            inputs,
+            checksums: Arc::new(Checksums::default()),
         }
     }
 }
 
-#[derive(Deserialize, Hash)]
+#[derive(Deserialize)]
 pub struct RequestBody {
     params: String,
     format: String,
@@ -62,10 +79,153 @@ pub struct ResponseBody {
     message: String,
 }
 
-fn calculate_hash<T: Hash>(t: &T) -> u64 {
-    let mut s = DefaultHasher::new();
-    t.hash(&mut s);
-    s.finish()
+/// An error responded as JSON with its status, e.g. `{"status":400,"error":"range is required"}`.
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    status: u16,
+    error: String,
+}
+
+impl ApiError {
+    pub fn new<M: fmt::Display>(status: StatusCode, message: M) -> Self {
+        ApiError {
+            status,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn bad_request<M: fmt::Display>(message: M) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, message)
+    }
+
+    pub fn forbidden<M: fmt::Display>(message: M) -> Self {
+        Self::new(StatusCode::FORBIDDEN, message)
+    }
+
+    pub fn not_found<M: fmt::Display>(message: M) -> Self {
+        Self::new(StatusCode::NOT_FOUND, message)
+    }
+
+    pub fn internal<M: fmt::Display>(message: M) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, message)
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status).json(ErrorBody {
+            status: self.status.as_u16(),
+            error: self.message.clone(),
+        })
+    }
+}
+
+/// Options which clients may not give: outputs and settings of the server.
+const CLIENT_DENIED_OPTIONS: [&str; 19] = [
+    "output",
+    "output-dir",
+    "circos",
+    "tiles",
+    "tile-zoom",
+    "tile-range",
+    "web",
+    "meaningless",
+    "meaningless2",
+    "cache-dir",
+    "cache-size",
+    "cache-age",
+    "static-dir",
+    "basic-auth",
+    "auth-file",
+    "tls-cert",
+    "tls-key",
+    "catalog",
+    "max-open-datasets",
+];
+
+/// Options giving input files, which clients may give only if the server reads them.
+const CLIENT_INPUT_OPTIONS: [&str; 11] = [
+    "INPUT",
+    "bam",
+    "frequency",
+    "bed",
+    "gff3",
+    "bed-range",
+    "ref-column",
+    "graph",
+    "cytoband",
+    "highlight-reads",
+    "only-reads",
+];
+
+/// Splits options of `vis` given by a client, rejecting outputs, settings of the server and
+/// files other than `inputs` of the server.
+fn client_params(params: &str, inputs: &[String]) -> Result<Vec<String>, ApiError> {
+    let params: Vec<String> = params
+        .split(' ')
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_string())
+        .collect();
+    let matches = get_matches_from(
+        std::iter::once("vis".to_string())
+            .chain(params.iter().cloned())
+            .collect(),
+    )
+    .map_err(|e| ApiError::bad_request(format!("parameter error: {}", e)))?;
+    if let Some(option) = CLIENT_DENIED_OPTIONS.iter().find(|t| matches.is_present(t)) {
+        return Err(ApiError::forbidden(format!(
+            "{} is not allowed on requests",
+            option
+        )));
+    }
+    for option in CLIENT_INPUT_OPTIONS.iter() {
+        for value in matches.values_of(option).into_iter().flatten() {
+            if !inputs.iter().any(|t| t == value) {
+                return Err(ApiError::forbidden(format!(
+                    "{} is not an input of the server",
+                    value
+                )));
+            }
+        }
+    }
+    Ok(params)
+}
+
+/// Stable key of a request, which changes if options of the server or input files are modified.
+fn request_key(args: &[String], inputs: &[String], body: &RequestBody) -> u64 {
+    let stamps = cache::input_stamps(inputs);
+    let prefetch = if body.prefetch { "prefetch" } else { "" };
+    let fields: Vec<&str> = args
+        .iter()
+        .chain(stamps.iter())
+        .map(|t| t.as_str())
+        .chain(vec![body.format.as_str(), body.params.as_str(), prefetch])
+        .collect();
+    cache::stable_hash(&fields)
+}
+
+/// Removes all cached files.
+fn purge(cache: &RenderCache) -> Result<HttpResponse> {
+    let removed = cache.purge().map_err(ApiError::internal)?;
+    Ok(HttpResponse::Ok().json(ResponseBody {
+        message: format!("Removed {} cached files", removed),
+    }))
 }
 
 fn get_matches_from(args: Vec<String>) -> Result<ArgMatches, Error> {
@@ -144,8 +304,39 @@ fn get_matches_from(args: Vec<String>) -> Result<ArgMatches, Error> {
     .arg(Arg::new("meaningless2").short('>').about("Serve the web server with accepting any parameter"))
     .arg(Arg::new("dump-json").short('%').long("write-json").about("Dump JSON of read metadata"))
     .arg(Arg::new("adjust-y").short('&').long("not-adjust-y").about("Do not adjust y on server mode"))
-    .arg(Arg::new("ref-column").short('!').long("2bit").takes_value(true).about("Show the base colors of reference genome (input must be 2bit format)"))
+    .arg(Arg::new("ref-column").short('!').long("2bit").takes_value(true).about("Show the base colors of reference genome (2bit or indexed FASTA)"))
     .arg(Arg::new("insertion-string").short('{').long("show-insertion-sequence").about("Show the insertion sequence along with insertion callets"))
+    .arg(Arg::new("allele-count").long("allele-count-track").takes_value(true).about("Show per-base allele counts on each coverage track"))
+    .arg(Arg::new("soft-clip").long("show-soft-clips").about("Show soft-clipped sequences as faded extensions of read alignments"))
+    .arg(Arg::new("view-as-pairs").long("view-as-pairs").about("Display mates of paired-end reads on the same line connected by a line, colored by insert size and orientation"))
+    .arg(Arg::new("insert-size-range").long("insert-size-range").takes_value(true).about("Expected insert sizes as min:max on --view-as-pairs (inferred from 0.5 and 99.5 percentiles if not given)"))
+    .arg(Arg::new("pair-orientation").long("pair-orientation").takes_value(true).possible_values(&["FR", "RF", "FF", "RR"]).about("Expected orientation of mates on --view-as-pairs [default: FR]"))
+    .arg(Arg::new("downsample").long("downsample").takes_value(true).about("Downsample reads deterministically to at most the given number of reads starting in each window (coverage is computed from all reads)"))
+    .arg(Arg::new("downsample-window").long("downsample-window").takes_value(true).about("Window size in bp for --downsample [default: 50]"))
+    .arg(Arg::new("downsample-seed").long("downsample-seed").takes_value(true).about("Seed of the read-name hash for --downsample [default: 0]"))
+    .arg(Arg::new("display-mode").long("display-mode").takes_value(true).about("Set display modes of BAM inputs: expanded, squished (thin rows without details) or collapsed (a single density row); comma-separated in the order of inputs, or a single mode for all"))
+    .arg(Arg::new("circos").long("circos").takes_value(true).about("Draw a circos plot of translocations, clustering breakpoints of split alignments of loaded reads and weighting chords by supporting reads"))
+    .arg(Arg::new("breakpoint-tolerance").long("breakpoint-tolerance").takes_value(true).about("Distance in bp to cluster breakpoints of split alignments [default: 100]"))
+    .arg(Arg::new("dotplot").long("dotplot").about("Draw a dot plot of all alignments of the read given by --read-name (the primary and SA entries) on reference against read coordinates"))
+    .arg(Arg::new("breakpoint-view").long("breakpoint-view").about("Show each split read on one row across all ranges (e.g. on different chromosomes) and link its pieces to the panels of their neighbors"))
+    .arg(Arg::new("highlight-reads").long("highlight-reads").takes_value(true).about("Outline reads whose names are listed in the file (one name per line)"))
+    .arg(Arg::new("highlight-read-names").long("highlight-read-names").takes_value(true).about("Outline reads whose names are given as a comma-separated list"))
+    .arg(Arg::new("only-reads").long("only-reads").takes_value(true).about("Show only reads whose names are listed in the file (one name per line)"))
+    .arg(Arg::new("only-read-names").long("only-read-names").takes_value(true).about("Show only reads whose names are given as a comma-separated list"))
+    .arg(Arg::new("catalog").long("catalog").takes_value(true).about("Serve GHB/BAM datasets at /datasets/<id> on the REST server (--rest-server only; not supported by --web); a directory of the files (ids are file stems) or a YAML file listing ids and paths"))
+    .arg(Arg::new("max-open-datasets").long("max-open-datasets").takes_value(true).about("Set the maximal number of datasets kept open on --catalog, closing the least recently used one [default: 8]"))
+    .arg(Arg::new("tiles").long("tiles").takes_value(true).about("Render tiles of the zoomable web server into the directory with genome.dzi and exit"))
+    .arg(Arg::new("tile-zoom").long("tile-zoom").takes_value(true).about("Zoom levels to render on --tiles as min:max or a single level [default: all levels]"))
+    .arg(Arg::new("tile-range").long("tile-range").takes_value(true).multiple(true).about("Render only tiles overlapping the genomic ranges on --tiles"))
+    .arg(Arg::new("auth-file").long("auth-file").takes_value(true).about("Authenticate users by Argon2 hashes of their passwords and API tokens by SHA-256 digests in a YAML file for the web server, optionally restricting datasets on --catalog (overrides --basic-auth)"))
+    .arg(Arg::new("tls-cert").long("tls-cert").takes_value(true).requires("tls-key").about("Serve HTTPS on the web server with the certificate chain in PEM"))
+    .arg(Arg::new("tls-key").long("tls-key").takes_value(true).requires("tls-cert").about("Private key in PEM (PKCS #8, RSA or EC) of --tls-cert"))
+    .arg(Arg::new("genome-overview").long("genome-overview").about("Draw binned coverage over all chromosomes with ideograms and markers of translocational split alignments"))
+    .arg(Arg::new("cytoband").long("cytoband").takes_value(true).about("Cytoband BED (UCSC cytoBand) to draw ideograms on --genome-overview; only chromosomes in the file are drawn"))
+    .arg(Arg::new("overview-bin-size").long("overview-bin-size").takes_value(true).about("Bin size in bp of coverage on --genome-overview [default: 1000000]"))
+    .arg(Arg::new("track-colors").long("track-colors").takes_value(true).about("Set read colors of BAM inputs as #rrggbb; comma-separated in the order of inputs (empty to keep the default)"))
+    .arg(Arg::new("track-heights").long("track-heights").takes_value(true).about("Set the maximal number of rows of BAM inputs; comma-separated in the order of inputs (empty for no limit)"))
+    .arg(Arg::new("track-filters").long("track-filters").takes_value(true).about("Set read filters of BAM inputs, e.g. min-mapq=20,min-read-length=1000,flag-exclude=1796; semicolon-separated in the order of inputs"))
     .arg(
         Arg::new("labels")
             .short('}')
@@ -253,8 +444,10 @@ fn get_matches_from(args: Vec<String>) -> Result<ArgMatches, Error> {
         Arg::new("cache-dir")
             .short('d')
             .takes_value(true)
-            .about("Cache directory for server (generated randomly if not specified)"),
+            .about("Cache directory for server (a directory of the server address and inputs under the cache directory of the user if not specified)"),
     )
+    .arg(Arg::new("cache-size").long("cache-size").takes_value(true).about("Set the maximal size in MB of cached files, evicting the oldest ones [default: 1024]"))
+    .arg(Arg::new("cache-age").long("cache-age").takes_value(true).about("Set seconds to keep cached files (kept until evicted by --cache-size if not given)"))
     .arg(
         Arg::new("static-dir")
             .short('.')
@@ -321,8 +514,9 @@ fn get_matches_from(args: Vec<String>) -> Result<ArgMatches, Error> {
         Arg::new("read-name")
             .short(')')
             .long("filtered-by-read-name")
+            .alias("read-name")
             .takes_value(true)
-            .about("Filter by read name"),
+            .about("Show only the read with the name"),
     )
     .arg(
         Arg::new("filtered-by-tag")
@@ -337,6 +531,7 @@ fn get_matches_from(args: Vec<String>) -> Result<ArgMatches, Error> {
             .takes_value(true)
             .about("[Output] image file (prefixed as .bmp / .png)"),
     )
+    .arg(Arg::new("output-dir").long("output-dir").takes_value(true).about("[Output] Directory to write an image per region of --bed-range (named after the name column) in parallel, with index.html of thumbnails"))
     .arg(
         Arg::new("bed")
             .short('L')
@@ -366,13 +561,20 @@ fn get_matches_from(args: Vec<String>) -> Result<ArgMatches, Error> {
     app.try_get_matches_from(args)
 }
 
-fn id_to_range(
-    _range: &StringRegion,
+/// Arguments of `vis` for a request, starting with "vis".
+fn request_args(
     args: &[String],
-    params: String,
+    inputs: &[String],
+    params: &str,
     path_string: String,
-) -> Result<(ArgMatches, StringRegion), Error> {
-    let a: Vec<String> = params.split(' ').map(|t| t.to_string()).collect();
+) -> Result<Vec<String>, ApiError> {
+    let a = client_params(params, inputs)?;
+    // Alleles are counted when the buffer loads reads, only if the server is given the track.
+    if is_given(&a, "allele-count") && !is_given(args, "allele-count") {
+        return Err(ApiError::bad_request(
+            "allele-count-track should be given to the server",
+        ));
+    }
     let b: Vec<String> = vec!["-o".to_string(), path_string];
     let mut args = args
         .to_owned()
@@ -382,29 +584,66 @@ fn id_to_range(
     args.extend(b);
     args.extend(a);
     args.remove(0);
-    args = args.into_iter().skip_while(|t| t != "vis").collect();
-    eprintln!("{:?}", args.join(" "));
-    let matches = get_matches_from(args)?;
-    let ranges_str = matches.values_of("range").unwrap();
-    let range = {
-        let ranges_tmp: Vec<String> = ranges_str.into_iter().map(|t| t.to_string()).collect();
-        StringRegion::new(&ranges_tmp[ranges_tmp.len() - 1]).unwrap()
+    Ok(args.into_iter().skip_while(|t| t != "vis").collect())
+}
+
+/// Whether the option is given on the arguments of `vis`, with or without the command line of
+/// the server.
+fn is_given(args: &[String], option: &str) -> bool {
+    let args = match args.iter().position(|t| t == "vis") {
+        Some(index) => args[index..]
+            .iter()
+            .filter(|t| *t != "-P")
+            .cloned()
+            .collect(),
+        None => std::iter::once("vis".to_string())
+            .chain(args.iter().cloned())
+            .collect(),
     };
+    get_matches_from(args).map_or(false, |t| t.is_present(option))
+}
+
+/// Parses the arguments of a request with the range to draw, which is the last one.
+fn parse_request(args: Vec<String>) -> Result<(ArgMatches, StringRegion), ApiError> {
+    let matches = get_matches_from(args)
+        .map_err(|e| ApiError::bad_request(format!("parameter error: {}", e)))?;
+    DisplayModes::from_matches(&matches).map_err(ApiError::bad_request)?;
+    let range = matches
+        .values_of("range")
+        .and_then(|t| t.last())
+        .ok_or_else(|| ApiError::bad_request("range is required"))?;
+    let range = StringRegion::new(range)
+        .map_err(|e| ApiError::bad_request(format!("Invalid range {}: {:?}", range, e)))?;
+    Ok((matches, range))
+}
+
+fn id_to_range(
+    _range: &StringRegion,
+    args: &[String],
+    inputs: &[String],
+    params: &str,
+    path_string: String,
+) -> Result<(ArgMatches, StringRegion), ApiError> {
+    let args = request_args(args, inputs, params, path_string)?;
+    eprintln!("{:?}", args.join(" "));
+    let (matches, range) = parse_request(args)?;
     eprintln!("{:?}", range);
     Ok((matches, range))
 }
 
 fn id_to_range_ab_initio(
-    params: String,
+    inputs: &[String],
+    params: &str,
     path_string: String,
-) -> Result<(ArgMatches, Vec<String>), Error> {
-    let a: Vec<String> = params.split(' ').map(|t| t.to_string()).collect();
+) -> Result<(ArgMatches, Vec<String>), ApiError> {
+    let a = client_params(params, inputs)?;
     let b: Vec<String> = vec!["-o".to_string(), path_string];
     let mut args = vec!["vis".to_string()];
     args.extend(a);
     args.extend(b);
     eprintln!("{:?}", args);
-    let matches = get_matches_from(args.clone())?;
+    let matches = get_matches_from(args.clone())
+        .map_err(|e| ApiError::bad_request(format!("parameter error: {}", e)))?;
     eprintln!("{:?}", matches.value_of("INPUT"));
     Ok((matches, args))
 }
@@ -415,266 +654,392 @@ async fn get_json(
     //query: web::Query<RequestBody>
 ) -> Result<NamedFile> {
     let qs = QString::from(req.query_string());
-    let format = qs.get("format").unwrap().clone(); // &query.format.clone();
-    let params = qs.get("params").unwrap().clone();
-    let prefetch = qs.get("params").is_some();
-    let hash: u64 = calculate_hash(&RequestBody {
-        format: format.to_string(),
-        params: params.to_string(),
-        prefetch,
-    });
+    let (format, params) = query_params(&qs)?;
+    let prefetch = true;
     let data = item.read().unwrap();
-    let cache_dir = &data.cache_dir;
-    let path_string = format!("{}/{}.json", cache_dir, hash);
-    match NamedFile::open(path_string) {
-        Ok(file) => Ok(file.set_content_disposition(ContentDisposition {
+    let hash = request_key(
+        &data.args,
+        &data.inputs,
+        &RequestBody {
+            format: format.to_string(),
+            params: params.to_string(),
+            prefetch,
+        },
+    );
+    match data.cache.get(hash, "json").map(NamedFile::open) {
+        Some(Ok(file)) => Ok(file.set_content_disposition(ContentDisposition {
             disposition: DispositionType::Attachment,
             parameters: vec![],
         })),
-        _ => Err(ErrorNotFound("No JSON File Available".to_string())),
+        _ => Err(ApiError::not_found("No JSON File Available").into()),
+    }
+}
+
+/// `format` (png if not given) and `params` of a query.
+fn query_params(qs: &QString) -> Result<(&str, &str), ApiError> {
+    let format = qs.get("format").unwrap_or("png");
+    if !["png", "bmp", "json"].contains(&format) {
+        return Err(ApiError::bad_request(format!("Invalid format: {}", format)));
+    }
+    let params = qs
+        .get("params")
+        .ok_or_else(|| ApiError::bad_request("params is required"))?;
+    Ok((format, params))
+}
+
+/// Rejects formats other than images.
+fn check_image_format(format: &str) -> Result<(), ApiError> {
+    match format {
+        "png" | "bmp" => Ok(()),
+        _ => Err(ApiError::bad_request(format!("Invalid format: {}", format))),
     }
 }
 
+/// A required number in a query.
+fn query_number<T: std::str::FromStr>(qs: &QString, name: &str) -> Result<T, ApiError> {
+    let value = qs
+        .get(name)
+        .ok_or_else(|| ApiError::bad_request(format!("{} is required", name)))?;
+    value
+        .parse::<T>()
+        .map_err(|_| ApiError::bad_request(format!("Invalid {}: {}", name, value)))
+}
+
 async fn get_read(req: HttpRequest, item: web::Data<RwLock<Item>>) -> Result<HttpResponse> {
     let qs = QString::from(req.query_string());
-    let format = qs.get("format").unwrap().clone(); // &query.format.clone();
-    let params = qs.get("params").unwrap().clone();
-    let x = qs.get("x").and_then(|t| t.parse::<i32>().ok()).unwrap();
-    let y = qs.get("y").and_then(|t| t.parse::<i32>().ok()).unwrap();
-    let index = qs
-        .get("index")
-        .and_then(|t| t.parse::<usize>().ok())
-        .unwrap_or(0);
-    let prefetch = qs.get("params").is_some();
-    let hash: u64 = calculate_hash(&RequestBody {
-        format: format.to_string(),
-        params: params.to_string(),
-        prefetch,
-    });
+    let (format, params) = query_params(&qs)?;
+    let x = query_number::<i32>(&qs, "x")?;
+    let y = query_number::<i32>(&qs, "y")?;
+    let index = match qs.get("index") {
+        Some(_) => query_number::<usize>(&qs, "index")?,
+        None => 0,
+    };
+    let prefetch = true;
     let data = item.read().unwrap();
-    let cache_dir = &data.cache_dir;
-    let path_string = format!("{}/{}.json", cache_dir, hash);
-    match File::open(path_string) {
-        Ok(file) => {
+    let hash = request_key(
+        &data.args,
+        &data.inputs,
+        &RequestBody {
+            format: format.to_string(),
+            params: params.to_string(),
+            prefetch,
+        },
+    );
+    match data.cache.get(hash, "json").map(File::open) {
+        Some(Ok(file)) => {
             let reader = BufReader::new(file);
 
-            let areas: Vec<Area> = serde_json::from_reader(reader).unwrap();
-            let read_tree = ReadTree::new(areas[index].clone());
+            let areas: Vec<Area> = serde_json::from_reader(reader).map_err(ApiError::internal)?;
+            let area = areas
+                .get(index)
+                .ok_or_else(|| ApiError::not_found(format!("No such index: {}", index)))?;
+            let read_tree = ReadTree::new(area.clone());
             let read = read_tree.find(x, y);
 
             Ok(HttpResponse::Ok().json(read))
         }
-        _ => Err(ErrorNotFound("No JSON File Available".to_string())),
+        _ => Err(ApiError::not_found("No JSON File Available").into()),
     }
 }
 
+async fn get_session(req: HttpRequest, item: web::Data<RwLock<Item>>) -> Result<HttpResponse> {
+    let qs = QString::from(req.query_string());
+    let format = qs.get("format").unwrap_or("png");
+    let params = qs.get("params").unwrap_or("");
+    let prefetch = qs.get("params").is_some();
+    let (hash, args, checksums) = {
+        let data = item.read().unwrap();
+        let hash = request_key(
+            &data.args,
+            &data.inputs,
+            &RequestBody {
+                format: format.to_string(),
+                params: params.to_string(),
+                prefetch,
+            },
+        );
+        let args = request_args(
+            &data.args,
+            &data.inputs,
+            params,
+            format!("{:016x}.{}", hash, format),
+        )?;
+        (hash, args, data.checksums.clone())
+    };
+    // The session regenerates the view on the command line instead of the server.
+    let args = SERVER_OPTIONS
+        .iter()
+        .fold(args, |args, t| remove_option(args, t.0, t.1, t.2));
+    let matches =
+        get_matches_from(args.clone()).map_err(|e| ApiError::bad_request(e.to_string()))?;
+    // Inputs are hashed in the blocking thread pool, only once unless they are modified.
+    let session = web::block(move || {
+        Session::with_inputs(args, &matches, |path| checksums.get(path)).and_then(|t| t.to_yaml())
+    })
+    .await
+    .map_err(ApiError::internal)?
+    .map_err(ApiError::internal)?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/x-yaml")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!("{:016x}.yaml", hash))],
+        })
+        .body(session))
+}
+
+/// Purges the render cache.
+async fn delete_cache(item: web::Data<RwLock<Item>>) -> Result<HttpResponse> {
+    purge(&item.read().unwrap().cache)
+}
+
+/// Parses `range`, `sample` and `params` (options of vis such as filters) of an API request.
+fn api_request(
+    req: &HttpRequest,
+    args: &[String],
+    inputs: &[String],
+    cache_dir: &str,
+) -> Result<(ArgMatches, StringRegion, Option<u64>)> {
+    let qs = QString::from(req.query_string());
+    let range = qs
+        .get("range")
+        .ok_or_else(|| ApiError::bad_request("range is required"))?;
+    let string_range = StringRegion::new(range)
+        .map_err(|e| ApiError::bad_request(format!("Invalid range {}: {:?}", range, e)))?;
+    let sample_id = match qs.get("sample") {
+        Some(t) => Some(
+            t.parse::<u64>()
+                .map_err(|_| ApiError::bad_request(format!("Invalid sample: {}", t)))?,
+        ),
+        None => None,
+    };
+    let args = request_args(
+        args,
+        inputs,
+        qs.get("params").unwrap_or(""),
+        format!("{}/api.png", cache_dir),
+    )?;
+    let matches = get_matches_from(args)
+        .map_err(|e| ApiError::bad_request(format!("parameter error: {}", e)))?;
+    Ok((matches, string_range, sample_id))
+}
+
+/// Returns reads on the range with their packing rows as JSON, without drawing.
+async fn get_api_reads<T: 'static + ChromosomeBufferTrait + Send + Sync>(
+    req: HttpRequest,
+    item: web::Data<RwLock<Item>>,
+    snapshot: web::Data<RwLock<Arc<Snapshot>>>,
+    buffer: web::Data<RwLock<T>>,
+) -> Result<HttpResponse> {
+    let (matches, string_range, sample_id) = {
+        let data = item.read().unwrap();
+        api_request(&req, &data.args, &data.inputs, data.cache.dir())?
+    };
+    let range = string_range.clone();
+    let snapshot =
+        web::block(move || Snapshot::load_with_options(&matches, &range, &snapshot, &buffer))
+            .await
+            .map_err(ApiError::internal)?
+            .map_err(ApiError::internal)?;
+    Ok(HttpResponse::Ok().json(api::reads(
+        &snapshot.list,
+        &snapshot.vis.index_list,
+        &string_range,
+        sample_id,
+    )))
+}
+
+/// Returns depths and alleles on the range as JSON, without drawing.
+async fn get_api_coverage<T: 'static + ChromosomeBufferTrait + Send + Sync>(
+    req: HttpRequest,
+    item: web::Data<RwLock<Item>>,
+    snapshot: web::Data<RwLock<Arc<Snapshot>>>,
+    buffer: web::Data<RwLock<T>>,
+) -> Result<HttpResponse> {
+    let (matches, string_range, sample_id) = {
+        let data = item.read().unwrap();
+        api_request(&req, &data.args, &data.inputs, data.cache.dir())?
+    };
+    let range = string_range.clone();
+    let snapshot =
+        web::block(move || Snapshot::load_with_options(&matches, &range, &snapshot, &buffer))
+            .await
+            .map_err(ApiError::internal)?
+            .map_err(ApiError::internal)?;
+    Ok(HttpResponse::Ok().json(api::coverage(&snapshot.vis.freq, &string_range, sample_id)))
+}
+
+/// Returns the references with their lengths as JSON.
+pub async fn get_api_header<T: 'static + ChromosomeBufferTrait>(
+    buffer: web::Data<RwLock<T>>,
+) -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(buffer.read().unwrap().references()))
+}
+
+/// Returns the sample ids, their file names and local header types as JSON.
+pub async fn get_api_samples<T: 'static + ChromosomeBufferTrait>(
+    buffer: web::Data<RwLock<T>>,
+) -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(buffer.read().unwrap().samples()))
+}
+
+/// Returns the version and supported formats and presets as JSON.
+pub async fn get_api_info() -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(api::ApiInfo::new()))
+}
+
 //
-async fn get_index<T: 'static + ChromosomeBufferTrait>(
+async fn get_index<T: 'static + ChromosomeBufferTrait + Send + Sync>(
     req: HttpRequest,
     item: web::Data<RwLock<Item>>,
-    vis: web::Data<RwLock<Vis>>,
-    list: web::Data<RwLock<Vec<(u64, Record)>>>,
-    list_btree: web::Data<RwLock<ReadBuffer>>,
+    snapshot: web::Data<RwLock<Arc<Snapshot>>>,
     buffer: web::Data<RwLock<T>>,
+    renders: web::Data<Renders>,
     //query: web::Query<RequestBody>
 ) -> Result<NamedFile> {
     let qs = QString::from(req.query_string());
-    let format = qs.get("format").unwrap().clone(); // &query.format.clone();
-    let params = qs.get("params").unwrap().clone();
-    let prefetch = qs.get("params").is_some();
-    let hash: u64 = calculate_hash(&RequestBody {
-        format: format.to_string(),
-        params: params.to_string(),
-        prefetch,
-    });
-    return index2(
+    let (format, params) = query_params(&qs)?;
+    let prefetch = true;
+    let hash = {
+        let data = item.read().unwrap();
+        request_key(
+            &data.args,
+            &data.inputs,
+            &RequestBody {
+                format: format.to_string(),
+                params: params.to_string(),
+                prefetch,
+            },
+        )
+    };
+    index2(
         item,
-        vis,
-        list,
-        list_btree,
+        snapshot,
         buffer,
+        renders,
         format.to_string(),
         params.to_string(),
         prefetch,
         hash,
-    );
+    )
+    .await
 }
 
-async fn index<T: 'static + ChromosomeBufferTrait>(
+async fn index<T: 'static + ChromosomeBufferTrait + Send + Sync>(
     item: web::Data<RwLock<Item>>,
-    vis: web::Data<RwLock<Vis>>,
-    list: web::Data<RwLock<Vec<(u64, Record)>>>,
-    list_btree: web::Data<RwLock<ReadBuffer>>,
+    snapshot: web::Data<RwLock<Arc<Snapshot>>>,
     buffer: web::Data<RwLock<T>>,
+    renders: web::Data<Renders>,
     request_body: web::Json<RequestBody>,
 ) -> Result<NamedFile> {
     let format = &request_body.format.clone();
     let params = &request_body.params.clone();
     let prefetch = &request_body.prefetch.clone();
-    let hash: u64 = calculate_hash(&request_body.into_inner());
-    return index2(
+    let hash = {
+        let data = item.read().unwrap();
+        request_key(&data.args, &data.inputs, &request_body)
+    };
+    index2(
         item,
-        vis,
-        list,
-        list_btree,
+        snapshot,
         buffer,
+        renders,
         format.to_string(),
         params.to_string(),
         *prefetch,
         hash,
-    );
+    )
+    .await
 }
 
-fn index2<T: 'static + ChromosomeBufferTrait>(
+async fn index2<T: 'static + ChromosomeBufferTrait + Send + Sync>(
     item: web::Data<RwLock<Item>>,
-    vis: web::Data<RwLock<Vis>>,
-    list: web::Data<RwLock<Vec<(u64, Record)>>>,
-    list_btree: web::Data<RwLock<ReadBuffer>>,
+    snapshot: web::Data<RwLock<Arc<Snapshot>>>,
     buffer: web::Data<RwLock<T>>,
+    renders: web::Data<Renders>,
     format: String,
     params: String,
     prefetch: bool,
     hash: u64,
 ) -> Result<NamedFile> {
-    let data = item.read().unwrap();
-    let cache_dir = &data.cache_dir;
-    let args = &data.args;
+    let (range, args, inputs, cache) = {
+        let data = item.read().unwrap();
+        (
+            data.range.clone(),
+            data.args.clone(),
+            data.inputs.clone(),
+            data.cache.clone(),
+        )
+    };
+    check_image_format(&format)?;
     let start = Instant::now();
-    let path_string = format!("{}/{}.{}", cache_dir, hash, format);
+    let path_string = cache.path(hash, &format);
     eprintln!("{} {} {:?}", format, params, path_string);
 
-    match NamedFile::open(path_string.clone()) {
-        Ok(file) => Ok(file.set_content_disposition(ContentDisposition {
+    if let Some(Ok(file)) = cache.get(hash, &format).map(NamedFile::open) {
+        return Ok(file.set_content_disposition(ContentDisposition {
             disposition: DispositionType::Attachment,
             parameters: vec![],
-        })),
-        _ => {
-            if prefetch {
-                let end0 = start.elapsed();
-                eprintln!(
-                    "match named file: {}.{:03} sec.",
-                    end0.as_secs(),
-                    end0.subsec_millis()
-                );
-
-                let end1 = start.elapsed();
-                eprintln!(
-                    "create dir: {}.{:03} sec.",
-                    end1.as_secs(),
-                    end1.subsec_millis()
-                );
-
-                let (matches, string_range) =
-                    id_to_range(&data.range, args, params, path_string.clone()).map_err(|t| {
-                        ErrorBadRequest(
-                            format!("parameter error: {}", t), //String::from("parameter error: " + t.description()),
-                        )
-                    })?;
-                let end2 = start.elapsed();
-                eprintln!(
-                    "id_to_range: {}.{:03} sec.",
-                    end2.as_secs(),
-                    end2.subsec_millis()
-                );
-                if !buffer
-                    .read()
-                    .unwrap()
-                    .included_string_local(&string_range, &list_btree.read().unwrap())
-                {
-                    // TODO() This ignores
-                    let endx = start.elapsed();
-                    eprintln!(
-                        "Fallback to reload: {}.{:03} sec.",
-                        endx.as_secs(),
-                        endx.subsec_millis()
-                    );
-                    //let (mut list, mut list_btree) = &*;
-                    {
-                        buffer.write().unwrap().retrieve(
-                            &string_range,
-                            &mut list.write().unwrap(),
-                            &mut list_btree.write().unwrap(),
-                        );
-                    }
-                    let endy = start.elapsed();
-                    eprintln!(
-                        "Fallback to reload2: {}.{:03} sec.",
-                        endy.as_secs(),
-                        endy.subsec_millis()
-                    );
-                    let new_vis = buffer.read().unwrap().vis(
-                        &matches,
-                        &string_range,
-                        &mut list.write().unwrap(),
-                        &mut list_btree.write().unwrap(),
-                    );
-                    let mut old_vis = vis.write().unwrap();
-                    *old_vis = new_vis.unwrap();
-                    let endz = start.elapsed();
-                    eprintln!(
-                        "Fallback to reload3: {}.{:03} sec.",
-                        endz.as_secs(),
-                        endz.subsec_millis()
-                    );
-                }
-
-                let data = vis.read().unwrap();
-                let ann = &data.annotation;
-                let freq = &data.freq;
-                let compressed_list = &data.compressed_list;
-                let index_list = &data.index_list;
-                let supplementary_list = &data.supplementary_list;
-                let prev_index = data.prev_index;
-
-                // If the end is exceeds the prefetch region, raise error.
-                // let arg_vec = vec!["ghb", "vis", "-t", "1", "-r",  "parse"];
-                bam_record_vis(
-                    &matches,
-                    vec![VisRef::new(
-                        string_range,
-                        &list.read().unwrap(),
-                        ann,
-                        freq,
-                        compressed_list,
-                        index_list,
-                        prev_index,
-                        supplementary_list,
-                    )],
-                    |_| None,
-                )
-                .unwrap();
-                let end3 = start.elapsed();
-                eprintln!(
-                    "img_saved: {}.{:03} sec.",
-                    end3.as_secs(),
-                    end3.subsec_millis()
-                );
-                // bam_vis(matches, 1);
-            } else {
-                //Visualization for unprefetch data.
-                let (matches, args) =
-                    id_to_range_ab_initio(params, path_string.clone()).map_err(|t| {
-                        ErrorBadRequest(
-                            format!("parameter error: {}", t), //String::from("parameter error: " + t.description()),
-                        )
-                    })?;
-                let threads = matches
-                    .value_of("threads")
-                    .and_then(|t| t.parse::<u16>().ok())
-                    .unwrap_or(1u16);
-                match matches.is_present("INPUT") {
-                    true => vis_query(&matches, args, threads).unwrap(),
-                    false => bam_vis(&matches, args, threads).unwrap(),
-                }
+        }));
+    }
+    let end0 = start.elapsed();
+    eprintln!(
+        "match named file: {}.{:03} sec.",
+        end0.as_secs(),
+        end0.subsec_millis()
+    );
+    // Parameters are parsed here to respond errors as bad requests, and drawn in the blocking
+    // thread pool without locking the loaded reads.
+    let draw: Box<dyn FnOnce() -> Result<(), String> + Send> = if prefetch {
+        let (matches, string_range) =
+            id_to_range(&range, &args, &inputs, &params, path_string.clone())?;
+        let end2 = start.elapsed();
+        eprintln!(
+            "id_to_range: {}.{:03} sec.",
+            end2.as_secs(),
+            end2.subsec_millis()
+        );
+        Box::new(move || {
+            let snapshot = Snapshot::load(&matches, &string_range, &snapshot, &buffer)
+                .map_err(|e| e.to_string())?;
+            snapshot
+                .render(&matches, string_range)
+                .map_err(|e| e.to_string())
+        })
+    } else {
+        //Visualization for unprefetch data.
+        let (matches, args) = id_to_range_ab_initio(&inputs, &params, path_string.clone())?;
+        Box::new(move || {
+            let threads = matches
+                .value_of("threads")
+                .and_then(|t| t.parse::<u16>().ok())
+                .unwrap_or(1u16);
+            match matches.is_present("INPUT") {
+                true => vis_query(&matches, args, threads),
+                false => bam_vis(&matches, args, threads),
             }
-            Ok(
-                NamedFile::open(path_string)?.set_content_disposition(ContentDisposition {
-                    disposition: DispositionType::Attachment,
-                    parameters: vec![],
-                }),
-            )
-        }
+            .map_err(|e| e.to_string())
+        })
+    };
+    // Identical requests in flight wait for the first one instead of drawing again.
+    web::block(move || renders.run(hash, draw))
+        .await
+        .map_err(ApiError::internal)?
+        .map_err(ApiError::internal)?;
+    let end3 = start.elapsed();
+    eprintln!(
+        "img_saved: {}.{:03} sec.",
+        end3.as_secs(),
+        end3.subsec_millis()
+    );
+    let file = NamedFile::open(path_string).map_err(ApiError::internal)?;
+    if let Err(e) = cache.evict() {
+        eprintln!("Failed to evict the cache: {}", e);
     }
+    Ok(file.set_content_disposition(ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters: vec![],
+    }))
 }
 
 #[actix_rt::main]
@@ -691,16 +1056,14 @@ pub async fn rest_server<T: 'static + ChromosomeBufferTrait + Send + Sync>(
     let mut list = vec![];
     let mut list_btree = (0, BTreeSet::new());
     let bind = matches.value_of("web").unwrap_or(&"0.0.0.0:4000");
-    let basic_auth: Option<(String, String)> = matches
-        .value_of("basic-auth")
-        .and_then(|t| t.split(':').map(|a| a.to_string()).collect_tuple());
-    let auth_condition = basic_auth.is_some();
+    let credentials = Credentials::from_matches(&matches)?;
+    let auth_condition = credentials.is_some();
+    let credentials = web::Data::new(credentials.unwrap_or_default());
+    let tls = tls_config(&matches)?;
 
     let uds_bind = matches.value_of("unix-socket");
     buffer.retrieve(&prefetch_range, &mut list, &mut list_btree);
-    let vis = buffer
-        .vis(&matches, &prefetch_range, &mut list, &mut list_btree)
-        .unwrap();
+    let vis = buffer.vis(&matches, &prefetch_range, &mut list, &mut list_btree)?;
     let view_range = if matches.is_present("whole-chromosome") {
         StringRegion {
             path: prefetch_range.path,
@@ -711,24 +1074,20 @@ pub async fn rest_server<T: 'static + ChromosomeBufferTrait + Send + Sync>(
         prefetch_range
     };
 
-    let mut rng = rand::thread_rng();
-    let cache_dir = matches
-        .value_of("cache-dir")
-        .map(|a| a.to_string())
-        .unwrap_or_else(|| rng.gen::<u32>().to_string());
+    let cache = RenderCache::from_matches(&matches)?;
     let static_dir = matches
         .value_of("static-dir")
         .map(|a| a.to_string())
         .unwrap_or_else(|| "static".to_string());
 
-    if let Err(e) = fs::create_dir(&cache_dir) {
-        panic!("{}: {}", e, &cache_dir)
-    }
     println!("REST Server is running on {}", bind);
     // Create some global state prior to building the server
 
-    let counter = web::Data::new(RwLock::new(Item::new(view_range, args, cache_dir)));
+    let inputs = cache::input_files(&matches);
+    let counter = web::Data::new(RwLock::new(Item::new(view_range, args, cache, inputs)));
     let buffer = web::Data::new(RwLock::new(buffer));
+    let snapshot = web::Data::new(RwLock::new(Arc::new(Snapshot::new(list, list_btree, vis))));
+    let renders = web::Data::new(Renders::new());
 
     let cross_origin_bool = matches.is_present("production");
 
@@ -740,24 +1099,37 @@ pub async fn rest_server<T: 'static + ChromosomeBufferTrait + Send + Sync>(
             Cors::permissive()
         };
 
-        let list = RwLock::new(list.clone());
-        let list_btree = RwLock::new(list_btree.clone()); //buffer =
-        let vis = RwLock::new(vis.clone());
-        let auth = HttpAuthentication::basic(basic_auth_validator);
+        let auth = HttpAuthentication::with_fn(auth_validator);
 
         actix_web::App::new()
-            .app_data(web::Data::new(list))
-            .app_data(web::Data::new(list_btree))
             .app_data(counter.clone())
-            .app_data(basic_auth.clone())
-            .app_data(web::Data::new(vis)) //.app_data(vis.clone())
+            .app_data(
+                web::JsonConfig::default().error_handler(|e, _| ApiError::bad_request(e).into()),
+            )
+            .app_data(credentials.clone())
+            .app_data(snapshot.clone())
             .app_data(buffer.clone())
+            .app_data(renders.clone())
             .route("/", web::post().to(index::<T>))
             .route("/", web::get().to(get_index::<T>))
             .route("/json", web::get().to(get_json))
             .route("/read", web::get().to(get_read))
+            .route("/session", web::get().to(get_session))
+            .route("/cache", web::delete().to(delete_cache))
+            .route("/api/reads", web::get().to(get_api_reads::<T>))
+            .route("/api/coverage", web::get().to(get_api_coverage::<T>))
+            .route("/api/header", web::get().to(get_api_header::<T>))
+            .route("/api/samples", web::get().to(get_api_samples::<T>))
+            .route("/api/info", web::get().to(get_api_info))
             .route("/static/api/json", web::get().to(get_json))
             .route("/static/api/read", web::get().to(get_read))
+            .route("/static/api/session", web::get().to(get_session))
+            .route("/static/api/cache", web::delete().to(delete_cache))
+            .route("/static/api/reads", web::get().to(get_api_reads::<T>))
+            .route("/static/api/coverage", web::get().to(get_api_coverage::<T>))
+            .route("/static/api/header", web::get().to(get_api_header::<T>))
+            .route("/static/api/samples", web::get().to(get_api_samples::<T>))
+            .route("/static/api/info", web::get().to(get_api_info))
             .route("/static/api", web::get().to(get_index::<T>))
             .service(
                 actix_files::Files::new("/static", static_dir.clone()).index_file("index.html"),
@@ -772,49 +1144,517 @@ pub async fn rest_server<T: 'static + ChromosomeBufferTrait + Send + Sync>(
             .workers(threads as usize)
             .run()
             .await
+    } else if let Some(tls) = tls {
+        server
+            .bind_rustls(bind, tls)?
+            .workers(threads as usize)
+            .run()
+            .await
     } else {
         server.bind(bind)?.workers(threads as usize).run().await
     }
 }
-fn validate_credentials(
-    user_id: &str,
-    user_password: &str,
-    basic_auth: &Option<BasicAuthTuple>,
-) -> Result<bool, std::io::Error> {
-    if let Some(basic_auth) = basic_auth {
-        if user_id.eq(&basic_auth.0) && user_password.eq(&basic_auth.1) {
-            return Ok(true);
+
+/// Buffers and the loaded reads of a dataset in the catalog.
+struct DatasetState<B: ?Sized> {
+    args: Vec<String>,
+    inputs: Vec<String>,
+    snapshot: RwLock<Arc<Snapshot>>,
+    buffer: RwLock<B>,
+}
+
+type SharedDataset = Arc<DatasetState<dyn ChromosomeBufferTrait + Send + Sync>>;
+
+impl<B: ChromosomeBufferTrait> DatasetState<B> {
+    fn new(args: Vec<String>, inputs: Vec<String>, buffer: B) -> Self {
+        DatasetState {
+            args,
+            inputs,
+            snapshot: RwLock::new(Arc::new(Snapshot::empty())),
+            buffer: RwLock::new(buffer),
         }
-        Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "Authentication failed!",
-        ))
+    }
+}
+
+fn open_dataset(
+    dataset: &Dataset,
+    args: &[String],
+) -> Result<SharedDataset, Box<dyn std::error::Error>> {
+    let args = dataset.args(args);
+    let matches = get_matches_from(
+        args.iter()
+            .filter(|t| *t != "-P")
+            .skip_while(|t| *t != "vis")
+            .cloned()
+            .collect(),
+    )?;
+    let inputs = cache::input_files(&matches);
+    if dataset.is_bam() {
+        let reader = bam::IndexedReader::build()
+            .additional_threads(0)
+            .from_path(&dataset.path)?;
+        let buffer = ghi::simple_bam_buffer::ChromosomeBuffer::new(reader, matches);
+        Ok(Arc::new(DatasetState::new(args, inputs, buffer)))
     } else {
-        Ok(true)
+        let reader =
+            ghi::reader::IndexedReader::from_path_with_additional_threads(&dataset.path, 0)?;
+        let buffer = ghi::simple_buffer::ChromosomeBuffer::new(reader, matches);
+        Ok(Arc::new(DatasetState::new(args, inputs, buffer)))
     }
 }
 
-async fn basic_auth_validator(
+/// Datasets of the catalog, opened on the first request.
+struct Datasets {
+    catalog: Catalog,
+    args: Vec<String>,
+    cache: RenderCache,
+    /// Only the recency order is locked, so that opening a dataset does not stall the others.
+    open: Mutex<Lru<SharedDataset>>,
+}
+
+impl Datasets {
+    fn get(&self, id: &str) -> Result<SharedDataset, ApiError> {
+        if let Some(state) = self.open.lock().unwrap().get(id) {
+            return Ok(state.clone());
+        }
+        let dataset = self
+            .catalog
+            .get(id)
+            .ok_or_else(|| ApiError::not_found(format!("No such dataset: {}", id)))?;
+        let state = open_dataset(dataset, &self.args)
+            .map_err(|e| ApiError::internal(format!("Failed to open {}: {}", id, e)))?;
+        let mut open = self.open.lock().unwrap();
+        // Another request may have opened the dataset meanwhile.
+        if let Some(state) = open.get(id) {
+            return Ok(state.clone());
+        }
+        // Requests on an evicted dataset keep it until they finish.
+        for (id, _) in open.insert(id.to_string(), state.clone()) {
+            eprintln!("Closed dataset: {}", id);
+        }
+        Ok(state)
+    }
+}
+
+/// Returns the dataset of the request, opening its index in the blocking thread pool.
+async fn request_dataset(
+    req: &HttpRequest,
+    datasets: &web::Data<Datasets>,
+) -> Result<SharedDataset> {
+    let id = req.match_info().get("id").unwrap_or("").to_string();
+    let datasets = datasets.clone();
+    let state = web::block(move || datasets.get(&id))
+        .await
+        .map_err(ApiError::internal)??;
+    Ok(state)
+}
+
+/// Lists the datasets of the catalog.
+async fn get_datasets(req: HttpRequest, datasets: web::Data<Datasets>) -> Result<HttpResponse> {
+    let access = req.extensions().get::<Access>().cloned();
+    let open = datasets.open.lock().unwrap();
+    let list: Vec<api::ApiDataset> = datasets
+        .catalog
+        .datasets
+        .iter()
+        .filter(|t| access.as_ref().map_or(true, |a| a.allows(&t.id)))
+        .map(|t| api::ApiDataset {
+            id: t.id.clone(),
+            format: if t.is_bam() { "bam" } else { "ghb" }.to_string(),
+            open: open.contains(&t.id),
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(list))
+}
+
+/// Draws a dataset in the same way as `GET /`, where `params` must contain the range.
+async fn get_dataset_index(
+    req: HttpRequest,
+    datasets: web::Data<Datasets>,
+    renders: web::Data<Renders>,
+) -> Result<NamedFile> {
+    let state = request_dataset(&req, &datasets).await?;
+    let qs = QString::from(req.query_string());
+    let (format, params) = query_params(&qs)?;
+    check_image_format(format)?;
+    let hash = request_key(
+        &state.args,
+        &state.inputs,
+        &RequestBody {
+            format: format.to_string(),
+            params: params.to_string(),
+            prefetch: true,
+        },
+    );
+    let cache = datasets.cache.clone();
+    let path_string = cache.path(hash, format);
+    if cache.get(hash, format).is_none() {
+        let (matches, string_range) = parse_request(request_args(
+            &state.args,
+            &state.inputs,
+            params,
+            path_string.clone(),
+        )?)?;
+        web::block(move || {
+            renders.run(hash, || {
+                let snapshot =
+                    Snapshot::load(&matches, &string_range, &state.snapshot, &state.buffer)
+                        .map_err(|e| e.to_string())?;
+                snapshot
+                    .render(&matches, string_range)
+                    .map_err(|e| e.to_string())
+            })
+        })
+        .await
+        .map_err(ApiError::internal)?
+        .map_err(ApiError::internal)?;
+    }
+    let file = NamedFile::open(path_string).map_err(ApiError::internal)?;
+    if let Err(e) = cache.evict() {
+        eprintln!("Failed to evict the cache: {}", e);
+    }
+    Ok(file.set_content_disposition(ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters: vec![],
+    }))
+}
+
+/// `/api/reads` of a dataset.
+async fn get_dataset_reads(
+    req: HttpRequest,
+    datasets: web::Data<Datasets>,
+) -> Result<HttpResponse> {
+    let state = request_dataset(&req, &datasets).await?;
+    let cache_dir = datasets.cache.dir().to_string();
+    let (matches, string_range, sample_id) =
+        api_request(&req, &state.args, &state.inputs, &cache_dir)?;
+    let range = string_range.clone();
+    let snapshot = web::block(move || {
+        Snapshot::load_with_options(&matches, &range, &state.snapshot, &state.buffer)
+    })
+    .await
+    .map_err(ApiError::internal)?
+    .map_err(ApiError::internal)?;
+    Ok(HttpResponse::Ok().json(api::reads(
+        &snapshot.list,
+        &snapshot.vis.index_list,
+        &string_range,
+        sample_id,
+    )))
+}
+
+/// `/api/coverage` of a dataset.
+async fn get_dataset_coverage(
+    req: HttpRequest,
+    datasets: web::Data<Datasets>,
+) -> Result<HttpResponse> {
+    let state = request_dataset(&req, &datasets).await?;
+    let cache_dir = datasets.cache.dir().to_string();
+    let (matches, string_range, sample_id) =
+        api_request(&req, &state.args, &state.inputs, &cache_dir)?;
+    let range = string_range.clone();
+    let snapshot = web::block(move || {
+        Snapshot::load_with_options(&matches, &range, &state.snapshot, &state.buffer)
+    })
+    .await
+    .map_err(ApiError::internal)?
+    .map_err(ApiError::internal)?;
+    Ok(HttpResponse::Ok().json(api::coverage(&snapshot.vis.freq, &string_range, sample_id)))
+}
+
+/// `/api/header` of a dataset.
+async fn get_dataset_header(
+    req: HttpRequest,
+    datasets: web::Data<Datasets>,
+) -> Result<HttpResponse> {
+    let state = request_dataset(&req, &datasets).await?;
+    let references = state.buffer.read().unwrap().references();
+    Ok(HttpResponse::Ok().json(references))
+}
+
+/// `/api/samples` of a dataset.
+async fn get_dataset_samples(
+    req: HttpRequest,
+    datasets: web::Data<Datasets>,
+) -> Result<HttpResponse> {
+    let state = request_dataset(&req, &datasets).await?;
+    let samples = state.buffer.read().unwrap().samples();
+    Ok(HttpResponse::Ok().json(samples))
+}
+
+/// Purges the cache shared by all datasets, which is allowed only to users of all datasets.
+async fn delete_dataset_cache(
+    req: HttpRequest,
+    datasets: web::Data<Datasets>,
+) -> Result<HttpResponse> {
+    let access = req.extensions().get::<Access>().cloned();
+    if let Some(access) = access {
+        if !access.is_unrestricted() {
+            return Err(ApiError::forbidden(format!(
+                "{} is not allowed to purge the cache",
+                access.name
+            ))
+            .into());
+        }
+    }
+    purge(&datasets.cache)
+}
+
+/// Serves the datasets of `--catalog` at `/datasets/{id}`.
+#[actix_rt::main]
+pub async fn catalog_server(
+    matches: ArgMatches,
+    args: Vec<String>,
+    threads: u16,
+) -> std::io::Result<()> {
+    use actix_web::{web, HttpServer};
+    let catalog = Catalog::from_path(matches.value_of("catalog").unwrap())?;
+    let max_open_datasets = matches
+        .value_of("max-open-datasets")
+        .and_then(|t| t.parse::<usize>().ok())
+        .unwrap_or(8);
+    let bind = matches.value_of("web").unwrap_or(&"0.0.0.0:4000");
+    let credentials = Credentials::from_matches(&matches)?;
+    let auth_condition = credentials.is_some();
+    let credentials = web::Data::new(credentials.unwrap_or_default());
+    let tls = tls_config(&matches)?;
+    let uds_bind = matches.value_of("unix-socket");
+
+    let cache = RenderCache::from_matches(&matches)?;
+    let static_dir = matches
+        .value_of("static-dir")
+        .map(|a| a.to_string())
+        .unwrap_or_else(|| "static".to_string());
+
+    println!(
+        "REST Server is running on {} with {} datasets",
+        bind,
+        catalog.datasets.len()
+    );
+    let renders = web::Data::new(Renders::new());
+    let datasets = web::Data::new(Datasets {
+        catalog,
+        args,
+        cache,
+        open: Mutex::new(Lru::new(max_open_datasets)),
+    });
+
+    let cross_origin_bool = matches.is_present("production");
+
+    let server = HttpServer::new(move || {
+        let cross_origin = if cross_origin_bool {
+            Cors::default()
+        } else {
+            Cors::permissive()
+        };
+        let auth = HttpAuthentication::with_fn(auth_validator);
+
+        actix_web::App::new()
+            .app_data(datasets.clone())
+            .app_data(renders.clone())
+            .app_data(credentials.clone())
+            .route("/datasets", web::get().to(get_datasets))
+            .route("/datasets/{id}", web::get().to(get_dataset_index))
+            .route("/datasets/{id}/api/reads", web::get().to(get_dataset_reads))
+            .route(
+                "/datasets/{id}/api/coverage",
+                web::get().to(get_dataset_coverage),
+            )
+            .route(
+                "/datasets/{id}/api/header",
+                web::get().to(get_dataset_header),
+            )
+            .route(
+                "/datasets/{id}/api/samples",
+                web::get().to(get_dataset_samples),
+            )
+            .route("/api/info", web::get().to(get_api_info))
+            .route("/cache", web::delete().to(delete_dataset_cache))
+            .service(
+                actix_files::Files::new("/static", static_dir.clone()).index_file("index.html"),
+            )
+            .wrap(Logger::default())
+            .wrap(cross_origin)
+            .wrap(Condition::new(auth_condition, auth))
+    });
+    if let Some(uds_bind) = uds_bind {
+        server
+            .bind_uds(uds_bind)?
+            .workers(threads as usize)
+            .run()
+            .await
+    } else if let Some(tls) = tls {
+        server
+            .bind_rustls(bind, tls)?
+            .workers(threads as usize)
+            .run()
+            .await
+    } else {
+        server.bind(bind)?.workers(threads as usize).run().await
+    }
+}
+
+/// TLS configuration given by `tls-cert` and `tls-key` in PEM, or `None` for plain HTTP.
+/// Unix sockets are not served over TLS.
+pub fn tls_config(matches: &ArgMatches) -> std::io::Result<Option<rustls::ServerConfig>> {
+    let (cert, key) = match (matches.value_of("tls-cert"), matches.value_of("tls-key")) {
+        (Some(cert), Some(key)) => (cert, key),
+        (None, None) => return Ok(None),
+        _ => {
+            return Err(IoError::new(
+                ErrorKind::InvalidInput,
+                "Both --tls-cert and --tls-key are required for TLS",
+            ))
+        }
+    };
+    if matches.is_present("unix-socket") {
+        return Err(IoError::new(
+            ErrorKind::InvalidInput,
+            "--tls-cert is not supported on --unix-socket",
+        ));
+    }
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert)?))?
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect();
+    // The first private key in PKCS #8, PKCS #1 (RSA) or SEC1 (EC).
+    let private_key = rustls_pemfile::read_all(&mut BufReader::new(File::open(key)?))?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(t)
+            | rustls_pemfile::Item::RSAKey(t)
+            | rustls_pemfile::Item::ECKey(t) => Some(rustls::PrivateKey(t)),
+            _ => None,
+        })
+        .ok_or_else(|| {
+            IoError::new(ErrorKind::InvalidData, format!("No private key in {}", key))
+        })?;
+    rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, private_key)
+        .map(Some)
+        .map_err(|e| IoError::new(ErrorKind::InvalidData, format!("{}: {}", key, e)))
+}
+
+/// Authenticates a user by basic authentication or a token by bearer authentication, and
+/// passes the datasets allowed to them as `Access` in the request extensions.
+pub async fn auth_validator(
     req: ServiceRequest,
-    credentials: BasicAuth,
+    _: HttpRequest,
 ) -> Result<ServiceRequest, (actix_web::Error, ServiceRequest)> {
     let config = req
         .app_data::<Config>()
         .cloned()
         .unwrap_or_else(Default::default);
-    let item = req.app_data::<Option<BasicAuthTuple>>().unwrap();
-    match validate_credentials(
-        credentials.user_id(),
-        credentials.password().unwrap().trim(),
-        item,
-    ) {
-        Ok(res) => {
-            if res {
-                Ok(req)
+    let access = match req.app_data::<web::Data<Credentials>>().cloned() {
+        Some(credentials) => {
+            if let Ok(auth) = Authorization::<Bearer>::parse(&req) {
+                credentials.bearer(auth.as_ref().token())
+            } else if let Ok(auth) = Authorization::<Basic>::parse(&req) {
+                // Passwords are checked by a slow hash in the blocking thread pool.
+                let auth = auth.into_scheme();
+                web::block(move || {
+                    credentials.basic(auth.user_id(), auth.password().unwrap_or_default().trim())
+                })
+                .await
+                .unwrap_or(None)
             } else {
-                Err((AuthenticationError::from(config).into(), req))
+                None
             }
         }
-        Err(_) => Err((AuthenticationError::from(config).into(), req)),
+        None => None,
+    };
+    let access = match access {
+        Some(access) => access,
+        None => return Err((AuthenticationError::from(config).into(), req)),
+    };
+    let dataset = req
+        .path()
+        .strip_prefix("/datasets/")
+        .and_then(|t| t.split('/').next());
+    if let Some(dataset) = dataset {
+        if !access.allows(dataset) {
+            let error = ApiError::forbidden(format!(
+                "{} is not allowed to access {}",
+                access.name, dataset
+            ));
+            return Err((error.into(), req));
+        }
+    }
+    req.extensions_mut().insert(access);
+    Ok(req)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{client_params, tls_config};
+    use actix_web::http::StatusCode;
+    use clap::{App, Arg};
+    use std::io::ErrorKind;
+
+    #[test]
+    fn client_options() {
+        let inputs: Vec<String> = vec!["input.bam", "ref.fa", "names.txt"]
+            .into_iter()
+            .map(|t| t.to_string())
+            .collect();
+        let status = |params: &str| {
+            client_params(params, &inputs)
+                .map(|_| ())
+                .map_err(|e| e.status)
+        };
+        // Inputs of the server.
+        assert_eq!(
+            client_params("-r chr1:1-100  -a input.bam --2bit ref.fa", &inputs).unwrap(),
+            ["-r", "chr1:1-100", "-a", "input.bam", "--2bit", "ref.fa"]
+        );
+        assert_eq!(status("input.bam --highlight-reads names.txt"), Ok(()));
+        // Outputs and settings of the server.
+        assert_eq!(status("-o out.png"), Err(StatusCode::FORBIDDEN));
+        assert_eq!(status("--output-dir out"), Err(StatusCode::FORBIDDEN));
+        assert_eq!(status("--catalog ."), Err(StatusCode::FORBIDDEN));
+        // Files which are not inputs of the server.
+        assert_eq!(status("-a /etc/passwd"), Err(StatusCode::FORBIDDEN));
+        assert_eq!(status("-a input.bam other.bam"), Err(StatusCode::FORBIDDEN));
+        assert_eq!(
+            status("--highlight-reads Cargo.toml"),
+            Err(StatusCode::FORBIDDEN)
+        );
+        // Names of reads, which are never read from a file.
+        assert_eq!(status("--highlight-read-names Cargo.toml"), Ok(()));
+        assert_eq!(status("--unknown"), Err(StatusCode::BAD_REQUEST));
+    }
+
+    #[test]
+    fn tls() {
+        let config = |args: &[&str]| {
+            let matches = App::new("vis")
+                .arg(Arg::new("tls-cert").long("tls-cert").takes_value(true))
+                .arg(Arg::new("tls-key").long("tls-key").takes_value(true))
+                .arg(
+                    Arg::new("unix-socket")
+                        .long("unix-socket")
+                        .takes_value(true),
+                )
+                .get_matches_from([&["vis"], args].concat());
+            tls_config(&matches).map(|t| t.is_some())
+        };
+        let error = |args: &[&str]| config(args).unwrap_err().kind();
+        let cert = ["--tls-cert", "test/tls/cert.pem"];
+        assert!(!config(&[]).unwrap());
+        // A self-signed certificate with an EC key in PKCS #8.
+        assert!(config(&[&cert[..], &["--tls-key", "test/tls/key.pem"]].concat()).unwrap());
+        // Missing keys.
+        assert_eq!(error(&cert), ErrorKind::InvalidInput);
+        let key = ["--tls-key", "test/tls/missing.pem"];
+        assert_eq!(error(&[&cert[..], &key].concat()), ErrorKind::NotFound);
+        let key = ["--tls-key", "test/tls/cert.pem"];
+        assert_eq!(error(&[&cert[..], &key].concat()), ErrorKind::InvalidData);
+        // Ed448 keys are not supported by rustls.
+        let key = ["--tls-key", "test/tls/ed448.pem"];
+        assert_eq!(error(&[&cert[..], &key].concat()), ErrorKind::InvalidData);
+        let key = ["--tls-key", "test/tls/key.pem", "--unix-socket", "hgb.sock"];
+        assert_eq!(error(&[&cert[..], &key].concat()), ErrorKind::InvalidInput);
     }
 }
@@ -1,13 +1,20 @@
+use crate::rest_server::{auth_validator, tls_config, ApiError};
+use crate::xyz::{write_tile, Grid};
 use actix_cors::Cors;
 use actix_files::NamedFile;
 use actix_web::http::header::{ContentDisposition, DispositionType};
-use actix_web::{error, middleware::Logger, web, HttpRequest, Responder, Result};
+use actix_web::middleware::Condition;
+use actix_web::{middleware::Logger, web, HttpRequest, Responder, Result};
+use actix_web_httpauth::middleware::HttpAuthentication;
 use bam::Record;
 use clap::{App, AppSettings, Arg, ArgMatches, ArgSettings};
 use genomic_range::StringRegion;
+use ghi::auth::Credentials;
 use ghi::{bed, vis::bam_record_vis, Vis, VisRef};
 use itertools::Itertools;
 use rand::Rng;
+use std::convert::TryFrom;
+use std::path::Path;
 use std::time::Instant;
 use std::{collections::BTreeMap, fs, sync::RwLock};
 
@@ -174,6 +181,9 @@ fn id_to_range(
                     .takes_value(true)
                     .about("Basic authentication on web server (username:password)"),
             )
+            .arg(Arg::new("auth-file").long("auth-file").takes_value(true).about("Authenticate users by Argon2 hashes of their passwords and API tokens by SHA-256 digests in a YAML file for the web server, optionally restricting datasets on --catalog (overrides --basic-auth)"))
+            .arg(Arg::new("tls-cert").long("tls-cert").takes_value(true).requires("tls-key").about("Serve HTTPS on the web server with the certificate chain in PEM"))
+            .arg(Arg::new("tls-key").long("tls-key").takes_value(true).requires("tls-cert").about("Private key in PEM (PKCS #8, RSA or EC) of --tls-cert"))
             .arg(
                 Arg::new("output")
                     .short('o')
@@ -359,94 +369,153 @@ async fn index(
     list: web::Data<RwLock<Vec<(u64, Record)>>>,
     req: HttpRequest,
 ) -> Result<NamedFile> {
+    let zoom: u64 = req
+        .match_info()
+        .query("zoom")
+        .parse()
+        .map_err(|_| ApiError::bad_request("Invalid zoom"))?;
+    let path: u64 = req
+        .match_info()
+        .query("filename")
+        .parse()
+        .map_err(|_| ApiError::bad_request("Invalid filename"))?;
+    let path_string = strip(&data, &list, zoom, path)?;
+    Ok(NamedFile::open(path_string)
+        .map_err(ApiError::internal)?
+        .set_content_disposition(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![],
+        }))
+}
+
+/// Returns the path of the `path`-th tile on the zoom level, which is a horizontal strip of
+/// all rows, drawing it unless cached.
+fn strip(
+    data: &RwLock<Item>,
+    list: &RwLock<Vec<(u64, Record)>>,
+    zoom: u64,
+    path: u64,
+) -> Result<String> {
     let start = Instant::now();
-    let zoom: u64 = req.match_info().query("zoom").parse().unwrap();
-    let path: u64 = req.match_info().query("filename").parse().unwrap();
     let data = data.read().unwrap();
 
     let cache_dir = &data.params.cache_dir;
-    match NamedFile::open(format!("{}/{}/{}_0.png", cache_dir, zoom, path)) {
-        Ok(file) => Ok(file.set_content_disposition(ContentDisposition {
-            disposition: DispositionType::Attachment,
-            parameters: vec![],
-        })),
-        _ => {
-            let end0 = start.elapsed();
-            eprintln!(
-                "match named file: {}.{:03} sec.",
-                end0.as_secs(),
-                end0.subsec_millis()
-            );
-            let params = &data.params;
-            let args = &data.args;
-            let data = &data.vis;
-            let ann = &data.annotation;
-            let freq = &data.freq;
-            let compressed_list = &data.compressed_list;
-            let index_list = &data.index_list;
-            let supplementary_list = &data.supplementary_list;
-            let prev_index = data.prev_index;
+    let path_string = format!("{}/{}/{}_0.png", cache_dir, zoom, path);
+    if Path::new(&path_string).exists() {
+        return Ok(path_string);
+    }
+    let end0 = start.elapsed();
+    eprintln!(
+        "match named file: {}.{:03} sec.",
+        end0.as_secs(),
+        end0.subsec_millis()
+    );
+    let params = &data.params;
+    let args = &data.args;
+    let vis = &data.vis;
 
-            //let min_zoom = 13;
+    //let min_zoom = 13;
 
-            let path_string = format!("{}/{}/{}_0.png", cache_dir, zoom, path);
-            let max_zoom = params.max_zoom as u64;
-            //let min_zoom = ((&data.params).criteria << (max_zoom - zoom)) >= 10000000;
-            let min_zoom = params.min_zoom as u64;
-            if zoom < min_zoom || zoom > max_zoom as u64 {
-                return Err(error::ErrorBadRequest(format!(
-                    "zoom level {} should be between {} and {}",
-                    zoom, min_zoom, max_zoom
-                )));
-            }
-            fs::create_dir_all(format!("{}/{}", cache_dir, zoom))?; //error is permitted.
-            let end1 = start.elapsed();
-            eprintln!(
-                "create dir: {}.{:03} sec.",
-                end1.as_secs(),
-                end1.subsec_millis()
-            );
-            let (matches, string_range) =
-                id_to_range(&data.range, args, zoom, path, params, path_string.clone());
-            let end2 = start.elapsed();
-            eprintln!(
-                "id_to_range: {}.{:03} sec.",
-                end2.as_secs(),
-                end2.subsec_millis()
-            );
-            // If the end is exceeds the prefetch region, raise error.
-            // let arg_vec = vec!["ghb", "vis", "-t", "1", "-r",  "parse"];
-            //bam_record_vis(&matches, vec![VisOrig::new(string_range, list.read().unwrap().to_vec(), ann.to_vec(), *freq, compressed_list, index_list.to_vec(), prev_index, supplementary_list)],|_| None).unwrap();
-            bam_record_vis(
-                &matches,
-                vec![VisRef::new(
-                    string_range,
-                    &list.read().unwrap(),
-                    ann,
-                    freq,
-                    compressed_list,
-                    index_list,
-                    prev_index,
-                    supplementary_list,
-                )],
-                |_| None,
-            )
-            .unwrap();
-            let end3 = start.elapsed();
-            eprintln!(
-                "img_saved: {}.{:03} sec.",
-                end3.as_secs(),
-                end3.subsec_millis()
-            );
-            // bam_vis(matches, 1);
-            Ok(
-                NamedFile::open(path_string)?.set_content_disposition(ContentDisposition {
-                    disposition: DispositionType::Attachment,
-                    parameters: vec![],
-                }),
-            )
-        }
+    let max_zoom = params.max_zoom as u64;
+    //let min_zoom = ((&data.params).criteria << (max_zoom - zoom)) >= 10000000;
+    let min_zoom = params.min_zoom as u64;
+    if zoom < min_zoom || zoom > max_zoom as u64 {
+        return Err(ApiError::bad_request(format!(
+            "zoom level {} should be between {} and {}",
+            zoom, min_zoom, max_zoom
+        ))
+        .into());
+    }
+    //error is permitted.
+    fs::create_dir_all(format!("{}/{}", cache_dir, zoom)).map_err(ApiError::internal)?;
+    let end1 = start.elapsed();
+    eprintln!(
+        "create dir: {}.{:03} sec.",
+        end1.as_secs(),
+        end1.subsec_millis()
+    );
+    let (matches, string_range) =
+        id_to_range(&vis.range, args, zoom, path, params, path_string.clone());
+    let end2 = start.elapsed();
+    eprintln!(
+        "id_to_range: {}.{:03} sec.",
+        end2.as_secs(),
+        end2.subsec_millis()
+    );
+    // If the end is exceeds the prefetch region, raise error.
+    // let arg_vec = vec!["ghb", "vis", "-t", "1", "-r",  "parse"];
+    //bam_record_vis(&matches, vec![VisOrig::new(string_range, list.read().unwrap().to_vec(), ann.to_vec(), *freq, compressed_list, index_list.to_vec(), prev_index, supplementary_list)],|_| None).unwrap();
+    bam_record_vis(
+        &matches,
+        vec![VisRef::new(
+            string_range,
+            &list.read().unwrap(),
+            &vis.annotation,
+            &vis.freq,
+            &vis.compressed_list,
+            &vis.index_list,
+            vis.prev_index,
+            &vis.supplementary_list,
+        )],
+        |_| None,
+    )
+    .map_err(ApiError::internal)?;
+    let end3 = start.elapsed();
+    eprintln!(
+        "img_saved: {}.{:03} sec.",
+        end3.as_secs(),
+        end3.subsec_millis()
+    );
+    // bam_vis(matches, 1);
+    Ok(path_string)
+}
+
+/// Returns the XYZ tile `{x}/{y}` on the zoom `{z}`, which is a square cropped from the DZI
+/// strip of the column.
+async fn get_xyz(
+    data: web::Data<RwLock<Item>>,
+    list: web::Data<RwLock<Vec<(u64, Record)>>>,
+    req: HttpRequest,
+) -> Result<NamedFile> {
+    let number = |name: &str| {
+        let value = req.match_info().query(name);
+        value
+            .parse::<u64>()
+            .map_err(|_| ApiError::bad_request(format!("Invalid {}: {}", name, value)))
+    };
+    let (z, x, y) = (number("z")?, number("x")?, number("y")?);
+    let (cache_dir, size, grid) = {
+        let data = data.read().unwrap();
+        (
+            data.params.cache_dir.clone(),
+            data.params.x,
+            data.params.grid(),
+        )
+    };
+    let (level, path) = grid
+        .tile(u32::try_from(z).unwrap_or(u32::MAX), x, y)
+        .ok_or_else(|| ApiError::not_found(format!("No tile {}/{}/{}", z, x, y)))?;
+    let tile_dir = format!("{}/xyz/{}/{}", cache_dir, z, x);
+    let tile = format!("{}/{}.png", tile_dir, y);
+    if !Path::new(&tile).exists() {
+        let strip = match path {
+            Some(path) => Some(strip(&data, &list, level as u64, path)?),
+            None => None,
+        };
+        fs::create_dir_all(&tile_dir).map_err(ApiError::internal)?;
+        let y = u32::try_from(y).unwrap_or(u32::MAX);
+        write_tile(strip.as_deref(), y, size, &tile).map_err(ApiError::internal)?;
     }
+    Ok(NamedFile::open(tile).map_err(ApiError::internal)?)
+}
+
+/// TileJSON describing the XYZ tiles.
+async fn get_tilejson(data: web::Data<RwLock<Item>>) -> impl Responder {
+    let data = data.read().unwrap();
+    web::Json(data.params.grid().tilejson(
+        format!("{}xyz/{{z}}/{{x}}/{{y}}.png", data.dzi.image.url),
+        data.params.x,
+    ))
 }
 
 pub struct Item {
@@ -499,7 +568,7 @@ pub struct Size {
 pub struct Param {
     x_scale: u32,
     max_y: u32,
-    _prefetch_max: u64,
+    prefetch_max: u64,
     criteria: u64,
     max_zoom: u32,
     min_zoom: u32,
@@ -510,6 +579,17 @@ pub struct Param {
     y_adjust: bool,
 }
 
+impl Param {
+    fn grid(&self) -> Grid {
+        Grid::new(
+            self.min_zoom,
+            self.max_zoom,
+            self.criteria,
+            self.prefetch_max,
+        )
+    }
+}
+
 const fn num_bits<T>() -> usize {
     std::mem::size_of::<T>() * 8
 }
@@ -537,6 +617,8 @@ pub async fn server(
     use actix_web::{web, HttpServer};
 
     let bind = matches.value_of("web").unwrap_or(&"0.0.0.0:4000");
+    let tls = tls_config(&matches)?;
+    let scheme = if tls.is_some() { "https" } else { "http" };
     let no_margin = matches.is_present("no-scale");
     let annotation_count = annotation.iter().unique_by(|s| s.0).count(); // annotation.len();
     let top_margin = if no_margin { 0 } else { 40 };
@@ -573,7 +655,7 @@ pub async fn server(
     };
     let image = Image {
         xmlns: "http://schemas.microsoft.com/deepzoom/2008".to_string(),
-        url: format!("http://{}/", bind),
+        url: format!("{}://{}/", scheme, bind),
         format: "png".to_string(),
         overlap: "0".to_string(),
         tile_size: x.to_string(),
@@ -601,7 +683,7 @@ pub async fn server(
     let params = Param {
         x_scale,
         max_y: x,
-        _prefetch_max: all,
+        prefetch_max: all,
         max_zoom,
         min_zoom,
         criteria: diff,
@@ -631,31 +713,40 @@ pub async fn server(
     );
     let counter = web::Data::new(RwLock::new(Item::new(vis, args, params, dzi)));
     //let buffer = web::Data::new(RwLock::new(ChromosomeBuffer::new()));
+    let credentials = Credentials::from_matches(&matches)?;
+    let auth_condition = credentials.is_some();
+    let credentials = web::Data::new(credentials.unwrap_or_default());
     let cross_origin_bool = matches.is_present("production");
 
     //https://github.com/actix/examples/blob/master/state/src/main.rs
-    HttpServer::new(move || {
+    let server = HttpServer::new(move || {
         let cross_origin = if cross_origin_bool {
             Cors::default()
         } else {
             Cors::permissive()
         };
+        let auth = HttpAuthentication::with_fn(auth_validator);
 
         actix_web::App::new()
             .app_data(())
             .app_data(web::Data::new(RwLock::new(list.clone())))
             .app_data(counter.clone())
+            .app_data(credentials.clone())
             .route("/", web::get().to(get_index))
             .route("openseadragon.min.js", web::get().to(get_js))
             .route("openseadragon.min.js.map", web::get().to(get_js_map))
             .route("genome.dzi", web::get().to(get_dzi))
+            .route("tilejson.json", web::get().to(get_tilejson))
+            .route("/xyz/{z}/{x}/{y}.png", web::get().to(get_xyz))
             .route("/{zoom:.*}/{filename:.*}_0.png", web::get().to(index))
             .service(actix_files::Files::new("/images", "static/images").show_files_listing())
             .wrap(Logger::default())
             .wrap(cross_origin)
-    })
-    .bind(bind)?
-    .workers(threads as usize)
-    .run()
-    .await
+            .wrap(Condition::new(auth_condition, auth))
+    });
+    let server = match tls {
+        Some(tls) => server.bind_rustls(bind, tls)?,
+        None => server.bind(bind)?,
+    };
+    server.workers(threads as usize).run().await
 }
@@ -0,0 +1,286 @@
+use crate::cache::input_stamps;
+use crate::hash::{fnv1a, fnv1a_with};
+use clap::ArgMatches;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Error, ErrorKind, Read};
+use std::path::Path;
+use std::sync::RwLock;
+
+/// Options whose values are input files, whose checksums are recorded on a session.
+const INPUT_OPTIONS: [&str; 10] = [
+    "INPUT",
+    "bam",
+    "frequency",
+    "bed",
+    "gff3",
+    "ref-column",
+    "bed-range",
+    "cytoband",
+    "highlight-reads",
+    "only-reads",
+];
+
+/// Options only for server mode, which are not recorded on a session.
+pub const SERVER_OPTIONS: [(&str, &str, bool); 6] = [
+    ("-w", "--web-server", true),
+    ("->", "--rest-server", false),
+    ("-$", "--serve-as-production", false),
+    ("-d", "--cache-directory", true),
+    ("-.", "--static-directory", true),
+    ("-[", "--basic-auth", true),
+];
+
+/// A view written by `vis --write-session` (or `/session` on the REST server) and
+/// restored by `vis --session`.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct Session {
+    pub version: String,
+    pub ranges: Vec<String>,
+    pub prefetch_ranges: Vec<String>,
+    pub inputs: Vec<SessionInput>,
+    /// Command-line arguments (without the program name) to regenerate the view.
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct SessionInput {
+    /// Absolute path, so that the session can be restored from another directory.
+    pub path: String,
+    pub size: u64,
+    /// 64-bit FNV-1a hash of the whole file in hex.
+    pub checksum: String,
+}
+
+impl SessionInput {
+    pub fn new(path: &str) -> io::Result<Self> {
+        let (size, checksum) = checksum(path)?;
+        Ok(SessionInput {
+            path: path.to_string(),
+            size,
+            checksum,
+        })
+    }
+}
+
+impl Session {
+    /// `matches` are the matches of `vis` parsed from `args`.
+    pub fn new(args: Vec<String>, matches: &ArgMatches) -> io::Result<Self> {
+        Self::with_inputs(args, matches, SessionInput::new)
+    }
+
+    /// Same as `new`, but each input is given by `input`, e.g. to reuse its checksum.
+    /// Input files are recorded with their absolute paths, also on `args`.
+    pub fn with_inputs<F>(args: Vec<String>, matches: &ArgMatches, mut input: F) -> io::Result<Self>
+    where
+        F: FnMut(&str) -> io::Result<SessionInput>,
+    {
+        let values = |key: &str| -> Vec<String> {
+            matches
+                .values_of(key)
+                .map(|t| t.map(|t| t.to_string()).collect())
+                .unwrap_or_default()
+        };
+        let mut inputs = vec![];
+        let mut absolute_paths = HashMap::new();
+        for key in INPUT_OPTIONS.iter() {
+            for path in values(key) {
+                let absolute_path = fs::canonicalize(&path)
+                    .map_err(|e| Error::new(e.kind(), format!("{}: {}", path, e)))?
+                    .to_string_lossy()
+                    .into_owned();
+                inputs.push(input(&absolute_path)?);
+                absolute_paths.insert(path, absolute_path);
+            }
+        }
+        Ok(Session {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            ranges: values("range"),
+            prefetch_ranges: values("prefetch-range"),
+            inputs,
+            args: args
+                .into_iter()
+                .map(|t| absolute_arg(t, &absolute_paths))
+                .collect(),
+        })
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        serde_yaml::from_reader(File::open(path)?)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))
+    }
+
+    pub fn to_yaml(&self) -> io::Result<String> {
+        serde_yaml::to_string(self).map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        std::fs::write(path, self.to_yaml()?)
+    }
+
+    /// Returns differences from the environment the session was written in.
+    pub fn verify(&self) -> Vec<String> {
+        let mut warnings = vec![];
+        if self.version != env!("CARGO_PKG_VERSION") {
+            warnings.push(format!(
+                "The session was written by hgb {}, but this is hgb {}",
+                self.version,
+                env!("CARGO_PKG_VERSION")
+            ));
+        }
+        for input in self.inputs.iter() {
+            match checksum(&input.path) {
+                Ok((size, checksum)) if size == input.size && checksum == input.checksum => {}
+                Ok(_) => warnings.push(format!("{} has been modified", input.path)),
+                Err(e) => warnings.push(format!("{} cannot be read: {}", input.path, e)),
+            }
+        }
+        warnings
+    }
+}
+
+/// Inputs with their checksums, which are computed again only if the files are modified.
+#[derive(Debug, Default)]
+pub struct Checksums {
+    inputs: RwLock<HashMap<String, SessionInput>>,
+}
+
+impl Checksums {
+    pub fn get(&self, path: &str) -> io::Result<SessionInput> {
+        let stamp = input_stamps(&[path.to_string()]).remove(0);
+        if let Some(input) = self.inputs.read().unwrap().get(&stamp) {
+            return Ok(input.clone());
+        }
+        let input = SessionInput::new(path)?;
+        self.inputs.write().unwrap().insert(stamp, input.clone());
+        Ok(input)
+    }
+}
+
+/// Replaces a path of `paths` given as an argument alone or as `--option=path`.
+fn absolute_arg(arg: String, paths: &HashMap<String, String>) -> String {
+    if let Some(path) = paths.get(&arg) {
+        return path.clone();
+    }
+    if let Some((option, value)) = arg.split_once('=') {
+        if let Some(path) = paths.get(value) {
+            return format!("{}={}", option, path);
+        }
+    }
+    arg
+}
+
+/// Returns the size and the 64-bit FNV-1a hash of a file.
+pub fn checksum<P: AsRef<Path>>(path: P) -> io::Result<(u64, String)> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut buffer = vec![0; 1 << 16];
+    let mut hash = fnv1a(b"");
+    let mut size = 0;
+    loop {
+        let len = reader.read(&mut buffer)?;
+        if len == 0 {
+            break;
+        }
+        hash = fnv1a_with(hash, &buffer[..len]);
+        size += len as u64;
+    }
+    Ok((size, format!("{:016x}", hash)))
+}
+
+/// Removes an option given as `short`, `long` or `long=value`, and its value if it takes one.
+pub fn remove_option(args: Vec<String>, short: &str, long: &str, takes_value: bool) -> Vec<String> {
+    let mut skip = false;
+    args.into_iter()
+        .filter(|t| {
+            if skip {
+                skip = false;
+                return false;
+            }
+            if (!short.is_empty() && t == short) || t == long {
+                skip = takes_value;
+                return false;
+            }
+            !(takes_value && t.starts_with(&format!("{}=", long)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{checksum, remove_option, Checksums, Session, SERVER_OPTIONS};
+    use clap::{App, Arg};
+
+    #[test]
+    fn checksum_works() {
+        let path = std::env::temp_dir().join("hgb_session_checksum.txt");
+        std::fs::write(&path, b"foobar").unwrap();
+        assert_eq!(
+            checksum(&path).unwrap(),
+            (6, "85944171f73967e8".to_string())
+        );
+        let checksums = Checksums::default();
+        let input = checksums.get(path.to_str().unwrap()).unwrap();
+        assert_eq!(input.checksum, "85944171f73967e8");
+        assert_eq!(checksums.get(path.to_str().unwrap()).unwrap(), input);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn absolute_inputs() {
+        let args: Vec<String> = vec!["-a", "Cargo.toml", "--bed=Cargo.toml", "-r", "chr1:1-100"]
+            .into_iter()
+            .map(|t| t.to_string())
+            .collect();
+        let matches = App::new("vis")
+            .arg(Arg::new("bam").short('a').takes_value(true).multiple(true))
+            .arg(Arg::new("bed").long("bed").takes_value(true))
+            .arg(Arg::new("range").short('r').takes_value(true))
+            .get_matches_from(std::iter::once("vis").chain(args.iter().map(|t| t.as_str())));
+        let session = Session::new(args, &matches).unwrap();
+        let path = std::fs::canonicalize("Cargo.toml")
+            .unwrap()
+            .to_string_lossy()
+            .into_owned();
+        assert!(std::path::Path::new(&path).is_absolute());
+        assert_eq!(session.inputs.len(), 2);
+        assert_eq!(session.inputs[0].path, path);
+        assert_eq!(
+            session.args,
+            vec![
+                "-a".to_string(),
+                path.clone(),
+                format!("--bed={}", path),
+                "-r".to_string(),
+                "chr1:1-100".to_string()
+            ]
+        );
+        assert_eq!(session.ranges, vec!["chr1:1-100"]);
+    }
+
+    #[test]
+    fn remove_options() {
+        let args: Vec<String> = vec![
+            "vis",
+            "-a",
+            "a.bam",
+            "-w",
+            "0.0.0.0:4000",
+            "->",
+            "--basic-auth=a:b",
+            "-r",
+            "chr1",
+        ]
+        .into_iter()
+        .map(|t| t.to_string())
+        .collect();
+        let args = SERVER_OPTIONS
+            .iter()
+            .fold(args, |args, t| remove_option(args, t.0, t.1, t.2));
+        assert_eq!(args, vec!["vis", "-a", "a.bam", "-r", "chr1"]);
+        let args = remove_option(args, "", "--session", true);
+        assert_eq!(args.len(), 5);
+    }
+}
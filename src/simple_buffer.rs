@@ -1,5 +1,12 @@
+use crate::allele::AlleleCount;
+use crate::api::{ApiReference, ApiSample};
+use crate::config::TrackFilter;
+use crate::display_mode::DisplayModes;
+use crate::downsample::Downsampler;
+use crate::header::HeaderType;
 use crate::index::Region;
 use crate::range::Default;
+use crate::read_selection::ReadSelection;
 use crate::ChromosomeBufferTrait;
 use crate::{
     bed, range::Format, reader::IndexedReader, twopass_alignment::Alignment, vis::RecordIter,
@@ -12,10 +19,10 @@ use genomic_range::StringRegion;
 use itertools::Itertools;
 use log::debug;
 use std::{
-    collections::{BTreeMap, BTreeSet, HashMap},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     convert::TryInto,
     fs::File,
-    io::BufReader,
+    io::{self, BufReader},
 };
 
 fn check_filter_by_tag(tags: &TagViewer, filter_by_tag: &Vec<&str>) -> bool {
@@ -136,6 +143,8 @@ impl ChromosomeBufferTrait for ChromosomeBuffer {
             .value_of("no-bits")
             .and_then(|t| t.parse::<u16>().ok())
             .unwrap_or(1796u16);
+        // Alleles are counted only if the server is given the allele count track.
+        let allele_count = matches.is_present("allele-count");
 
         let mut chunks = BTreeMap::new();
         let mut bin_ids = BTreeSet::new();
@@ -274,6 +283,12 @@ impl ChromosomeBufferTrait for ChromosomeBuffer {
                     // == range.path
                     // &&
                     line.push((column.ref_pos() as u64, column.entries().len() as u32, '*'));
+                    if allele_count {
+                        line.extend(
+                            AlleleCount::from_entries(column.entries())
+                                .to_frequency(column.ref_pos() as u64),
+                        );
+                    }
                 }
             });
             merged_list.extend(list);
@@ -450,7 +465,7 @@ impl ChromosomeBufferTrait for ChromosomeBuffer {
         string_range: &StringRegion,
         list: &mut Vec<(u64, bam::Record)>,
         _list_btree: &mut ReadBuffer,
-    ) -> Option<Vis> {
+    ) -> io::Result<Vis> {
         let closure = |x: &str| self.reader.reference_id(x);
         let _reference_name = &string_range.path;
         let range = Region::convert(string_range, closure).unwrap();
@@ -463,7 +478,12 @@ impl ChromosomeBufferTrait for ChromosomeBuffer {
         let exclude_split = matches.is_present("exclude-split-alignment");
         let sort_by_name = matches.is_present("sort-by-name");
         let packing = !matches.is_present("no-packing");
-        let split = matches.is_present("split-alignment");
+        // On the breakpoint view, each split read occupies one row as on `vis`.
+        let breakpoint_view = matches.is_present("breakpoint-view");
+        let split = matches.is_present("split-alignment")
+            || matches.is_present("view-as-pairs")
+            || breakpoint_view;
+        let read_per_line = matches.is_present("read-per-line") || breakpoint_view;
         let max_coverage = matches
             .value_of("max-coverage")
             .and_then(|a| a.parse::<u32>().ok());
@@ -475,16 +495,18 @@ impl ChromosomeBufferTrait for ChromosomeBuffer {
             .value_of("no-bits")
             .and_then(|t| t.parse::<u16>().ok())
             .unwrap_or(1796u16);
-        let read_name = matches
-            .value_of("read-name")
-            .map(|t| t.to_string())
-            .unwrap_or("".to_string());
         let filtered_by_tag = matches
             .value_of("filtered-by-tag")
             .map(|a| a.split(':').collect_vec())
             .unwrap_or_default();
         let filter_by_tag = matches.is_present("filtered-by-tag");
-        let filter_by_read_name = matches.is_present("read-name");
+        let downsampler = Downsampler::from_matches(matches);
+        let track_filters = matches
+            .value_of("track-filters")
+            .and_then(|t| TrackFilter::parse_list(t).ok())
+            .unwrap_or_default();
+        let display_modes = DisplayModes::from_matches(matches)?;
+        let selection = ReadSelection::from_matches(matches)?;
         // Calculate coverage; it won't work on sort_by_name
         // let mut frequency = BTreeMap::new(); // Vec::with_capacity();
 
@@ -509,6 +531,16 @@ impl ChromosomeBufferTrait for ChromosomeBuffer {
         let mut compressed_list = vec![];
         let mut index_list = Vec::with_capacity(list.len());
         let mut supplementary_list = vec![];
+        // Downsampled reads are hidden as well as filtered ones; coverage is kept as is.
+        let dropped: HashSet<(u64, i32, Vec<u8>)> = downsampler
+            .map(|downsampler| {
+                downsampler
+                    .dropped(list.iter().map(|t| (t.0, t.1.start(), t.1.name())))
+                    .into_iter()
+                    .map(|t| (t.0, t.1, t.2.to_vec()))
+                    .collect()
+            })
+            .unwrap_or_default();
         if split {
             let mut end_map = HashMap::new();
 
@@ -590,17 +622,27 @@ impl ChromosomeBufferTrait for ChromosomeBuffer {
                     let end = if !packing {
                         range.end() as i32
                     } else if let Some(end) = end_map.get(&(sample_id, k.1.name())) {
-                        end.2
+                        // 1 line for only split alignments, until the end of the range.
+                        if read_per_line {
+                            range.end() as i32
+                        } else {
+                            end.2
+                        }
                     } else {
                         k.1.calculate_end()
                     };
 
                     let mut index = if !k.1.flag().no_bits(no_bits)
                         || k.1.query_len() < min_read_len
-                        || (filter_by_read_name && read_name == String::from_utf8_lossy(k.1.name()))
+                        || selection.is_hidden(k.1.name())
                         || (only_split && k.1.tags().get(b"SA").is_none())
                         || (exclude_split && k.1.tags().get(b"SA").is_some())
                         || (filter_by_tag && check_filter_by_tag(k.1.tags(), &filtered_by_tag))
+                        || (!dropped.is_empty()
+                            && dropped.contains(&(k.0, k.1.start(), k.1.name().to_vec())))
+                        || track_filters
+                            .get(display_modes.input_index(k.0))
+                            .map_or(false, |t| !t.is_match(&k.1))
                     {
                         std::u32::MAX as usize
                     } else if sort_by_name {
@@ -653,10 +695,15 @@ impl ChromosomeBufferTrait for ChromosomeBuffer {
                 (t.1).for_each(|k| {
                     let mut index = if !k.1.flag().no_bits(no_bits)
                         || k.1.query_len() < min_read_len
-                        || (filter_by_read_name && read_name == String::from_utf8_lossy(k.1.name()))
+                        || selection.is_hidden(k.1.name())
                         || (only_split && k.1.tags().get(b"SA").is_some())
                         || (exclude_split && k.1.tags().get(b"SA").is_none())
                         || (filter_by_tag && check_filter_by_tag(k.1.tags(), &filtered_by_tag))
+                        || (!dropped.is_empty()
+                            && dropped.contains(&(k.0, k.1.start(), k.1.name().to_vec())))
+                        || track_filters
+                            .get(display_modes.input_index(k.0))
+                            .map_or(false, |t| !t.is_match(&k.1))
                     {
                         std::u32::MAX as usize
                     } else if let Some(TagValue::Int(array_view, _)) = k.1.tags().get(b"YY") {
@@ -720,7 +767,7 @@ impl ChromosomeBufferTrait for ChromosomeBuffer {
             )
         }
 
-        return Some(Vis {
+        return Ok(Vis {
             range: string_range.clone(),
             //list: list,
             annotation: ann,
@@ -732,4 +779,32 @@ impl ChromosomeBufferTrait for ChromosomeBuffer {
             prefetch_max: self.reader.header().reference_len(0).unwrap(), // The max should be the same as the longest ?
         });
     }
+
+    fn references(&self) -> Vec<ApiReference> {
+        let header = self.reader.header();
+        header
+            .reference_names()
+            .iter()
+            .zip(header.reference_lengths())
+            .map(|(name, length)| ApiReference {
+                name: name.clone(),
+                length: *length as u64,
+            })
+            .collect()
+    }
+
+    fn samples(&self) -> Vec<ApiSample> {
+        let header = self.reader.header();
+        (0..header.n_samples())
+            .map(|i| ApiSample {
+                sample_id: i as u64,
+                name: header.get_name(i).cloned().unwrap_or_default(),
+                header_type: match header.get_local_header(i) {
+                    Some(HeaderType::BAM(_)) => "bam",
+                    _ => "none",
+                }
+                .to_string(),
+            })
+            .collect()
+    }
 }
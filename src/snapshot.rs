@@ -0,0 +1,368 @@
+use crate::vis::bam_record_vis;
+use crate::{ChromosomeBufferTrait, ReadBuffer, Vis, VisRef};
+use bam::Record;
+use clap::ArgMatches;
+use genomic_range::StringRegion;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io;
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::time::Instant;
+
+/// Reads loaded from a buffer and their layout.
+///
+/// A snapshot is never modified; reloading replaces the shared `Arc` as a whole, so that
+/// requests render on their own snapshots without holding locks.
+#[derive(Clone)]
+pub struct Snapshot {
+    pub list: Vec<(u64, Record)>,
+    pub list_btree: ReadBuffer,
+    pub vis: Vis,
+}
+
+impl Snapshot {
+    pub fn new(list: Vec<(u64, Record)>, list_btree: ReadBuffer, vis: Vis) -> Self {
+        Snapshot {
+            list,
+            list_btree,
+            vis,
+        }
+    }
+
+    /// A snapshot without reads, which is replaced on the first request.
+    pub fn empty() -> Self {
+        let vis = Vis {
+            range: StringRegion {
+                path: String::new(),
+                start: 0,
+                end: 0,
+            },
+            annotation: vec![],
+            freq: BTreeMap::new(),
+            compressed_list: vec![],
+            index_list: vec![],
+            prev_index: 0,
+            supplementary_list: vec![],
+            prefetch_max: 0,
+        };
+        Snapshot::new(vec![], (0, BTreeSet::new()), vis)
+    }
+
+    /// Returns the current snapshot if it includes the range, or replaces it with the one
+    /// reloaded from the buffer.
+    ///
+    /// Reloads run one at a time under the write lock of the buffer, and each checks the
+    /// current snapshot again once it holds the lock, so that requests for a range that was
+    /// reloaded while they waited reuse that snapshot instead of reloading it again.
+    pub fn load<T: ChromosomeBufferTrait + ?Sized>(
+        matches: &ArgMatches,
+        string_range: &StringRegion,
+        current: &RwLock<Arc<Snapshot>>,
+        buffer: &RwLock<T>,
+    ) -> io::Result<Arc<Snapshot>> {
+        Snapshot::reload(matches, string_range, current, buffer).map(|t| t.0)
+    }
+
+    /// Same as `load`, but the reads are always laid out with `matches`, while the current
+    /// snapshot may have been laid out with the options of another request (e.g. filters
+    /// given by an API client). The current snapshot is kept as is.
+    pub fn load_with_options<T: ChromosomeBufferTrait + ?Sized>(
+        matches: &ArgMatches,
+        string_range: &StringRegion,
+        current: &RwLock<Arc<Snapshot>>,
+        buffer: &RwLock<T>,
+    ) -> io::Result<Arc<Snapshot>> {
+        let (snapshot, reloaded) = Snapshot::reload(matches, string_range, current, buffer)?;
+        if reloaded {
+            return Ok(snapshot);
+        }
+        let mut list = snapshot.list.clone();
+        let mut list_btree = snapshot.list_btree.clone();
+        let vis = buffer
+            .read()
+            .unwrap()
+            .vis(matches, string_range, &mut list, &mut list_btree)?;
+        Ok(Arc::new(Snapshot::new(list, list_btree, vis)))
+    }
+
+    /// `load`, also returning whether the snapshot was reloaded with `matches`.
+    fn reload<T: ChromosomeBufferTrait + ?Sized>(
+        matches: &ArgMatches,
+        string_range: &StringRegion,
+        current: &RwLock<Arc<Snapshot>>,
+        buffer: &RwLock<T>,
+    ) -> io::Result<(Arc<Snapshot>, bool)> {
+        let snapshot = current.read().unwrap().clone();
+        if buffer
+            .read()
+            .unwrap()
+            .included_string_local(string_range, &snapshot.list_btree)
+        {
+            return Ok((snapshot, false));
+        }
+        let start = Instant::now();
+        let mut buffer = buffer.write().unwrap();
+        let snapshot = current.read().unwrap().clone();
+        if buffer.included_string_local(string_range, &snapshot.list_btree) {
+            return Ok((snapshot, false));
+        }
+        let mut list = snapshot.list.clone();
+        let mut list_btree = snapshot.list_btree.clone();
+        buffer.retrieve(string_range, &mut list, &mut list_btree);
+        let vis = buffer.vis(matches, string_range, &mut list, &mut list_btree)?;
+        let snapshot = Arc::new(Snapshot::new(list, list_btree, vis));
+        // Replaced before the buffer is unlocked, so the next reload starts from this one.
+        *current.write().unwrap() = snapshot.clone();
+        drop(buffer);
+        let end = start.elapsed();
+        eprintln!(
+            "Fallback to reload: {}.{:03} sec.",
+            end.as_secs(),
+            end.subsec_millis()
+        );
+        Ok((snapshot, true))
+    }
+
+    /// Draws the range into the output of `matches`.
+    pub fn render(
+        &self,
+        matches: &ArgMatches,
+        string_range: StringRegion,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        bam_record_vis(
+            matches,
+            vec![VisRef::new(
+                string_range,
+                &self.list,
+                &self.vis.annotation,
+                &self.vis.freq,
+                &self.vis.compressed_list,
+                &self.vis.index_list,
+                self.vis.prev_index,
+                &self.vis.supplementary_list,
+            )],
+            |_| None,
+        )
+    }
+}
+
+struct Call<R> {
+    /// Whether the call finished, and its result unless it panicked.
+    state: Mutex<(bool, Option<R>)>,
+    done: Condvar,
+}
+
+/// Identical requests (with the same key) running at the same time, where only the first
+/// one runs and the others wait for its result.
+pub struct InFlight<R> {
+    calls: Mutex<HashMap<u64, Arc<Call<R>>>>,
+}
+
+impl<R: Clone> InFlight<R> {
+    pub fn new() -> Self {
+        InFlight {
+            calls: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Runs `f`, or waits for the running call with the same key and returns its result.
+    /// If the running call panics, `f` runs instead.
+    pub fn run<F: FnOnce() -> R>(&self, key: u64, f: F) -> R {
+        let (call, first) = {
+            let mut calls = self.calls.lock().unwrap();
+            match calls.get(&key) {
+                Some(call) => (call.clone(), false),
+                None => {
+                    let call = Arc::new(Call {
+                        state: Mutex::new((false, None)),
+                        done: Condvar::new(),
+                    });
+                    calls.insert(key, call.clone());
+                    (call, true)
+                }
+            }
+        };
+        if !first {
+            let mut state = call.state.lock().unwrap();
+            while !state.0 {
+                state = call.done.wait(state).unwrap();
+            }
+            return match &state.1 {
+                Some(result) => result.clone(),
+                None => f(),
+            };
+        }
+        // Finishes the call even if `f` panics.
+        let finish = Finish {
+            calls: &self.calls,
+            key,
+            call: &call,
+        };
+        let result = f();
+        call.state.lock().unwrap().1 = Some(result.clone());
+        drop(finish);
+        result
+    }
+}
+
+impl<R: Clone> Default for InFlight<R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct Finish<'a, R> {
+    calls: &'a Mutex<HashMap<u64, Arc<Call<R>>>>,
+    key: u64,
+    call: &'a Call<R>,
+}
+
+impl<'a, R> Drop for Finish<'a, R> {
+    fn drop(&mut self) {
+        if let Ok(mut calls) = self.calls.lock() {
+            calls.remove(&self.key);
+        }
+        if let Ok(mut state) = self.call.state.lock() {
+            state.0 = true;
+        }
+        self.call.done.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InFlight, Snapshot};
+    use crate::index::Region;
+    use crate::{api, ChromosomeBufferTrait, ReadBuffer, Vis};
+    use clap::{App, Arg, ArgMatches};
+    use genomic_range::StringRegion;
+    use std::io;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, RwLock};
+    use std::thread;
+    use std::time::Duration;
+
+    /// A buffer without reads, which lays out a range with `max-coverage` as `prev_index`.
+    #[derive(Default)]
+    struct Buffer {
+        retrieved: usize,
+    }
+
+    impl ChromosomeBufferTrait for Buffer {
+        fn drop(&mut self) {}
+        fn set_ref_id(&mut self, _ref_id: u64) {}
+        fn included(&self, _range: Region) -> bool {
+            unimplemented!()
+        }
+        fn bins(&self) -> Vec<&usize> {
+            vec![]
+        }
+        fn size_limit(&self) -> bool {
+            false
+        }
+        fn size_limit_local(&self, _bins: &ReadBuffer) -> bool {
+            false
+        }
+        fn included_local(&self, _range: Region, bins: &ReadBuffer) -> bool {
+            !bins.1.is_empty()
+        }
+        fn add(&mut self, _range: &StringRegion) -> (bool, Vec<(u64, bam::Record)>, ReadBuffer) {
+            unimplemented!()
+        }
+        fn included_string(&self, _string_range: &StringRegion) -> bool {
+            unimplemented!()
+        }
+        fn included_string_local(&self, _string_range: &StringRegion, bins: &ReadBuffer) -> bool {
+            !bins.1.is_empty()
+        }
+        fn add_local(
+            &mut self,
+            _range: &StringRegion,
+            _local_bins: &mut ReadBuffer,
+        ) -> (bool, Vec<(u64, bam::Record)>) {
+            unimplemented!()
+        }
+        fn retrieve(
+            &mut self,
+            _string_range: &StringRegion,
+            _list: &mut Vec<(u64, bam::Record)>,
+            list_btree: &mut ReadBuffer,
+        ) {
+            self.retrieved += 1;
+            list_btree.1.insert(0);
+        }
+        fn vis(
+            &self,
+            matches: &ArgMatches,
+            string_range: &StringRegion,
+            _list: &mut Vec<(u64, bam::Record)>,
+            _list_btree: &mut ReadBuffer,
+        ) -> io::Result<Vis> {
+            let mut vis = Snapshot::empty().vis;
+            vis.range = string_range.clone();
+            vis.prev_index = matches
+                .value_of("max-coverage")
+                .map_or(0, |t| t.parse().unwrap());
+            Ok(vis)
+        }
+        fn references(&self) -> Vec<api::ApiReference> {
+            vec![]
+        }
+        fn samples(&self) -> Vec<api::ApiSample> {
+            vec![]
+        }
+    }
+
+    #[test]
+    fn options_of_requests() {
+        let matches = |max_coverage: &str| {
+            App::new("vis")
+                .arg(
+                    Arg::new("max-coverage")
+                        .long("max-coverage")
+                        .takes_value(true),
+                )
+                .get_matches_from(vec!["vis", "--max-coverage", max_coverage])
+        };
+        let range = StringRegion::new("chr1:1-100").unwrap();
+        let current = RwLock::new(Arc::new(Snapshot::empty()));
+        let buffer = RwLock::new(Buffer::default());
+        let load = |max_coverage: &str| {
+            Snapshot::load_with_options(&matches(max_coverage), &range, &current, &buffer)
+                .unwrap()
+                .vis
+                .prev_index
+        };
+        assert_eq!(load("10"), 10);
+        // Laid out with the options of each request, without reloading the range.
+        assert_eq!(load("20"), 20);
+        assert_eq!(buffer.read().unwrap().retrieved, 1);
+        // The current snapshot is the one laid out on the reload.
+        let snapshot = Snapshot::load(&matches("20"), &range, &current, &buffer).unwrap();
+        assert_eq!(snapshot.vis.prev_index, 10);
+    }
+
+    #[test]
+    fn deduplication() {
+        let inflight = Arc::new(InFlight::new());
+        let count = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let inflight = inflight.clone();
+                let count = count.clone();
+                thread::spawn(move || {
+                    inflight.run(1, || {
+                        count.fetch_add(1, Ordering::SeqCst);
+                        thread::sleep(Duration::from_millis(100));
+                        "done".to_string()
+                    })
+                })
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), "done");
+        }
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        // The finished call is not reused.
+        assert_eq!(inflight.run(1, || "again".to_string()), "again");
+    }
+}
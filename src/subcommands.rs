@@ -6,19 +6,30 @@ use mt_bam;
 use mt_bam::RecordWriter as MtRecordWriter;
 
 #[cfg(feature = "web")]
-use crate::buffered_server::server as buffered_server;
+use crate::buffered_server::{server as buffered_server, tiles as buffered_tiles};
 #[cfg(feature = "web")]
-use crate::rest_server::rest_server;
+use crate::rest_server::{catalog_server, rest_server};
 #[cfg(feature = "web")]
 use crate::server::server;
 
+use ghi::allele::{write_allele_count_header, write_allele_counts, AlleleCount};
 use ghi::bed;
 use ghi::binary::GhbWriter;
+use ghi::breakpoint::{cluster, segments, vcf_header, Junction};
 use ghi::builder::InvertedRecordBuilder;
+use ghi::circos::CircosLayout;
+use ghi::color::ColorSet;
+use ghi::config::TrackFilter;
+use ghi::dotplot::DotPlot;
+use ghi::downsample::Downsampler;
 use ghi::header::Header;
 use ghi::index::{Chunk, Region, VirtualOffset};
+use ghi::overview::{major_chromosomes, Overview, SampleOverview};
 use ghi::range::Default;
 use ghi::range::{Format, InvertedRecordEntire, Set};
+use ghi::read_selection::ReadSelection;
+use ghi::reference::Reference;
+use ghi::session::remove_option;
 use ghi::twopass_alignment::{Alignment, AlignmentBuilder};
 use ghi::vis::{bam_record_vis_orig, RecordIter};
 use ghi::writer::GhiWriter;
@@ -30,8 +41,9 @@ use io::{BufReader, Error, ErrorKind, Write};
 use itertools::EitherOrBoth::{Both, Left};
 use itertools::Itertools;
 use log::{debug, info, warn};
+use rayon::prelude::*;
 use std::{
-    collections::{BTreeMap, BTreeSet, HashMap},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     convert::TryInto,
     fs::File,
     io,
@@ -44,30 +56,15 @@ pub fn bam_vis(
     args: Vec<String>,
     threads: u16,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    if matches.is_present("catalog") {
+        return Ok(catalog_server(matches.clone(), args, threads)?);
+    }
     // let output_path = matches.value_of("OUTPUT").unwrap();
-    let min_read_len = matches
-        .value_of("min-read-length")
-        .and_then(|a| a.parse::<u32>().ok())
-        .unwrap_or(0u32);
     let neighbor = matches
         .value_of("neighbor")
         .and_then(|a| a.parse::<u64>().ok())
         .unwrap_or(0u64);
-    let no_bits = matches
-        .value_of("no-bits")
-        .and_then(|t| t.parse::<u16>().ok())
-        .unwrap_or(1796u16);
-    let full_length = matches.is_present("full-length");
-    let separated_by_tag = matches.occurrences_of("separated-by-tag") != 0;
-    let separated_by_tag_vec = matches.value_of("separated-by-tag");
-    let separated_by_tag_offset = matches
-        .value_of("separated-by-tag-offset")
-        .and_then(|a| a.parse::<usize>().ok());
-    let bam_interval = if separated_by_tag {
-        separated_by_tag_offset.unwrap()
-    } else {
-        1
-    };
+    let bam_interval = bam_interval(matches);
     let labels: Option<Vec<&str>> = matches.values_of("labels").map(|t| t.collect());
 
     if let Some(bam_files) = matches.values_of("bam") {
@@ -76,7 +73,33 @@ pub fn bam_vis(
             false => threads - 1,
         };
         let mut bam_files: Vec<&str> = bam_files.collect();
-        let mut bam_readers = bam_files
+        let bam_len = bam_files.len();
+        // Frequency files are labeled following BAM files.
+        if let Some(freq_files) = matches.values_of("frequency") {
+            bam_files.extend(freq_files);
+        }
+        let label = |idx: usize| {
+            if let Some(labels) = &labels {
+                labels.get(idx / bam_interval).copied()
+            } else {
+                bam_files.get(idx / bam_interval).copied()
+            }
+        };
+
+        if matches.is_present("genome-overview") {
+            return bam_vis_overview(matches, &bam_files[..bam_len], label);
+        }
+        if let Some(output_dir) = matches.value_of("output-dir") {
+            return bam_vis_batch(
+                matches,
+                &bam_files[..bam_len],
+                output_dir,
+                args,
+                threads,
+                label,
+            );
+        }
+        let mut bam_readers = bam_files[..bam_len]
             .iter()
             .map(|bam_path| {
                 bam::IndexedReader::build()
@@ -158,7 +181,6 @@ pub fn bam_vis(
             } else {
                 string_range
             };*/
-            let mut list: Vec<(u64, Record)> = vec![];
             println!("Input file: {:?}", bam_files);
             let reader2 = &mut bam_readers[0];
             let lambda = |range: String| {
@@ -193,7 +215,25 @@ pub fn bam_vis(
                     > { lambda(prefetch_str) },
                 )
                 .unwrap();
-            if matches.is_present("rest") {
+            if matches.is_present("tiles") {
+                let buffer: ghi::simple_bam_buffer::ChromosomeBuffer =
+                    ghi::simple_bam_buffer::ChromosomeBuffer::new(
+                        bam::IndexedReader::build()
+                            .additional_threads(0)
+                            .from_path(bam_files[0])
+                            .unwrap(),
+                        matches.clone(),
+                    );
+                buffered_tiles(
+                    matches.clone(),
+                    string_range,
+                    prefetch_range,
+                    args,
+                    buffer,
+                    threads,
+                )?;
+                return Ok(());
+            } else if matches.is_present("rest") {
                 let buffer: ghi::simple_bam_buffer::ChromosomeBuffer =
                     ghi::simple_bam_buffer::ChromosomeBuffer::new(
                         bam::IndexedReader::build()
@@ -230,198 +270,695 @@ pub fn bam_vis(
                 )?;
                 return Ok(());
             }
-            for (index, reader2) in &mut bam_readers.iter_mut().enumerate() {
-                //println!("Loading {}", bam_path);
-                // let reader = bam::BamReader::from_path(bam_path, threads).unwrap();
-                //let mut reader2 = bam::IndexedReader::build()
-                //    .additional_threads(threads - 1)
-                //    .from_path(bam_path)?;
+            precursor.push(load_precursor(
+                matches,
+                &mut bam_readers,
+                string_range,
+                prefetch_range,
+            )?);
+        }
 
-                // Here all threads can be used, but I suspect that runs double
-                //reader2.fetch()
+        let header = bam_readers[0].header();
+        let chromosomes = header
+            .reference_names()
+            .iter()
+            .enumerate()
+            .filter_map(|(id, name)| {
+                header
+                    .reference_len(id as u32)
+                    .map(|len| (name.clone(), len as u64))
+            })
+            .collect();
+        write_circos(matches, &precursor, chromosomes)?;
+        if matches.is_present("dotplot") {
+            return write_dotplot(matches, &precursor);
+        }
+        bam_record_vis_pre_calculate(matches, &args, precursor, threads, label)?;
+    }
+    Ok(())
+}
 
-                let ref_id = reader2
-                    .header()
-                    .reference_id(prefetch_range.path.as_ref())
-                    .ok_or_else(|| Error::new(ErrorKind::Other, "Invalid reference id."))?;
-                let ref_len = reader2
-                    .header()
-                    .reference_len(ref_id)
-                    .ok_or_else(|| Error::new(ErrorKind::Other, "Invalid reference length."))?;
-                let start = if prefetch_range.start == 0 {
-                    warn!("Invalid reference length: start == 0",);
-                    1
-                } else {
-                    prefetch_range.start as u32
-                };
-                let end = if prefetch_range.end as u32 > ref_len {
-                    warn!(
-                        "Invalid reference length: end > reference length ({} > {})",
-                        prefetch_range.end as u32, ref_len
-                    );
-                    ref_len
-                } else {
-                    prefetch_range.end as u32
+/// Draws a circos plot of translocations supported by split alignments of loaded reads.
+fn write_circos(
+    matches: &ArgMatches,
+    precursor: &[VisPrecursor],
+    chromosomes: Vec<(String, u64)>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let output = match matches.value_of("circos") {
+        Some(output) => output,
+        None => return Ok(()),
+    };
+    let tolerance = matches
+        .value_of("breakpoint-tolerance")
+        .and_then(|a| a.parse::<u64>().ok())
+        .unwrap_or(100u64);
+    let size = matches
+        .value_of("x")
+        .and_then(|a| a.parse::<u32>().ok())
+        .unwrap_or(1280u32);
+    let mut junctions = vec![];
+    for i in precursor.iter() {
+        let list = i.list.lock().unwrap();
+        for (_, record) in list.iter() {
+            let name = String::from_utf8_lossy(record.name()).to_string();
+            for junction in Junction::from_segments(&segments(record, &i.prefetch_range.path)) {
+                if junction.is_translocation() {
+                    junctions.push((junction, name.clone()));
+                }
+            }
+        }
+    }
+    let clusters = cluster(junctions, tolerance);
+    info!("Translocations: {}", clusters.len());
+    CircosLayout::new(major_chromosomes(chromosomes), 0.01).draw(output, size, &clusters)
+}
+
+/// Draws a dot plot of all alignments of the read given by `--read-name` to `--output`.
+fn write_dotplot(
+    matches: &ArgMatches,
+    precursor: &[VisPrecursor],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let read_name = matches.value_of("read-name").unwrap_or("");
+    let output = matches
+        .value_of("output")
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "--dotplot requires --output"))?;
+    let x = matches
+        .value_of("x")
+        .and_then(|a| a.parse::<u32>().ok())
+        .unwrap_or(1280u32);
+    let preset_color: ColorSet = matches
+        .value_of_t("preset-color")
+        .ok()
+        .unwrap_or_else(ColorSet::new);
+    // The primary alignment is preferred as its SA tag lists all other alignments.
+    let mut found = None;
+    for i in precursor.iter() {
+        let list = i.list.lock().unwrap();
+        for (_, record) in list.iter() {
+            if record.name() == read_name.as_bytes()
+                && (found.is_none() || !record.flag().is_supplementary())
+            {
+                found = Some(segments(record, &i.prefetch_range.path));
+            }
+        }
+    }
+    let segments = found.ok_or_else(|| {
+        Error::new(
+            ErrorKind::NotFound,
+            format!("{} is not found on the ranges", read_name),
+        )
+    })?;
+    let margin = segments.iter().map(|t| t.end - t.start).max().unwrap_or(0) / 10;
+    DotPlot::new(read_name, segments, margin).draw(output, (x, x * 3 / 4), &preset_color)
+}
+
+/// Renders binned coverage over all chromosomes of each BAM input along with ideograms.
+fn bam_vis_overview<'a, F>(
+    matches: &ArgMatches,
+    bam_files: &[&str],
+    lambda: F,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    F: Fn(usize) -> Option<&'a str>,
+{
+    let output = matches.value_of("output").ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            "--genome-overview requires --output",
+        )
+    })?;
+    let bin_size = matches
+        .value_of("overview-bin-size")
+        .and_then(|a| a.parse::<u64>().ok())
+        .unwrap_or(1_000_000u64);
+    let x = matches
+        .value_of("x")
+        .and_then(|a| a.parse::<u32>().ok())
+        .unwrap_or(1280u32);
+    let min_read_len = matches
+        .value_of("min-read-length")
+        .and_then(|a| a.parse::<u32>().ok())
+        .unwrap_or(0u32);
+    let no_bits = matches
+        .value_of("no-bits")
+        .and_then(|t| t.parse::<u16>().ok())
+        .unwrap_or(1796u16);
+    let bam_interval = bam_interval(matches);
+    let cytobands = matches
+        .value_of("cytoband")
+        .map(Overview::read_cytobands)
+        .transpose()?
+        .unwrap_or_default();
+
+    // Chromosomes in the cytoband file, or those not much shorter than the longest one.
+    let chromosomes: Vec<(String, u64)> = {
+        let reader = bam::IndexedReader::build()
+            .additional_threads(0)
+            .from_path(bam_files[0])?;
+        let header = reader.header();
+        let lens: Vec<(String, u64)> = header
+            .reference_names()
+            .iter()
+            .enumerate()
+            .filter_map(|(id, name)| {
+                header
+                    .reference_len(id as u32)
+                    .map(|len| (name.clone(), len as u64))
+            })
+            .collect();
+        if cytobands.is_empty() {
+            major_chromosomes(lens)
+        } else {
+            lens.into_iter()
+                .filter(|t| cytobands.contains_key(&t.0))
+                .collect()
+        }
+    };
+    let samples = bam_files
+        .par_iter()
+        .map(|bam_path| {
+            let mut reader = bam::IndexedReader::build()
+                .additional_threads(0)
+                .from_path(bam_path)
+                .map_err(|e| e.to_string())?;
+            let mut sample = SampleOverview::new(&chromosomes, bin_size);
+            for (chrom, (name, len)) in chromosomes.iter().enumerate() {
+                let ref_id = match reader.header().reference_id(name) {
+                    Some(ref_id) => ref_id,
+                    None => continue,
                 };
-                let viewer = reader2.fetch(&bam::bam_reader::Region::new(ref_id, start, end))?;
-                let mut count = 0;
-                let mut total = 0;
+                let viewer = reader
+                    .fetch(&bam::bam_reader::Region::new(ref_id, 0, *len as u32))
+                    .map_err(|e| e.to_string())?;
                 for record in viewer {
-                    let record = record?;
-                    total += 1;
-                    if record.flag().no_bits(no_bits)
-                        && record.query_len() >= min_read_len
-                        && (!full_length
-                            || (record.start() <= prefetch_range.start as i32
-                                && record.calculate_end() >= prefetch_range.end as i32))
-                    {
-                        if count % 1000 == 0 {
-                            debug!("Reads loaded: {}", count);
-                        }
-                        count += 1;
-                        let idx = if separated_by_tag {
-                            if let Some(colored_by_str) = separated_by_tag_vec {
-                                if colored_by_str.is_empty() {
-                                    let track = if record.flag().is_reverse_strand() {
-                                        1
-                                    } else {
-                                        0
-                                    };
-                                    index * bam_interval + track
-                                } else {
-                                    let tag: &[u8; 2] = colored_by_str.as_bytes().try_into().expect("colored by tag with unexpected length: tag name must be two characters.");
-                                    if let Some(TagValue::Int(tag_id, _)) = record.tags().get(tag) {
-                                        index * bam_interval + tag_id as usize
-                                    } else {
-                                        index * bam_interval
-                                    }
-                                }
+                    let record = record.map_err(|e| e.to_string())?;
+                    if record.flag().no_bits(no_bits) && record.query_len() >= min_read_len {
+                        sample.add_record(chrom, name, &record);
+                    }
+                }
+            }
+            info!("Loaded {}", bam_path);
+            Ok(sample)
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    Overview::new(chromosomes, samples, bin_size)
+        .with_cytobands(cytobands)
+        .draw(output, x, |i| lambda(i * bam_interval))
+}
+
+/// Renders each region of `--bed-range` into its own image under `--output-dir` in parallel,
+/// along with an index page of thumbnails.
+fn bam_vis_batch<'a, F>(
+    matches: &ArgMatches,
+    bam_files: &[&str],
+    output_dir: &str,
+    args: Vec<String>,
+    threads: u16,
+    lambda: F,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    F: Fn(usize) -> Option<&'a str> + Sync,
+{
+    let bed_range = matches
+        .value_of("bed-range")
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "--output-dir requires --bed-range"))?;
+    let neighbor = matches
+        .value_of("neighbor")
+        .and_then(|a| a.parse::<u64>().ok())
+        .unwrap_or(0u64);
+    let format = matches.value_of("format").unwrap_or("png");
+    let string_range = matches
+        .value_of("range")
+        .and_then(|t| StringRegion::new(t).ok());
+
+    // Each region is named after the name column of BED (or its coordinates).
+    let mut regions = vec![];
+    let mut names = HashSet::new();
+    let mut reader = bed::Reader::from_file(bed_range)?;
+    for record in reader.records() {
+        let record = record?;
+        if let Some(string_range) = &string_range {
+            if record.chrom() != string_range.path
+                || record.end() <= string_range.start()
+                || string_range.end() <= record.start()
+            {
+                continue;
+            }
+        }
+        let range = format!(
+            "{}:{}-{}",
+            record.chrom(),
+            record.start().saturating_sub(neighbor),
+            record.end() + neighbor
+        );
+        let name: String = record
+            .name()
+            .unwrap_or(&format!(
+                "{}_{}_{}",
+                record.chrom(),
+                record.start(),
+                record.end()
+            ))
+            .chars()
+            .map(|c| match c {
+                'A'..='Z' | 'a'..='z' | '0'..='9' | '.' | '-' | '_' => c,
+                _ => '_',
+            })
+            .collect();
+        let mut unique_name = name.clone();
+        let mut count = 1;
+        while !names.insert(unique_name.clone()) {
+            count += 1;
+            unique_name = format!("{}_{}", name, count);
+        }
+        let range = StringRegion::new(&range).map_err(|_| {
+            Error::new(ErrorKind::InvalidInput, format!("Invalid range: {}", range))
+        })?;
+        regions.push((unique_name, range));
+    }
+    std::fs::create_dir_all(output_dir)?;
+
+    // Options are parsed again for each region with its own output path.
+    let args = [("-o", "--output"), ("", "--output-dir")]
+        .iter()
+        .fold(args, |args, t| remove_option(args, t.0, t.1, true));
+    let results: Vec<Result<(), String>> = regions
+        .par_iter()
+        .map_init(
+            || {
+                bam_files
+                    .iter()
+                    .map(|bam_path| {
+                        bam::IndexedReader::build()
+                            .additional_threads(0)
+                            .from_path(bam_path)
+                            .unwrap()
+                    })
+                    .collect::<Vec<_>>()
+            },
+            |bam_readers, (name, range)| {
+                let path = Path::new(output_dir).join(format!("{}.{}", name, format));
+                let mut args = args.clone();
+                args.push("-o".to_string());
+                args.push(path.to_string_lossy().to_string());
+                let app_matches = crate::build_app()
+                    .try_get_matches_from(&args)
+                    .map_err(|e| e.to_string())?;
+                let matches = app_matches
+                    .subcommand_matches("vis")
+                    .ok_or_else(|| "vis is not specified".to_string())?;
+                let precursor = load_precursor(matches, bam_readers, range.clone(), range.clone())
+                    .map_err(|e| e.to_string())?;
+                bam_record_vis_pre_calculate(matches, &args, vec![precursor], threads, &lambda)
+                    .map_err(|e| e.to_string())
+            },
+        )
+        .collect();
+
+    let mut index = io::BufWriter::new(File::create(Path::new(output_dir).join("index.html"))?);
+    writeln!(
+        index,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n\
+         <style>figure {{ display: inline-block; margin: 4px; }} img {{ width: 320px; }}</style>\n\
+         </head>\n<body>",
+        bed_range
+    )?;
+    let mut failed = 0;
+    for ((name, range), result) in regions.iter().zip(results) {
+        match result {
+            Ok(()) => writeln!(
+                index,
+                "<figure><a href=\"{0}.{1}\"><img src=\"{0}.{1}\" loading=\"lazy\"></a>\
+                 <figcaption>{0} ({2}:{3}-{4})</figcaption></figure>",
+                name,
+                format,
+                range.path,
+                range.start(),
+                range.end()
+            )?,
+            Err(e) => {
+                warn!("Failed to render {}: {}", name, e);
+                failed += 1;
+            }
+        }
+    }
+    writeln!(index, "</body>\n</html>")?;
+    if failed > 0 {
+        return Err(format!("Failed to render {} of {} regions", failed, regions.len()).into());
+    }
+    Ok(())
+}
+
+/// The number of sample ids assigned to each BAM input.
+fn bam_interval(matches: &ArgMatches) -> usize {
+    if matches.occurrences_of("separated-by-tag") != 0 {
+        matches
+            .value_of("separated-by-tag-offset")
+            .and_then(|a| a.parse::<usize>().ok())
+            .unwrap()
+    } else {
+        1
+    }
+}
+
+/// Loads reads of opened BAM readers, annotations and frequencies on a range.
+fn load_precursor<R: io::Read + io::Seek>(
+    matches: &ArgMatches,
+    bam_readers: &mut [bam::IndexedReader<R>],
+    string_range: StringRegion,
+    prefetch_range: StringRegion,
+) -> Result<VisPrecursor, Box<dyn std::error::Error>> {
+    let min_read_len = matches
+        .value_of("min-read-length")
+        .and_then(|a| a.parse::<u32>().ok())
+        .unwrap_or(0u32);
+    let no_bits = matches
+        .value_of("no-bits")
+        .and_then(|t| t.parse::<u16>().ok())
+        .unwrap_or(1796u16);
+    let full_length = matches.is_present("full-length");
+    let separated_by_tag = matches.occurrences_of("separated-by-tag") != 0;
+    let separated_by_tag_vec = matches.value_of("separated-by-tag");
+    let bam_interval = bam_interval(matches);
+    let track_filters = matches
+        .value_of("track-filters")
+        .map(TrackFilter::parse_list)
+        .transpose()
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?
+        .unwrap_or_default();
+    let mut list: Vec<(u64, Record)> = vec![];
+    for (index, reader2) in bam_readers.iter_mut().enumerate() {
+        //println!("Loading {}", bam_path);
+        // let reader = bam::BamReader::from_path(bam_path, threads).unwrap();
+        //let mut reader2 = bam::IndexedReader::build()
+        //    .additional_threads(threads - 1)
+        //    .from_path(bam_path)?;
+
+        // Here all threads can be used, but I suspect that runs double
+        //reader2.fetch()
+
+        let ref_id = reader2
+            .header()
+            .reference_id(prefetch_range.path.as_ref())
+            .ok_or_else(|| Error::new(ErrorKind::Other, "Invalid reference id."))?;
+        let ref_len = reader2
+            .header()
+            .reference_len(ref_id)
+            .ok_or_else(|| Error::new(ErrorKind::Other, "Invalid reference length."))?;
+        let start = if prefetch_range.start == 0 {
+            warn!("Invalid reference length: start == 0",);
+            1
+        } else {
+            prefetch_range.start as u32
+        };
+        let end = if prefetch_range.end as u32 > ref_len {
+            warn!(
+                "Invalid reference length: end > reference length ({} > {})",
+                prefetch_range.end as u32, ref_len
+            );
+            ref_len
+        } else {
+            prefetch_range.end as u32
+        };
+        let viewer = reader2.fetch(&bam::bam_reader::Region::new(ref_id, start, end))?;
+        let mut count = 0;
+        let mut total = 0;
+        for record in viewer {
+            let record = record?;
+            total += 1;
+            if record.flag().no_bits(no_bits)
+                && record.query_len() >= min_read_len
+                && (!full_length
+                    || (record.start() <= prefetch_range.start as i32
+                        && record.calculate_end() >= prefetch_range.end as i32))
+                && track_filters
+                    .get(index)
+                    .map_or(true, |t| t.is_match(&record))
+            {
+                if count % 1000 == 0 {
+                    debug!("Reads loaded: {}", count);
+                }
+                count += 1;
+                let idx = if separated_by_tag {
+                    if let Some(colored_by_str) = separated_by_tag_vec {
+                        if colored_by_str.is_empty() {
+                            let track = if record.flag().is_reverse_strand() {
+                                1
+                            } else {
+                                0
+                            };
+                            index * bam_interval + track
+                        } else {
+                            let tag: &[u8; 2] = colored_by_str.as_bytes().try_into().expect("colored by tag with unexpected length: tag name must be two characters.");
+                            if let Some(TagValue::Int(tag_id, _)) = record.tags().get(tag) {
+                                index * bam_interval + tag_id as usize
                             } else {
                                 index * bam_interval
                             }
-                        } else {
-                            index * bam_interval
-                        };
-                        list.push((idx as u64, record));
+                        }
+                    } else {
+                        index * bam_interval
                     }
-                }
-                debug!(
-                    "Reads loaded: {} of {} ({}, {})",
-                    count, total, no_bits, min_read_len
-                );
+                } else {
+                    index * bam_interval
+                };
+                list.push((idx as u64, record));
             }
-            //    (string_range, prefetch_range)
-            //};
+        }
+        debug!(
+            "Reads loaded: {} of {} ({}, {})",
+            count, total, no_bits, min_read_len
+        );
+    }
+    //    (string_range, prefetch_range)
+    //};
 
-            let mut ann = vec![];
-            let mut idx = bam_files.len() * bam_interval;
-            let mut freq = BTreeMap::new();
-            if let Some(freq_files) = matches.values_of("frequency") {
-                // let bed_files: Vec<_> = matches.values_of("bed").unwrap().collect();
-                // frequency bed file needs to be (start, score).
-                let mut freq_files: Vec<&str> = freq_files.collect();
-                for (_idx, bed_path) in freq_files.iter().enumerate() {
-                    info!("Loading {}", bed_path);
-                    let mut reader = bed::Reader::from_file(bed_path)?;
-                    let mut values = vec![];
-                    for record in reader.records() {
-                        let record = record?;
-                        if record.end() > prefetch_range.start()
-                            && record.start() < prefetch_range.end()
-                            && record.chrom() == prefetch_range.path
-                        {
-                            values.push((
-                                record.start(),
+    let mut ann = vec![];
+    let mut idx = bam_readers.len() * bam_interval;
+    let mut freq = BTreeMap::new();
+    if let Some(freq_files) = matches.values_of("frequency") {
+        // let bed_files: Vec<_> = matches.values_of("bed").unwrap().collect();
+        // frequency bed file needs to be (start, score).
+        let freq_files: Vec<&str> = freq_files.collect();
+        for (_idx, bed_path) in freq_files.iter().enumerate() {
+            info!("Loading {}", bed_path);
+            let mut reader = bed::Reader::from_file(bed_path)?;
+            let mut values = vec![];
+            for record in reader.records() {
+                let record = record?;
+                if record.end() > prefetch_range.start()
+                    && record.start() < prefetch_range.end()
+                    && record.chrom() == prefetch_range.path
+                {
+                    values.push((
+                        record.start(),
+                        record
+                            .score()
+                            .and_then(|t| t.parse::<f32>().ok())
+                            .map(|t| t as u32)
+                            .unwrap_or_else(|| {
                                 record
-                                    .score()
+                                    .name()
                                     .and_then(|t| t.parse::<f32>().ok())
                                     .map(|t| t as u32)
-                                    .unwrap_or_else(|| {
-                                        record
-                                            .name()
-                                            .and_then(|t| t.parse::<f32>().ok())
-                                            .map(|t| t as u32)
-                                            .unwrap_or(0)
-                                    }),
-                                '*',
-                            ));
-                        }
-                    }
-                    freq.insert(idx as u64, values);
-                    idx += 1;
+                                    .unwrap_or(0)
+                            }),
+                        '*',
+                    ));
                 }
-                // Need to change immutable
-                bam_files.append(&mut freq_files);
             }
-            //eprintln!("{:?}", freq);
-
-            if let Some(bed_files) = matches.values_of("bed") {
-                let bed_files: Vec<&str> = bed_files.collect();
-                for (_idx, bed_path) in bed_files.iter().enumerate() {
-                    info!("Loading {}", bed_path);
-                    let mut reader = bed::Reader::from_file(bed_path)?;
-                    for record in reader.records() {
-                        let record = record?;
-                        if record.end() > prefetch_range.start()
-                            && record.start() < prefetch_range.end()
-                            && record.chrom() == prefetch_range.path
-                        {
-                            ann.push((idx as u64, record));
-                        }
+            freq.insert(idx as u64, values);
+            idx += 1;
+        }
+    }
+    //eprintln!("{:?}", freq);
+
+    if let Some(bed_files) = matches.values_of("bed") {
+        let bed_files: Vec<&str> = bed_files.collect();
+        for (_idx, bed_path) in bed_files.iter().enumerate() {
+            info!("Loading {}", bed_path);
+            let mut reader = bed::Reader::from_file(bed_path)?;
+            for record in reader.records() {
+                let record = record?;
+                if record.end() > prefetch_range.start()
+                    && record.start() < prefetch_range.end()
+                    && record.chrom() == prefetch_range.path
+                {
+                    ann.push((idx as u64, record));
+                }
+            }
+            idx += 1;
+        }
+        // bam_files.append(&mut bed_files);
+    }
+    if let Some(gff_files) = matches.values_of("gff3") {
+        let gff_files: Vec<&str> = gff_files.collect();
+        for (_idx, gff_path) in gff_files.iter().enumerate() {
+            info!("Loading {}", gff_path);
+            let mut reader = gff::Reader::from_file(gff_path, gff::GffType::GFF3).unwrap();
+            for gff_record in reader.records() {
+                let gff = gff_record?;
+                if *gff.end() > prefetch_range.start()
+                    && *gff.start() < prefetch_range.end()
+                    && gff.seqname() == prefetch_range.path
+                {
+                    let mut record = bed::Record::new();
+                    record.set_chrom(gff.seqname());
+                    record.set_start(*gff.start());
+                    record.set_end(*gff.end());
+                    record.set_name(&gff.attributes()["gene_id"]);
+                    record.set_score(&gff.score().unwrap_or(0).to_string());
+                    if let Some(strand) = gff.strand() {
+                        record.push_aux(strand.strand_symbol()); // Strand
                     }
-                    idx += 1;
+                    ann.push((idx as u64, record));
                 }
-                // bam_files.append(&mut bed_files);
             }
-            if let Some(gff_files) = matches.values_of("gff3") {
-                let gff_files: Vec<&str> = gff_files.collect();
-                for (_idx, gff_path) in gff_files.iter().enumerate() {
-                    info!("Loading {}", gff_path);
-                    let mut reader = gff::Reader::from_file(gff_path, gff::GffType::GFF3).unwrap();
-                    for gff_record in reader.records() {
-                        let gff = gff_record?;
-                        if *gff.end() > prefetch_range.start()
-                            && *gff.start() < prefetch_range.end()
-                            && gff.seqname() == prefetch_range.path
-                        {
-                            let mut record = bed::Record::new();
-                            record.set_chrom(gff.seqname());
-                            record.set_start(*gff.start());
-                            record.set_end(*gff.end());
-                            record.set_name(&gff.attributes()["gene_id"]);
-                            record.set_score(&gff.score().unwrap_or(0).to_string());
-                            if let Some(strand) = gff.strand() {
-                                record.push_aux(strand.strand_symbol()); // Strand
-                            }
-                            ann.push((idx as u64, record));
-                        }
+            idx += 1;
+        }
+        // bam_files.append(&mut gff_files);
+    }
+    Ok(VisPrecursor::new(
+        string_range,
+        prefetch_range,
+        list,
+        ann,
+        freq,
+    ))
+}
+
+/// Extracts breakpoints of split alignments from BAM files, clusters them and writes BEDPE or
+/// VCF BND records with supporting reads.
+pub fn breakpoints(matches: &ArgMatches, threads: u16) -> Result<(), Box<dyn std::error::Error>> {
+    let tolerance = matches
+        .value_of("tolerance")
+        .and_then(|a| a.parse::<u64>().ok())
+        .unwrap_or(100u64);
+    let min_support = matches
+        .value_of("min-support")
+        .and_then(|a| a.parse::<usize>().ok())
+        .unwrap_or(1usize);
+    let min_mapq = matches
+        .value_of("min-mapq")
+        .and_then(|a| a.parse::<u8>().ok())
+        .unwrap_or(0u8);
+    let no_bits = matches
+        .value_of("no-bits")
+        .and_then(|t| t.parse::<u16>().ok())
+        .unwrap_or(1796u16);
+    let target = matches.value_of("target-chromosome");
+    let translocation_only = matches.is_present("translocation-only");
+    let vcf = match matches.value_of("format").unwrap_or("bedpe") {
+        "bedpe" => false,
+        "vcf" => true,
+        format => {
+            return Err(Box::new(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unknown format: {}", format),
+            )))
+        }
+    };
+
+    let mut junctions = vec![];
+    let mut chromosomes: Vec<(String, u64)> = vec![];
+    for bam_path in matches.values_of("INPUT").into_iter().flatten() {
+        let mut reader = bam::IndexedReader::build()
+            .additional_threads(threads - 1)
+            .from_path(bam_path)?;
+        let header = reader.header().clone();
+        let lens: Vec<(String, u64)> = header
+            .reference_names()
+            .iter()
+            .enumerate()
+            .filter_map(|(id, name)| {
+                header
+                    .reference_len(id as u32)
+                    .map(|len| (name.clone(), len as u64))
+            })
+            .collect();
+        let ranges: Vec<StringRegion> = match matches.values_of("range") {
+            Some(ranges) => ranges.map(StringRegion::new).collect::<Result<_, _>>()?,
+            None => major_chromosomes(lens.clone())
+                .into_iter()
+                .map(|(name, len)| StringRegion {
+                    path: name,
+                    start: 1,
+                    end: len,
+                })
+                .collect(),
+        };
+        if chromosomes.is_empty() {
+            chromosomes = lens;
+        }
+        for range in ranges {
+            let ref_id = header
+                .reference_id(&range.path)
+                .ok_or_else(|| Error::new(ErrorKind::Other, "Invalid reference id."))?;
+            let ref_len = header
+                .reference_len(ref_id)
+                .ok_or_else(|| Error::new(ErrorKind::Other, "Invalid reference length."))?;
+            let viewer = reader.fetch(&bam::bam_reader::Region::new(
+                ref_id,
+                (range.start as u32).max(1),
+                (range.end as u32).min(ref_len),
+            ))?;
+            for record in viewer {
+                let record = record?;
+                if !record.flag().no_bits(no_bits) {
+                    continue;
+                }
+                let segments = segments(&record, &range.path);
+                let name = String::from_utf8_lossy(record.name()).to_string();
+                for junction in Junction::from_segments_min_mapq(&segments, min_mapq) {
+                    if (!translocation_only || junction.is_translocation())
+                        && target.map_or(true, |t| {
+                            junction.first.chrom == t || junction.second.chrom == t
+                        })
+                    {
+                        junctions.push((junction, name.clone()));
                     }
-                    idx += 1;
                 }
-                // bam_files.append(&mut gff_files);
             }
-            precursor.push(VisPrecursor::new(
-                string_range,
-                prefetch_range,
-                list,
-                ann,
-                freq,
-            ));
+            info!("Loaded {} of {}", range.path, bam_path);
         }
+    }
 
-        bam_record_vis_pre_calculate(matches, &args, precursor, threads, |idx| {
-            //            if separated_by_tag {
-            //                bam_files
-            //                    .get(idx / bam_interval)
-            //                    .and_then(|t| Some(format!("{}_{}", *t, idx % bam_interval).as_str()))
-            //            } else {
-            if let Some(labels) = &labels {
-                labels.get(idx / bam_interval).copied()
-            } else {
-                bam_files.get(idx / bam_interval).copied()
+    let clusters: Vec<_> = cluster(junctions, tolerance)
+        .into_iter()
+        .filter(|t| t.support() >= min_support)
+        .collect();
+    let out = std::io::stdout();
+    let out_writer = match matches.value_of("output") {
+        Some(x) => Box::new(File::create(x)?) as Box<dyn Write>,
+        None => Box::new(out.lock()) as Box<dyn Write>,
+    };
+    let mut output = io::BufWriter::new(out_writer);
+    if vcf {
+        writeln!(output, "{}", vcf_header(&chromosomes))?;
+        let mut records: Vec<(usize, u64, String)> = vec![];
+        let rank: HashMap<&String, usize> = chromosomes
+            .iter()
+            .enumerate()
+            .map(|(i, t)| (&t.0, i))
+            .collect();
+        for (id, cluster) in clusters.iter().enumerate() {
+            let sides = [&cluster.junction.first, &cluster.junction.second];
+            for (side, record) in sides.iter().zip(cluster.to_vcf(id).iter()) {
+                records.push((
+                    *rank.get(&side.chrom).unwrap_or(&usize::MAX),
+                    side.pos,
+                    record.clone(),
+                ));
             }
-            //            }
-        })?;
+        }
+        records.sort();
+        for (_, _, record) in records {
+            writeln!(output, "{}", record)?;
+        }
+    } else {
+        for (id, cluster) in clusters.iter().enumerate() {
+            writeln!(output, "{}", cluster.to_bedpe(id))?;
+        }
     }
     Ok(())
 }
@@ -1009,7 +1546,18 @@ pub fn vis_query(
 
             let range = Region::convert(&prefetch_range, closure).unwrap();
             let viewer = reader.fetch(&range).unwrap();
-            if matches.is_present("rest") {
+            if matches.is_present("tiles") {
+                let buffer: ChromosomeBuffer = ChromosomeBuffer::new(reader, matches.clone());
+                buffered_tiles(
+                    matches.clone(),
+                    string_range,
+                    prefetch_range,
+                    args,
+                    buffer,
+                    threads,
+                )?;
+                return Ok(());
+            } else if matches.is_present("rest") {
                 let buffer: ChromosomeBuffer = ChromosomeBuffer::new(reader, matches.clone());
                 rest_server(
                     matches.clone(),
@@ -1108,6 +1656,23 @@ pub fn vis_query(
                 BTreeMap::new(),
             ));
         }
+        let chromosomes = reader
+            .header()
+            .reference_names()
+            .iter()
+            .cloned()
+            .zip(
+                reader
+                    .header()
+                    .reference_lengths()
+                    .iter()
+                    .map(|t| *t as u64),
+            )
+            .collect();
+        write_circos(matches, &precursor, chromosomes)?;
+        if matches.is_present("dotplot") {
+            return write_dotplot(matches, &precursor);
+        }
         bam_record_vis_pre_calculate(matches, &args, precursor, threads, |idx| {
             reader.header().get_name(idx).map(|t| t.as_str())
         })?;
@@ -1286,14 +1851,22 @@ where
     //let range = &vis[0].range.clone();
     //let prefetch_range = &vis[0].prefetch_range.clone();
 
-    let pileup = matches.is_present("pileup");
+    let allele_count = matches
+        .value_of("allele-count")
+        .and_then(|a| a.parse::<f64>().ok());
+    let pileup = matches.is_present("pileup") || allele_count.is_some();
     let split_only = matches.is_present("only-split-alignment");
     let split_exclude = matches.is_present("exclude-split-alignment");
     let sort_by_name = matches.is_present("sort-by-name");
     let packing = !matches.is_present("no-packing");
-    let split = matches.is_present("split-alignment");
-    let read_per_line = matches.is_present("read-per-line");
+    // On the breakpoint view, each split read occupies one row across all ranges.
+    let breakpoint_view = matches.is_present("breakpoint-view");
+    let split = matches.is_present("split-alignment")
+        || matches.is_present("view-as-pairs")
+        || breakpoint_view;
+    let read_per_line = matches.is_present("read-per-line") || breakpoint_view;
     let read_per_two_node = matches.is_present("read-per-two-range");
+    let downsampler = Downsampler::from_matches(matches);
 
     let read_index = matches
         .value_of("read-index")
@@ -1412,6 +1985,12 @@ where
                         && column.ref_pos() as u64 <= prefetch_range.end
                     {
                         line.push((column.ref_pos() as u64, column.entries().len() as u32, '*'));
+                        if allele_count.is_some() {
+                            line.extend(
+                                AlleleCount::from_entries(column.entries())
+                                    .to_frequency(column.ref_pos() as u64),
+                            );
+                        }
                     }
                 }
                 //eprintln!("{:?}", line);
@@ -1424,6 +2003,46 @@ where
         //freq.extend(freq_tmp);
     }
 
+    if let (Some(threshold), Some(output)) = (allele_count, matches.value_of("allele-count-output"))
+    {
+        let mut writer = io::BufWriter::new(File::create(output)?);
+        write_allele_count_header(&mut writer)?;
+        for i in vis.iter() {
+            let freq = i.frequency.lock().unwrap();
+            let range = &i.range;
+            let reference_seq = match matches.value_of("ref-column") {
+                Some(twobit) => Reference::open(twobit)
+                    .map_err(|e| format!("{}: {}", twobit, e))?
+                    .read_sequence(&range.path, range.start() as usize..range.end() as usize)
+                    .ok(),
+                None => None,
+            };
+            let reference = |pos: u64| {
+                reference_seq
+                    .as_ref()
+                    .and_then(|seq| seq.as_bytes().get(pos.checked_sub(range.start())? as usize))
+                    .map(|t| t.to_ascii_uppercase() as char)
+            };
+            for (sample_id, values) in freq.iter() {
+                let counts: BTreeMap<u64, AlleleCount> = AlleleCount::from_frequency(values)
+                    .range(range.start()..range.end())
+                    .map(|(pos, count)| (*pos, *count))
+                    .collect();
+                let sample = lambda(*sample_id as usize)
+                    .map(|t| t.to_string())
+                    .unwrap_or_else(|| sample_id.to_string());
+                write_allele_counts(
+                    &mut writer,
+                    &sample,
+                    &range.path,
+                    &counts,
+                    reference,
+                    threshold,
+                )?;
+            }
+        }
+    }
+
     //eprintln!("{:?}", freq.keys());
     if sort_by_name {
         for i in vis.iter() {
@@ -1446,6 +2065,32 @@ where
     let mut supplementary_list = vec![];
     let mut suppl_map = HashMap::new();
 
+    // Downsampling is applied after the pileup, so that coverage is computed from all reads.
+    if let Some(downsampler) = downsampler {
+        for i in vis.iter() {
+            let mut list = i.list.lock().unwrap();
+            let keep: Vec<bool> = {
+                let dropped =
+                    downsampler.dropped(list.iter().map(|t| (t.0, t.1.start(), t.1.name())));
+                list.iter()
+                    .map(|t| !dropped.contains(&(t.0, t.1.start(), t.1.name())))
+                    .collect()
+            };
+            let mut keep = keep.into_iter();
+            list.retain(|_| keep.next().unwrap_or(true));
+        }
+    }
+
+    let selection = ReadSelection::from_matches(matches)?;
+    if selection.only.is_some() {
+        for i in vis.iter() {
+            i.list
+                .lock()
+                .unwrap()
+                .retain(|t| !selection.is_hidden(t.1.name()));
+        }
+    }
+
     // New_list is merged list to decide the order of alignments.
     let mut new_list = {
         // new_list is a tuple (sample_id, record, range_id), whose record needs to be cloned.
@@ -1967,6 +2612,11 @@ fn rest_server<T: 'static + ChromosomeBufferTrait + Send + Sync>(
     unimplemented!("Please add web as a feature.")
 }
 
+#[cfg(not(feature = "web"))]
+fn catalog_server(matches: ArgMatches, args: Vec<String>, threads: u16) -> std::io::Result<()> {
+    unimplemented!("Please add web as a feature.")
+}
+
 #[cfg(not(feature = "web"))]
 fn buffered_server<T: 'static + ChromosomeBufferTrait + Send + Sync>(
     matches: ArgMatches,
@@ -1978,3 +2628,15 @@ fn buffered_server<T: 'static + ChromosomeBufferTrait + Send + Sync>(
 ) -> std::io::Result<()> {
     unimplemented!("Please add web as a feature.")
 }
+
+#[cfg(not(feature = "web"))]
+fn buffered_tiles<T: ChromosomeBufferTrait + Send + Sync>(
+    matches: ArgMatches,
+    _range: StringRegion,
+    prefetch_range: StringRegion,
+    args: Vec<String>,
+    mut buffer: T,
+    threads: u16,
+) -> std::io::Result<()> {
+    unimplemented!("Please add web as a feature.")
+}
@@ -1,5 +1,11 @@
+use crate::allele::AlleleCount;
+use crate::breakpoint::{segments, Breakpoint};
+use crate::display_mode::{DisplayMode, DisplayModes, Layout};
 use crate::dump::*;
-use crate::{color::ColorSet, color::VisColor, VisOrig, VisPreset, VisRef};
+use crate::pair::{InsertSizeRange, PairOrientation, PairStatus};
+use crate::read_selection::ReadSelection;
+use crate::reference::Reference;
+use crate::{color::parse_hex_color, color::ColorSet, color::VisColor, VisOrig, VisPreset, VisRef};
 use bam::record::{
     tags::{StringType, TagValue},
     Cigar,
@@ -7,6 +13,7 @@ use bam::record::{
 use bam::{Record, RecordReader};
 use bio_types::strand::Strand;
 use clap::ArgMatches;
+use genomic_range::StringRegion;
 use itertools::Itertools;
 use log::{debug, info};
 use num_format::{Locale, ToFormattedString};
@@ -18,7 +25,6 @@ use plotters::style::RGBColor;
 use std::ops::Range;
 use std::{collections::BTreeMap, fs::File, time::Instant};
 use std::{convert::TryInto, path::PathBuf};
-use twobit::TwoBitFile;
 use udon::{Udon, UdonPalette, UdonScaler, UdonUtils};
 
 const PARBASE_THRESHOLD: u64 = 5;
@@ -385,13 +391,19 @@ where
     let legend = !matches.is_present("no-legend");
     let insertion = !matches.is_present("no-insertion");
     let deletion = !matches.is_present("no-deletion");
-    let split = matches.is_present("split-alignment");
+    let breakpoint_view = matches.is_present("breakpoint-view");
+    let selection = ReadSelection::from_matches(matches)?;
+    let highlight_color = preset_color.pick(VisColor::HighlightCol);
+    let split = matches.is_present("split-alignment") || breakpoint_view;
     let _split_only = matches.is_present("only-split-alignment");
     let _sort_by_name = matches.is_present("sort-by-name");
     let sort_by_cigar = matches.is_present("sort-by-cigar");
     let colored_by_name = matches.is_present("colored-by-name");
     let colored_by_track = matches.is_present("colored-by-track");
-    let pileup = matches.is_present("pileup");
+    let allele_count = matches
+        .value_of("allele-count")
+        .and_then(|a| a.parse::<f64>().ok());
+    let pileup = matches.is_present("pileup") || allele_count.is_some();
     let all_bases = matches.is_present("all-bases");
     let hide_alignment = matches.is_present("hide-alignment");
     let only_translocation = matches.is_present("only-translocation");
@@ -399,7 +411,7 @@ where
     let with_caption = matches.is_present("with-caption");
     let with_caption_val = matches.value_of("with-caption").unwrap_or("");
     let output_translocation = matches.is_present("output-translocation");
-    let _translocation_target = matches.value_of("translocation-target");
+    let translocation_target = matches.value_of("translocation-target");
     let square = matches.is_present("square");
     let read_index = matches.is_present("read-index");
     let x_as_range = matches.is_present("x-as-range");
@@ -413,6 +425,20 @@ where
     let colored_by_tag = matches.occurrences_of("colored-by-tag") != 0;
     let colored_by_tag_vec = matches.value_of("colored-by-tag");
     let twobit = matches.value_of("ref-column");
+    let soft_clip = matches.is_present("soft-clip");
+    let view_as_pairs = matches.is_present("view-as-pairs");
+    let display_modes = DisplayModes::from_matches(matches)?;
+    let track_colors: Vec<Option<RGBColor>> = matches
+        .value_of("track-colors")
+        .map(|t| t.split(',').map(parse_hex_color).collect())
+        .unwrap_or_default();
+    let given_insert_size_range = matches
+        .value_of("insert-size-range")
+        .and_then(|a| a.parse::<InsertSizeRange>().ok());
+    let pair_orientation = matches
+        .value_of("pair-orientation")
+        .and_then(|a| a.parse::<PairOrientation>().ok())
+        .unwrap_or(PairOrientation::FR);
     // let metadata = vec![];
 
     if hide_alignment {
@@ -498,7 +524,30 @@ where
         .flatten()
         .unique_by(|s| s.0)
         .count(); // annotation.len();
-    let prev_index = vis.iter().map(|a| a.prev_index).max().unwrap();
+
+    // Rows of each range after applying display modes of tracks.
+    let mut layouts: Vec<Option<Layout>> = vis
+        .iter()
+        .map(|a| {
+            if display_modes.is_expanded() {
+                None
+            } else {
+                Some(Layout::new(
+                    a.compressed_list,
+                    a.index_list,
+                    a.prev_index,
+                    &display_modes,
+                    (y / 2).max(1) as usize,
+                ))
+            }
+        })
+        .collect();
+    let prev_index = vis
+        .iter()
+        .zip(layouts.iter())
+        .map(|(a, layout)| layout.as_ref().map_or(a.prev_index, |t| t.prev_index))
+        .max()
+        .unwrap();
     let freq_len = if pileup {
         vis.iter().map(|a| a.frequency.len()).max().unwrap()
     } else {
@@ -557,10 +606,12 @@ where
     //eprintln!("n_x_labels: {:?}", n_x_labels);
     if let Some(val) = vis_index {
         vis = vec![vis[val].clone()];
+        layouts = vec![layouts[val].clone()];
         if dynamic_partition {
             n_x_labels = vec![n_x_labels[val]];
         }
     }
+    let ranges: Vec<StringRegion> = vis.iter().map(|t| t.range.clone()).collect();
     let y_len = top_margin
         + (prev_index as u32 + axis_count as u32 + annotation_count as u32 * 2) * y
         + freq_len as u32 * freq_size;
@@ -604,10 +655,46 @@ where
         let frequency = &vis.frequency;
         let list = &vis.list;
         let annotation = &vis.annotation;
-        let compressed_list = &vis.compressed_list;
-        let index_list = &vis.index_list;
-        let prev_index = vis.prev_index;
-        let supplementary_list = &vis.supplementary_list;
+        let layout = &layouts[index];
+        let compressed_list = layout
+            .as_ref()
+            .map_or(vis.compressed_list, |t| &t.compressed_list);
+        let index_list = layout.as_ref().map_or(vis.index_list, |t| &t.index_list);
+        let prev_index = layout.as_ref().map_or(vis.prev_index, |t| t.prev_index);
+        // Connections of split alignments are kept only on expanded tracks.
+        let mapped_supplementary_list = layout.as_ref().map(|layout| {
+            vis.supplementary_list
+                .iter()
+                .filter(|t| {
+                    layout.is_expanded_row(t.1) && layout.map_row(t.1).0 < std::u32::MAX as usize
+                })
+                .map(|t| {
+                    let row = layout.map_row(t.1).0;
+                    (t.0.clone(), row, row + t.2 - t.1, t.3, t.4)
+                })
+                .collect::<Vec<_>>()
+        });
+        let supplementary_list = mapped_supplementary_list
+            .as_ref()
+            .unwrap_or(vis.supplementary_list);
+        // Unless given, the expected insert sizes are inferred from pairs on the same chromosome.
+        let insert_size_range = if view_as_pairs {
+            given_insert_size_range.or_else(|| {
+                InsertSizeRange::from_insert_sizes(
+                    list.iter()
+                        .filter(|t| {
+                            t.1.flag().is_paired()
+                                && t.1.flag().mate_is_mapped()
+                                && t.1.ref_id() == t.1.mate_ref_id()
+                                && t.1.template_len() != 0
+                        })
+                        .map(|t| t.1.template_len().abs() as u32)
+                        .collect(),
+                )
+            })
+        } else {
+            None
+        };
 
         // After this point, we should be able to draw construct a chart context
         // let areas = root.split_by_breakpoints([], compressed_list);
@@ -778,10 +865,17 @@ where
             node_id_dict.insert(prev_node_id, (prev_pos, range.end()));
         }
 
-        if let Some(twobit) = twobit {
+        // A reference which cannot be opened is an error, but a missing sequence is not.
+        let reference_seq = match twobit {
+            Some(twobit) => Reference::open(twobit)
+                .map_err(|e| format!("{}: {}", twobit, e))?
+                .read_sequence(&range.path, range.start as usize..range.end as usize)
+                .ok(),
+            None => None,
+        };
+        if let Some(seq) = &reference_seq {
             let left_top = chart.as_coord_spec().translate(&(range.start, index)); // range.start - 1 is better?
             let right_bottom = chart.as_coord_spec().translate(&(range.end + 1, index + 1));
-            eprintln!("{:?}, {:?}", left_top, right_bottom);
             let opt_len = (right_bottom.0 - left_top.0) as usize;
 
             let window = Range::<usize> {
@@ -791,39 +885,41 @@ where
 
             let pixels_per_column = opt_len as f64 / window.len() as f64;
 
-            let enable_softmask = false;
-            let tb = TwoBitFile::open(twobit).unwrap();
-            let mut tb_nosoft = tb.enable_softmask(enable_softmask);
-
-            if let Ok(seq) =
-                tb_nosoft.read_sequence(&range.path, range.start as usize..range.end as usize)
-            {
-                let mut pos = range.start;
-                let mut bars = vec![];
-                let mut texts = vec![];
-                for base in seq.chars() {
-                    let color = nt_color(base, &preset_color)
-                        .unwrap_or_else(|| preset_color.pick(VisColor::NCol));
-                    let mut bar = Rectangle::new(
-                        [(pos, y_spec_max - 1), (pos + 1, y_spec_max)],
-                        color.filled(),
+            let mut pos = range.start;
+            let mut bars = vec![];
+            let mut texts = vec![];
+            for base in seq.chars() {
+                let color = nt_color(base, &preset_color)
+                    .unwrap_or_else(|| preset_color.pick(VisColor::NCol));
+                let mut bar = Rectangle::new(
+                    [(pos, y_spec_max - 1), (pos + 1, y_spec_max)],
+                    color.filled(),
+                );
+                bar.set_margin(0, 0, 0, 0);
+                bars.push(bar);
+                if pixels_per_column as u32 >= y / 2 {
+                    let text = Text::new(
+                        format!("{}", base),
+                        (pos, y_spec_max),
+                        ("sans-serif", y / 4 * 3),
                     );
-                    bar.set_margin(0, 0, 0, 0);
-                    bars.push(bar);
-                    if pixels_per_column as u32 >= y / 2 {
-                        let text = Text::new(
-                            format!("{}", base),
-                            (pos, y_spec_max),
-                            ("sans-serif", y / 4 * 3),
-                        );
-                        texts.push(text);
-                    }
-                    pos += 1;
+                    texts.push(text);
                 }
-                chart.draw_series(bars)?;
-                chart.draw_series(texts)?;
+                pos += 1;
             }
+            chart.draw_series(bars)?;
+            chart.draw_series(texts)?;
         }
+        // Reference base on the given position, if it is within the reference row.
+        let reference_base = |pos: u64| -> Option<char> {
+            if pos < range.start() {
+                return None;
+            }
+            reference_seq
+                .as_ref()
+                .and_then(|seq| seq.as_bytes().get((pos - range.start()) as usize))
+                .map(|t| t.to_ascii_uppercase() as char)
+        };
         let mut annotations = vec![];
         let mut tracks = BTreeMap::new();
 
@@ -1104,7 +1200,17 @@ where
                     ))?;
                 }
             } else {*/
-            if vis_len <= 1 {
+            if view_as_pairs {
+                // Connect mates on the same row with a thin line.
+                chart.draw_series(supplementary_list.iter().filter(|t| t.3 < t.4).map(|i| {
+                    let mut bar = Rectangle::new(
+                        [(i.3 as u64, i.1), (i.4 as u64, i.1 + 1)],
+                        BLACK.mix(0.5).filled(),
+                    );
+                    bar.set_margin((y / 2).saturating_sub(1), (y / 2).saturating_sub(1), 0, 0);
+                    bar
+                }))?;
+            } else if vis_len <= 1 {
                 chart.draw_series(supplementary_list.iter().filter(|t| t.3 < t.4).map(|i| {
                     let stroke = BLACK;
                     let mut bar2 = Rectangle::new(
@@ -1117,10 +1223,68 @@ where
             }
             //}
         }
+        if breakpoint_view {
+            // Each piece of a split read is linked to its neighbors along the read on the same
+            // row, up to the edge of the panel if the neighbor is on another panel.
+            let panel = |t: &Breakpoint| {
+                ranges
+                    .iter()
+                    .position(|r| r.path == t.chrom && r.start() <= t.pos && t.pos <= r.end())
+            };
+            let x_of = |t: &Breakpoint| if t.retains_left { t.pos } else { t.pos - 1 };
+            let mut links = vec![];
+            for (&row, data) in index_list.iter().zip(list.iter()) {
+                if row >= std::u32::MAX as usize
+                    || (layout.is_some() && display_modes.get(data.0) != DisplayMode::Expanded)
+                {
+                    continue;
+                }
+                let segments = segments(&data.1, &range.path);
+                let pos = match segments
+                    .iter()
+                    .position(|t| t.chrom == range.path && t.start == data.1.start() as u64)
+                {
+                    Some(pos) => pos,
+                    None => continue,
+                };
+                let current = &segments[pos];
+                let neighbors = [
+                    (
+                        pos.checked_sub(1).map(|t| segments[t].exit()),
+                        current.entry(),
+                    ),
+                    (segments.get(pos + 1).map(|t| t.entry()), current.exit()),
+                ];
+                for (neighbor, from) in neighbors.iter() {
+                    let neighbor = match neighbor {
+                        Some(neighbor) => neighbor,
+                        None => continue,
+                    };
+                    let to = match panel(neighbor) {
+                        Some(i) if i == index => x_of(neighbor),
+                        Some(i) if i > index => range.end(),
+                        Some(_) => range.start(),
+                        None => continue,
+                    };
+                    let from = x_of(from);
+                    links.push((from.min(to), from.max(to), row));
+                }
+            }
+            chart.draw_series(links.into_iter().filter(|t| t.0 < t.1).map(
+                |(start, end, row)| {
+                    let mut bar =
+                        Rectangle::new([(start, row), (end, row + 1)], BLACK.mix(0.5).filled());
+                    bar.set_margin((y / 2).saturating_sub(1), (y / 2).saturating_sub(1), 0, 0);
+                    bar
+                },
+            ))?;
+        }
         let mut split_frequency = vec![];
+        let mut clip_frequency = vec![];
         // let mut allele_frequency = vec![];
         // For each alignment:
         let mut reads = vec![];
+        let mut highlights = vec![];
         let ins_color = preset_color.pick(VisColor::InsCol);
         let (bars, texts) = {
             //list.into_iter().enumerate().map(|(index, data)| {
@@ -1130,11 +1294,12 @@ where
             index_list
                 .iter()
                 .zip(list.iter())
-                .filter(|(index, data)| {
+                .enumerate()
+                .filter(|(_, (index, data))| {
                     (data.1.start() as u64) < range.end()
                         && (data.1.calculate_end() as u64) > range.start() && **index < std::u32::MAX as usize
                 })
-                .for_each(|(&index, data)| {
+                .for_each(|(read_idx, (&index, data))| {
                     //chart.draw_series(index_list.into_par_iter().zip(list).map(|(index, data)| {
                     //for (index, data) in list.iter().enumerate() {
                     let bam = &data.1;
@@ -1159,11 +1324,31 @@ where
                         }
                     } else if colored_by_track {
                         Palette9999::pick(data.0 as usize).mix(0.3)
+                    } else if let Some(track_color) = track_colors.get(display_modes.input_index(data.0)).copied().flatten() {
+                        track_color.mix(0.8)
                     } else if bam.flag().is_reverse_strand() {
                         preset_color.pick(VisColor::NegCol).mix(0.8)
                     } else {
                         preset_color.pick(VisColor::PosCol).mix(0.8)
                     };
+                    let pair_status = if view_as_pairs {
+                        PairStatus::from_record(bam, insert_size_range.as_ref(), pair_orientation)
+                    } else {
+                        None
+                    };
+                    let color = match pair_status {
+                        Some(PairStatus::SmallInsert) => preset_color.pick(VisColor::SmallInsCol).mix(0.8),
+                        Some(PairStatus::LargeInsert) => preset_color.pick(VisColor::LargeInsCol).mix(0.8),
+                        Some(PairStatus::Orientation(orientation)) => match orientation {
+                            PairOrientation::FR => preset_color.pick(VisColor::PosCol).mix(0.8),
+                            PairOrientation::RF => preset_color.pick(VisColor::RfCol).mix(0.8),
+                            PairOrientation::FF => preset_color.pick(VisColor::FfCol).mix(0.8),
+                            PairOrientation::RR => preset_color.pick(VisColor::RrCol).mix(0.8),
+                        },
+                        Some(PairStatus::OtherChromosome(_)) => preset_color.pick(VisColor::MateChromCol).mix(0.8),
+                        _ => color,
+                    };
+                    let highlighted = selection.is_highlighted(bam.name());
                     let _stroke = Palette99::pick(data.0 as usize); //.unwrap(); //if data.0 % 2 == 0 { CYAN } else { GREEN };
                     let start = if bam.start() as u64 > range.start() {
                         bam.start() as u64
@@ -1189,12 +1374,123 @@ where
                             continue
                         }
                     } else {*/
+                    // Squished and collapsed tracks show only the reads without details.
+                    if let Some(layout) = layout {
+                        match display_modes.get(data.0) {
+                            DisplayMode::Squished => {
+                                let height = (y / layout.slots_per_row() as u32).max(1);
+                                let slot = layout.slots[read_idx] as u32;
+                                let color = if highlighted { highlight_color.mix(0.8) } else { color };
+                                let mut bar =
+                                    Rectangle::new([(start, index), (end, index + 1)], color.filled());
+                                bar.set_margin(slot * height, y.saturating_sub((slot + 1) * height), 0, 0);
+                                bars.push(bar);
+                                return;
+                            }
+                            DisplayMode::Collapsed => {
+                                let color = if highlighted { highlight_color.mix(0.8) } else { color.mix(0.1) };
+                                let mut bar =
+                                    Rectangle::new([(start, index), (end, index + 1)], color.filled());
+                                bar.set_margin(2, 2, 0, 0);
+                                bars.push(bar);
+                                return;
+                            }
+                            DisplayMode::Expanded => {}
+                        }
+                    }
                         let mut bar =
                             Rectangle::new([(start, index), (end, index + 1)], color.filled());
                         bar.set_margin(2, 2, 0, 0);
 
                         bars.push(bar);
+                        if highlighted {
+                            // Outlines are drawn over the details of reads.
+                            let mut outline = Rectangle::new(
+                                [(start, index), (end, index + 1)],
+                                highlight_color.stroke_width(2),
+                            );
+                            outline.set_margin(1, 1, 0, 0);
+                            highlights.push(outline);
+                        }
                     //}
+                    if soft_clip {
+                        let left_clip = bam.cigar().soft_clipping(true) as u64;
+                        let right_clip = bam.cigar().soft_clipping(false) as u64;
+                        let query_len = bam.sequence().len() as u64;
+                        let clip_color = color.mix(0.3);
+                        // (first position on reference, first index on query, length)
+                        let clips = [
+                            (
+                                (bam.start() as u64).saturating_sub(left_clip),
+                                0u64,
+                                left_clip,
+                            ),
+                            (
+                                bam.calculate_end() as u64,
+                                query_len.saturating_sub(right_clip),
+                                right_clip,
+                            ),
+                        ];
+                        for &(clip_start, query_start, clip_len) in clips.iter() {
+                            if clip_len == 0 {
+                                continue;
+                            }
+                            let breakpoint = if query_start == 0 {
+                                bam.start() as u64
+                            } else {
+                                bam.calculate_end() as u64
+                            };
+                            if breakpoint >= range.start() && breakpoint < range.end() {
+                                clip_frequency.push((data.0, (breakpoint, approximate_one_pixel)));
+                            }
+                            let clip_end = clip_start + clip_len;
+                            if clip_end <= range.start() || clip_start >= range.end() {
+                                continue;
+                            }
+                            let mut bar = Rectangle::new(
+                                [
+                                    (clip_start.max(range.start()), index),
+                                    (clip_end.min(range.end()), index + 1),
+                                ],
+                                clip_color.filled(),
+                            );
+                            bar.set_margin(2, 2, 0, 0);
+                            bars.push(bar);
+                            if no_cigar || query_len == 0 || reference_seq.is_none() {
+                                continue;
+                            }
+                            for offset in 0..clip_len {
+                                let pos = clip_start + offset;
+                                if pos < range.start() || pos >= range.end() {
+                                    continue;
+                                }
+                                let record_nt =
+                                    bam.sequence().at((query_start + offset) as usize) as char;
+                                match reference_base(pos) {
+                                    Some(ref_nt) if ref_nt != record_nt.to_ascii_uppercase() => {
+                                        if let Some(clip_nt_color) = nt_color(record_nt, &preset_color) {
+                                            let mut bar = Rectangle::new(
+                                                [(pos, index), (pos + 1, index + 1)],
+                                                clip_nt_color.mix(0.5).filled(),
+                                            );
+                                            bar.set_margin(margin, margin, 0, 0);
+                                            bars.push(bar);
+                                        }
+                                    }
+                                    _ => {}
+                                }
+                            }
+                        }
+                    }
+                    if let Some(PairStatus::OtherChromosome(mate_ref_id)) = pair_status {
+                        // Mark the chromosome of the mate with an inner bar.
+                        let mut inner_bar = Rectangle::new(
+                            [(start, index), (end, index + 1)],
+                            Palette99::pick(mate_ref_id as usize).filled(),
+                        );
+                        inner_bar.set_margin((y / 2).saturating_sub(1), margin, 0, 0);
+                        bars.push(inner_bar);
+                    }
                     if show_read_id {
                         let pos = Pos::new(HPos::Left, VPos::Bottom);
                         let style = TextStyle::from(("sans-serif", y / 3 * 2).into_font()).pos(pos);
@@ -1247,6 +1543,8 @@ where
                                 let sastr = String::from_utf8_lossy(array_view);
                                 let sa: Vec<Vec<&str>> =
                                     sastr.split(';').map(|t| t.split(',').collect()).collect();
+                                let is_target = translocation_target
+                                    .map_or(true, |c| sa.iter().any(|t| t[0] == c));
                                 let sa_left_clip: Vec<u32> = sa
                                     .into_iter()
                                     .filter(|t| t.len() > 2)
@@ -1284,8 +1582,13 @@ where
                                     bar.set_margin(0, 0, 0, 0);
                                     bars.push(bar);
                                     split_frequency.push((data.0, (start, approximate_one_pixel)));
-                                    if output_translocation {
-                                        println!("S\t{}\t{}\tR\t{}", range.path, end, start);
+                                    if output_translocation && is_target {
+                                        println!(
+                                            "S\t{}\t{}\tL\t{}",
+                                            range.path,
+                                            start,
+                                            String::from_utf8_lossy(bam.name())
+                                        );
                                     }
                                     if dump_json {
                                         let (lt, _) = chart.as_coord_spec().translate(&(start, index));
@@ -1304,8 +1607,13 @@ where
                                     bar.set_margin(0, 0, 0, 0);
                                     bars.push(bar);
                                     split_frequency.push((data.0, (end, approximate_one_pixel)));
-                                    if output_translocation {
-                                        println!("S\t{}\t{}\tR\t{}", range.path, end, start);
+                                    if output_translocation && is_target {
+                                        println!(
+                                            "S\t{}\t{}\tR\t{}",
+                                            range.path,
+                                            end,
+                                            String::from_utf8_lossy(bam.name())
+                                        );
                                     }
                                     if dump_json {
                                         let (lt, _) = chart.as_coord_spec().translate(&(end, index));
@@ -1654,6 +1962,34 @@ where
                                                     bar.set_margin(margin, margin, 0, 0);
                                                     bars.push(bar);
                                                 }
+                                            } else if !quality
+                                                && reference as u64 >= range.start()
+                                                && (reference as u64) < range.end()
+                                                && (_record as usize) < bam.sequence().len()
+                                            {
+                                                // Without MD tags, mismatches are computed from the given reference.
+                                                // Records without sequences (SEQ *) are skipped by the length check.
+                                                let record_nt =
+                                                    bam.sequence().at(_record as usize) as char;
+                                                if let Some(ref_nt) = reference_base(reference as u64) {
+                                                    if all_bases
+                                                        || ref_nt != record_nt.to_ascii_uppercase()
+                                                    {
+                                                        if let Some(color) =
+                                                            nt_color(record_nt, &preset_color)
+                                                        {
+                                                            let mut bar = Rectangle::new(
+                                                                [
+                                                                    (reference as u64, index),
+                                                                    (reference as u64 + 1, index + 1),
+                                                                ],
+                                                                color.filled(),
+                                                            );
+                                                            bar.set_margin(margin, margin, 0, 0);
+                                                            bars.push(bar);
+                                                        }
+                                                    }
+                                                }
                                             }
                                             prev_ref = reference as u64;
                                             if reference > range.end() as u32 {
@@ -1753,6 +2089,7 @@ where
             });
         }
         chart.draw_series(bars)?;
+        chart.draw_series(highlights)?;
         chart.draw_series(texts)?;
         //let dump = {reads: [], annotation: };
         let end1 = start.elapsed();
@@ -1856,6 +2193,56 @@ where
                                     .map(|t| t.1),
                             ),
                     )?;
+                    if let Some(threshold) = allele_count {
+                        let allele_colors = [
+                            preset_color.pick(VisColor::ACol),
+                            preset_color.pick(VisColor::CCol),
+                            preset_color.pick(VisColor::GCol),
+                            preset_color.pick(VisColor::TCol),
+                            preset_color.pick(VisColor::DelCol),
+                            preset_color.pick(VisColor::InsCol),
+                        ];
+                        let mut allele_bars = vec![];
+                        for (pos, count) in
+                            AlleleCount::from_frequency(values).range(range.start()..range.end())
+                        {
+                            // Stacks A/C/G/T/del/ins counts from the bottom.
+                            let mut bottom = 0u32;
+                            for (k, &value) in count.counts.iter().enumerate() {
+                                if value == 0 {
+                                    continue;
+                                }
+                                let mut bar = Rectangle::new(
+                                    [(*pos, bottom), (*pos + 1, bottom + value)],
+                                    allele_colors[k].filled(),
+                                );
+                                bar.set_margin(0, 0, 1, 1);
+                                allele_bars.push(bar);
+                                bottom += value;
+                            }
+                            if count.is_flagged(reference_base(*pos), threshold) {
+                                let bar = Rectangle::new(
+                                    [(*pos, 0), (*pos + 1, y_max)],
+                                    BLACK.stroke_width(1),
+                                );
+                                allele_bars.push(bar);
+                            }
+                        }
+                        chart.draw_series(allele_bars)?;
+                    }
+                    if soft_clip {
+                        chart.draw_series(
+                            Histogram::vertical(&chart)
+                                .style(preset_color.pick(VisColor::ClipCol).filled())
+                                .margin(1)
+                                .data(
+                                    clip_frequency
+                                        .iter()
+                                        .filter(|&&t| t.0 == *sample_sequential_id)
+                                        .map(|t| t.1),
+                                ),
+                        )?;
+                    }
 
                     /*if snp_frequency {
                         [('A', A_COL), ]
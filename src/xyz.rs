@@ -0,0 +1,168 @@
+use image::{imageops, Rgb, RgbImage};
+
+/// Latitude of the edges of the Web Mercator world, which are the top and bottom of tiles.
+const MAX_LATITUDE: f64 = 85.051_128_779_806_59;
+
+/// The XYZ scheme over the levels of the DZI pyramid.
+///
+/// A DZI level `l` covers the range with strips of `criteria << (max_level - l)` bp, one per
+/// column. XYZ expects `2^z` columns and rows on the zoom `z`, so the zoom is the level less
+/// `offset`, chosen so that a single column covers the range on zoom 0. Columns right of the
+/// range and rows below a strip are white, and rows of a strip below `2^z` tiles are cut.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grid {
+    offset: u32,
+    min_level: u32,
+    max_level: u32,
+    criteria: u64,
+    length: u64,
+}
+
+impl Grid {
+    /// A grid of DZI levels `min_level..=max_level` over `length` bp, with strips of
+    /// `criteria` bp on the maximum level.
+    pub fn new(min_level: u32, max_level: u32, criteria: u64, length: u64) -> Self {
+        let criteria = criteria.max(1);
+        let columns = length.div_ceil(criteria).max(1);
+        let zooms = columns.next_power_of_two().trailing_zeros();
+        Grid {
+            offset: max_level.saturating_sub(zooms),
+            min_level,
+            max_level,
+            criteria,
+            length,
+        }
+    }
+
+    pub fn min_zoom(&self) -> u32 {
+        self.min_level.max(self.offset) - self.offset
+    }
+
+    pub fn max_zoom(&self) -> u32 {
+        self.max_level - self.offset
+    }
+
+    /// Number of strips on the DZI level.
+    pub fn strips(&self, level: u32) -> u64 {
+        let width = self.criteria << (self.max_level - level);
+        self.length.div_ceil(width)
+    }
+
+    /// The DZI level and strip of the tile, or None if the tile is out of the grid.
+    /// The strip is None right of the range.
+    pub fn tile(&self, z: u32, x: u64, y: u64) -> Option<(u32, Option<u64>)> {
+        if z < self.min_zoom() || z > self.max_zoom() || x >> z > 0 || y >> z > 0 {
+            return None;
+        }
+        let level = z + self.offset;
+        Some((level, Some(x).filter(|&x| x < self.strips(level))))
+    }
+
+    /// TileJSON of tiles at `url` with `{z}`, `{x}` and `{y}`.
+    pub fn tilejson(&self, url: String, tile_size: u32) -> TileJson {
+        let columns = self.strips(self.max_level) as f64 / (1u64 << self.max_zoom()) as f64;
+        TileJson {
+            tilejson: "2.2.0".to_string(),
+            scheme: "xyz".to_string(),
+            tiles: vec![url],
+            minzoom: self.min_zoom(),
+            maxzoom: self.max_zoom(),
+            bounds: [
+                -180.0,
+                -MAX_LATITUDE,
+                -180.0 + 360.0 * columns,
+                MAX_LATITUDE,
+            ],
+            tile_size,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TileJson {
+    tilejson: String,
+    scheme: String,
+    tiles: Vec<String>,
+    minzoom: u32,
+    maxzoom: u32,
+    /// Longitudes and latitudes of the range in the Web Mercator world.
+    bounds: [f64; 4],
+    /// The width and height of tiles in pixels, which is not 256 unlike most maps.
+    #[serde(rename = "tileSize")]
+    tile_size: u32,
+}
+
+/// Crops the `y`-th square from the top of the strip, filling white below its bottom and
+/// right of its right edge.
+pub fn crop_tile(strip: &RgbImage, y: u32, size: u32) -> RgbImage {
+    let mut tile = blank_tile(size);
+    let top = y.saturating_mul(size);
+    if top < strip.height() {
+        let height = (strip.height() - top).min(size);
+        let width = strip.width().min(size);
+        let part = imageops::crop_imm(strip, 0, top, width, height).to_image();
+        imageops::replace(&mut tile, &part, 0, 0);
+    }
+    tile
+}
+
+pub fn blank_tile(size: u32) -> RgbImage {
+    RgbImage::from_pixel(size, size, Rgb([255, 255, 255]))
+}
+
+/// Writes the tile `y` of the strip file, or a white tile without the strip.
+pub fn write_tile(strip: Option<&str>, y: u32, size: u32, output: &str) -> Result<(), String> {
+    let tile = match strip {
+        Some(strip) => crop_tile(
+            &image::open(strip).map_err(|e| e.to_string())?.to_rgb8(),
+            y,
+            size,
+        ),
+        None => blank_tile(size),
+    };
+    tile.save(output).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crop_tile, Grid};
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn grid() {
+        // 5 strips on the level 12, and 2^3 columns on the zoom 3.
+        let grid = Grid::new(4, 12, 100, 450);
+        assert_eq!((grid.min_zoom(), grid.max_zoom()), (0, 3));
+        assert_eq!(grid.tile(3, 4, 7), Some((12, Some(4))));
+        assert_eq!(grid.tile(3, 5, 0), Some((12, None)));
+        assert_eq!(grid.tile(0, 0, 0), Some((9, Some(0))));
+        assert_eq!(grid.strips(9), 1);
+        assert_eq!(grid.tile(3, 8, 0), None);
+        assert_eq!(grid.tile(1, 0, 2), None);
+        assert_eq!(grid.tile(4, 0, 0), None);
+        // Levels below the minimum are not served.
+        let grid = Grid::new(11, 12, 100, 450);
+        assert_eq!((grid.min_zoom(), grid.max_zoom()), (2, 3));
+        assert_eq!(grid.tile(1, 0, 0), None);
+    }
+
+    #[test]
+    fn crop() {
+        let black = Rgb([0, 0, 0]);
+        let white = Rgb([255, 255, 255]);
+        // A strip narrower than the tile and 1.5 tiles high.
+        let strip = RgbImage::from_pixel(3, 6, black);
+        let tile = crop_tile(&strip, 0, 4);
+        assert_eq!(tile.dimensions(), (4, 4));
+        assert_eq!(*tile.get_pixel(2, 3), black);
+        assert_eq!(*tile.get_pixel(3, 0), white);
+        let tile = crop_tile(&strip, 1, 4);
+        assert_eq!(*tile.get_pixel(0, 1), black);
+        assert_eq!(*tile.get_pixel(0, 2), white);
+        // Beyond the bottom of the strip.
+        let tile = crop_tile(&strip, 2, 4);
+        assert!(tile.pixels().all(|p| *p == white));
+        let tile = crop_tile(&strip, u32::MAX, 4);
+        assert!(tile.pixels().all(|p| *p == white));
+    }
+}